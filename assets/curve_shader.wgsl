@@ -4,44 +4,106 @@
 [[group(2), binding(0)]]
 var<uniform> mesh: Mesh;
 
-struct CurveWidth {
+struct CurveMaterial {
+    color: vec4<f32>;
+    // alternating on and off lengths in logical pixels
+    dash: vec4<f32>;
+    // physical pixels
+    viewport: vec2<f32>;
+    // logical pixels
     curve_width: f32;
+    glow: f32;
+    scale_factor: f32;
 };
 
 [[group(1), binding(0)]]
-var<uniform> curve_width: CurveWidth;
+var<uniform> material: CurveMaterial;
 
 struct Vertex {
     [[location(0)]] position: vec3<f32>;
-    [[location(1)]] curve_normal: vec2<f32>;
+    // xy is the offset from the curve, z the distance across it.
+    [[location(1)]] curve_normal: vec3<f32>;
+    // x is the arc length along the curve in world space.
+    // y is the distance along the curve, only non-zero in round caps.
+    [[location(2)]] uv: vec2<f32>;
 };
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
+    // Offset from the centreline along and across the curve in units of half the
+    // curve width. Miters are not lengthened, so its length is the distance from the curve.
+    [[location(0)]] distance: vec2<f32>;
+    // Arc length along the curve in logical pixels.
+    [[location(1)]] arc_length: f32;
 };
 
 [[stage(vertex)]]
 fn vertex(vertex: Vertex) -> VertexOutput {
-    let rh = view.projection[0];
-    let n = sqrt(dot(rh, rh)) * 5.0;
-    let scale = curve_width.curve_width / n;
-
-    let offset_position = vec4<f32>(
-        vertex.position.x + vertex.curve_normal.x * scale,
-        vertex.position.y + vertex.curve_normal.y * scale,
-        vertex.position.z,
-        1.0
+    let half_width = 0.5 * material.curve_width * material.scale_factor;
+
+    // Extend the curve by a pixel so the antialiased edge is not cut off.
+    let extent = half_width * (1.0 + material.glow) + 1.0;
+
+    let world_position = mesh.model * vec4<f32>(vertex.position, 1.0);
+    var clip_position = view.view_proj * world_position;
+
+    // x and y are scaled equally by the projection, so normals keep their
+    // direction in screen space.
+    let ndc_offset = vertex.curve_normal.xy * extent * 2.0 / material.viewport;
+    clip_position = vec4<f32>(
+        clip_position.xy + ndc_offset * clip_position.w,
+        clip_position.zw
     );
 
-    let world_position = mesh.model * offset_position;
+    let rh = view.projection[0];
+    let pixels_per_unit = sqrt(dot(rh, rh)) * 0.5 * material.viewport.x / material.scale_factor;
 
     var out: VertexOutput;
-    out.clip_position = view.view_proj * world_position;
+    out.clip_position = clip_position;
+    out.distance = vec2<f32>(vertex.uv.y, vertex.curve_normal.z) * extent / half_width;
+    out.arc_length = vertex.uv.x * pixels_per_unit;
 
     return out;
 }
 
+struct FragmentInput {
+    [[location(0)]] distance: vec2<f32>;
+    [[location(1)]] arc_length: f32;
+};
+
+fn in_dash(arc_length: f32) -> bool {
+    let dash = material.dash;
+    let period = dash.x + dash.y + dash.z + dash.w;
+    if (period <= 0.0) {
+        return true;
+    }
+
+    let p = arc_length - floor(arc_length / period) * period;
+    return p < dash.x || (p >= dash.x + dash.y && p < dash.x + dash.y + dash.z);
+}
+
 [[stage(fragment)]]
-fn fragment() -> [[location(0)]] vec4<f32> {
-    return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+fn fragment(in: FragmentInput) -> [[location(0)]] vec4<f32> {
+    if (!in_dash(in.arc_length)) {
+        discard;
+    }
+
+    // Zero on the curve and one at its edge. Only round caps have a distance along,
+    // which rounds their ends.
+    let d = length(in.distance);
+
+    // fade out over one pixel centred on the edge of the curve
+    let aa = max(fwidth(d), 0.0001);
+    var alpha = clamp((1.0 - d) / aa + 0.5, 0.0, 1.0);
+
+    if (material.glow > 0.0) {
+        let halo = 1.0 - smoothStep(1.0, 1.0 + material.glow, d);
+        alpha = max(alpha, halo * halo * 0.5);
+    }
+
+    if (alpha <= 0.0) {
+        discard;
+    }
+
+    return vec4<f32>(material.color.rgb, material.color.a * alpha);
 }
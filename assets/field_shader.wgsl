@@ -0,0 +1,51 @@
+#import bevy_pbr::mesh_view_bind_group
+#import bevy_pbr::mesh_struct
+
+[[group(1), binding(0)]]
+var<uniform> mesh: Mesh;
+
+struct Vertex {
+    // x runs from the tail of the glyph at -0.5 to its tip at 0.5.
+    [[location(0)]] position: vec3<f32>;
+    // Offset along and across the glyph in half line widths.
+    [[location(1)]] glyph_offset: vec3<f32>;
+    [[location(2)]] uv: vec2<f32>;
+
+    // xy is the centre of the glyph and zw runs from its tail to its tip, in world space.
+    [[location(3)]] i_glyph: vec4<f32>;
+    [[location(4)]] i_color: vec4<f32>;
+    // Half the line width in normalized device coordinates.
+    [[location(5)]] i_half_width: vec2<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let direction = vertex.i_glyph.zw;
+    let position = vertex.i_glyph.xy + direction * vertex.position.x;
+    let world_position = mesh.model * vec4<f32>(position, vertex.position.z, 1.0);
+    var clip_position = view.view_proj * world_position;
+
+    // x and y are scaled equally in pixels, so the glyph keeps its direction on screen.
+    let along = normalize(direction);
+    let across = vec2<f32>(-along.y, along.x);
+    let offset = along * vertex.glyph_offset.x + across * vertex.glyph_offset.y;
+    clip_position = vec4<f32>(
+        clip_position.xy + offset * vertex.i_half_width * clip_position.w,
+        clip_position.zw
+    );
+
+    var out: VertexOutput;
+    out.clip_position = clip_position;
+    out.color = vertex.i_color;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return in.color;
+}
@@ -1,15 +1,18 @@
 use bevy::prelude::*;
-use crate::scaling::{GraphingView, GraphingBounds};
-use crate::gen_mesh::{mid_axis_count, mid_axis_diff};
-
-#[derive(Copy, Clone, Debug)]
-pub struct MidAxisInfo {
-    pub separation: f32,
-    pub xline_count: usize,
-    pub yline_count: usize,
-    pub rounded_xcentre: f32,
-    pub rounded_ycentre: f32,
-}
+use crate::scaling::{Bounds, GraphingView, GraphingBounds};
+use crate::geometry::{axis_line_positions, mid_axis_info};
+use crate::grid::GridSettings;
+use crate::surface::ViewMode;
+
+pub use crate::geometry::{MidAxisInfo, MinAxisInfo};
+
+pub const AXIS_FONT_SIZE: f32 = 14.0;
+
+/// Logical pixels between a label and its line.
+const LABEL_GAP: f32 = 2.0;
+
+/// Room left for y labels kept on screen at the right edge, in logical pixels.
+const YLABEL_WIDTH: f32 = 40.0;
 
 #[derive(Clone, Debug)]
 pub struct AxisTextInfo {
@@ -19,10 +22,11 @@ pub struct AxisTextInfo {
 #[derive(Bundle, Clone, Debug)]
 pub struct AxisTextBundle {
     #[bundle]
-    pub text_bundle: Text2dBundle,
+    pub text_bundle: TextBundle,
     pub axis_text: AxisText,
 }
 
+/// A label of the mid axis line at a position in graph space.
 #[derive(Component, Copy, Clone, Debug)]
 pub enum AxisText {
     X(f32),
@@ -32,138 +36,142 @@ pub enum AxisText {
 
 pub fn generate_text_bundle(
     axis_text: AxisText,
+    label: String,
     text_style: TextStyle,
 ) -> AxisTextBundle {
-    //let position = match axis_text {
-    //    AxisText::X(n) => Rect { bottom: Val::Px(0.0), left: Val::Px(n), ..Default::default()},
-    //    AxisText::Y(n) => Rect { bottom: Val::Px(n), left: Val::Px(0.0), ..Default::default()},
-    //    AxisText::Origin => Rect::default(),
-    //};
-
-    let n = match axis_text {
-        AxisText::X(n) => n,
-        AxisText::Y(n) => n,
-        AxisText::Origin => 0.0,
-    };
-    
+    // placed by `position_axis_text_system`
     AxisTextBundle {
         axis_text,
-        text_bundle: Text2dBundle {
+        text_bundle: TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
             text: Text::with_section(
-                n.to_string(),
+                label,
                 text_style,
                 TextAlignment::default(),
             ),
-            //style: Style {
-            //    position_type: PositionType::Absolute,
-            //    position,
-            //    ..Default::default()
-            //},
-            //transform: Transform::from_scale(Vec3::new(0.1, 0.1, 1.0)),
+            visibility: Visibility { is_visible: false },
             ..Default::default()
         }
     }
 }
 
+/// Labels the mid axis lines within the graphing bounds again when the lines change.
 pub fn regenerate_axis_text_system(
     mut commands: Commands,
     graphing_bounds: Res<GraphingBounds>,
-    view: Res<GraphingView>,
+    mid_axis_info: Res<MidAxisInfo>,
     axis_text_info: Res<AxisTextInfo>,
-    prev_text: Query<(Entity, With<AxisText>)>,
+    prev_text: Query<Entity, With<AxisText>>,
 ) {
-    if graphing_bounds.is_changed() {
-        for (entity, _) in prev_text.iter() {
-            commands.entity(entity).despawn();
-        }
+    if !mid_axis_info.is_changed() {
+        return
+    }
 
-        let xbounds = graphing_bounds.xbounds;
-        let ybounds = graphing_bounds.ybounds;
-
-        let axis_separation = mid_axis_diff(view.scale);
-        let (xline_count, yline_count) = mid_axis_count(xbounds, ybounds, axis_separation);
-
-        let main_x_axis_rendered = ybounds.start < 0.0 && 0.0 < ybounds.end;
-        let main_y_axis_rendered = xbounds.start < 0.0 && 0.0 < xbounds.end;
-        
-        if main_y_axis_rendered {
-            let rounded_ycentre = (view.centre.y / axis_separation).round() * axis_separation;
-            let text_style = axis_text_info.text_style.clone();
-
-            let bundles_iter = (1..yline_count).map(move |i| {
-                let n1 = rounded_ycentre + axis_separation * i as f32;
-                let n2 = rounded_ycentre - axis_separation * i as f32;
-                [
-                    generate_text_bundle(AxisText::Y(n1), text_style.clone()),
-                    generate_text_bundle(AxisText::Y(n2), text_style.clone())
-                ]
-            }).flatten();
-            commands.spawn_batch(bundles_iter);
-        }
+    for entity in prev_text.iter() {
+        commands.entity(entity).despawn();
+    }
 
-        if main_x_axis_rendered {
-            let rounded_xcentre = (view.centre.x / axis_separation).round() * axis_separation;
-            let text_style = axis_text_info.text_style.clone();
-
-            let bundles_iter = (1..xline_count).map(move |i| {
-                let n1 = rounded_xcentre + axis_separation * i as f32;
-                let n2 = rounded_xcentre - axis_separation * i as f32;
-                [
-                    generate_text_bundle(AxisText::X(n1), text_style.clone()),
-                    generate_text_bundle(AxisText::X(n2), text_style.clone())
-                ]
-            }).flatten();
-            commands.spawn_batch(bundles_iter);
+    let xbounds = graphing_bounds.xbounds;
+    let ybounds = graphing_bounds.ybounds;
+    let info = *mid_axis_info;
+    let text_style = axis_text_info.text_style.clone();
+
+    let x0 = info.xscale.forward(0.0);
+    let y0 = info.yscale.forward(0.0);
+
+    let positions = axis_line_positions(
+        info.yscale, ybounds, info.yseparation, info.yline_count, info.rounded_ycentre, false
+    );
+    let ylabels = positions.into_iter()
+        .filter(|&t| t != y0)
+        .map(|t| generate_text_bundle(
+            AxisText::Y(t),
+            info.yticks.format(info.yscale.inverse(t), info.yseparation, info.yscale),
+            text_style.clone(),
+        ));
+
+    let positions = axis_line_positions(
+        info.xscale, xbounds, info.xseparation, info.xline_count, info.rounded_xcentre, false
+    );
+    let xlabels = positions.into_iter()
+        .filter(|&t| t != x0)
+        .map(|t| generate_text_bundle(
+            AxisText::X(t),
+            info.xticks.format(info.xscale.inverse(t), info.xseparation, info.xscale),
+            text_style.clone(),
+        ));
+
+    let mut bundles = ylabels.chain(xlabels).collect::<Vec<_>>();
+    if x0.is_finite() && y0.is_finite() {
+        bundles.push(generate_text_bundle(AxisText::Origin, "0".to_string(), text_style.clone()));
+    }
+    commands.spawn_batch(bundles);
+}
+
+/// Keeps labels beside their lines as the view is panned and zoomed, and hides them
+/// off screen and while the surface is shown.
+pub fn position_axis_text_system(
+    view: Res<GraphingView>,
+    window: Res<WindowDescriptor>,
+    mode: Res<ViewMode>,
+    mut labels: Query<(&AxisText, &mut Style, &mut Visibility, ChangeTrackers<AxisText>)>,
+) {
+    let moved = view.is_changed() || window.is_changed() || mode.is_changed();
+
+    for (label, mut style, mut visibility, tracker) in labels.iter_mut() {
+        if !(moved || tracker.is_added()) {
+            continue;
         }
 
-        if main_x_axis_rendered && main_y_axis_rendered {
-            commands.spawn_bundle(generate_text_bundle(
-                AxisText::Origin, 
-                axis_text_info.text_style.clone()
-            ));
+        match label_position(*label, &view, &window) {
+            Some(position) if *mode == ViewMode::Graph => {
+                style.position = position;
+                visibility.is_visible = true;
+            }
+            _ => visibility.is_visible = false,
         }
     }
 }
 
-pub fn recalculate_mid_axis_info(bounds: &GraphingBounds, view: &GraphingView) -> MidAxisInfo {
-    use crate::gen_mesh;
-    let xbounds = bounds.xbounds;
-    let separation = gen_mesh::mid_axis_diff(view.scale);
-    let (xline_count, yline_count) = gen_mesh::mid_axis_count(xbounds, xbounds, separation);
-    let rounded_ycentre = (view.centre.y / separation).round() * separation;
-    let rounded_xcentre = (view.centre.x / separation).round() * separation;
-
-    MidAxisInfo {
-        separation,
-        xline_count,
-        yline_count,
-        rounded_xcentre,
-        rounded_ycentre,
-    }
+/// Where a label goes in the window, or `None` if its line is off screen.
+/// Labels follow the main axes, and stay at the edge of the window when the axes are off screen.
+fn label_position(label: AxisText, view: &GraphingView, window: &WindowDescriptor) -> Option<Rect<Val>> {
+    let visible = view.visible_bounds(window.width, window.height);
+    let pixels_per_unit = window.width / (2.0 * view.scale);
+    let contains = |b: Bounds, t: f32| b.start < t && t < b.end;
+
+    let x0 = view.xscale.forward(0.0);
+    let y0 = view.yscale.forward(0.0);
+    let xaxis_y = if y0.is_finite() { y0.clamp(visible.ybounds.start, visible.ybounds.end) } else { visible.ybounds.start };
+    let yaxis_x = if x0.is_finite() { x0.clamp(visible.xbounds.start, visible.xbounds.end) } else { visible.xbounds.start };
+
+    // measured from the bottom left, and kept far enough inside for the text to fit
+    let left = |x: f32| ((x - visible.xbounds.start) * pixels_per_unit + LABEL_GAP)
+        .min(window.width - YLABEL_WIDTH);
+    let bottom = |y: f32| ((y - visible.ybounds.start) * pixels_per_unit - AXIS_FONT_SIZE - LABEL_GAP)
+        .max(LABEL_GAP);
+
+    let (x, y) = match label {
+        AxisText::X(t) if contains(visible.xbounds, t) => (left(t), bottom(xaxis_y)),
+        AxisText::Y(t) if contains(visible.ybounds, t) => (left(yaxis_x), bottom(t) + AXIS_FONT_SIZE / 2.0),
+        AxisText::Origin if contains(visible.xbounds, x0) && contains(visible.ybounds, y0) => (left(x0), bottom(y0)),
+        _ => return None,
+    };
+
+    Some(Rect {
+        left: Val::Px(x),
+        bottom: Val::Px(y),
+        ..Default::default()
+    })
 }
 
-//pub fn keep_axis_text_on_screen_system(
-//    view: Res<GraphingView>,
-//    axis_text_x: Query<mut Transform, AxisTextX>,
-//    axis_text_y: Query<mut Transform, AxisTextY>,
-//) {
-//    if view.is_changed() {
-//        let xbounds = view.visible_xbounds();
-//
-//        if 0.0 > xbounds.start
-//
-//        let ybounds = view.visible_ybounds();
-//    }
-//}
-
-pub type MinAxisInfo = MidAxisInfo;
-impl MidAxisInfo {
-    pub fn calculate_min_axis_info(&self) -> MinAxisInfo {
-        MinAxisInfo {
-            separation: self.separation / 5.0,
-            xline_count: self.xline_count * 5,
-            yline_count: self.yline_count * 5,
-            ..*self
-        }
-    }
+pub fn recalculate_mid_axis_info(
+    bounds: &GraphingBounds,
+    view: &GraphingView,
+    settings: &GridSettings,
+) -> MidAxisInfo {
+    mid_axis_info(bounds, view, settings.xticks, settings.yticks)
 }
@@ -0,0 +1,309 @@
+use crate::scaling::{AxisScale, Bounds};
+use crate::grid::TickMode;
+use crate::data_series::{DataSource, MarkerShape};
+use crate::fit::Model;
+use crate::geometry::{Colormap, FieldKind, MagnitudeStyle};
+use crate::project::PROJECT_EXTENSION;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: fge [OPTIONS] <expression>...
+       fge [OPTIONS] <project.fge> [expression]...
+Options:
+    --xscale SCALE    linear, log10, ln, symlog or symlog:<threshold>
+    --yscale SCALE
+    --xticks TICKS    decimal, pi, degrees or a step size
+    --yticks TICKS
+    --watch FILE      graph the expressions in FILE, one per line, reloading them when it changes
+    --svg FILE        write the graph to an svg file and exit, without opening a window
+    --png FILE        render the graph to a png file and exit, without opening a window
+    --csv FILE        write sampled curve values to a csv file and exit
+    --tsv FILE        write sampled curve values to a tsv file and exit
+    --range START:END x range sampled for --csv and --tsv, defaults to the visible range
+    --samples N       number of samples for --csv and --tsv, defaults to 256
+    --data FILE[:X:Y] plot columns X and Y of a csv or tsv file, by header or index from 0.
+                      defaults to the first two columns. may be repeated
+    --marker SHAPE    circle, square, diamond, triangle or none, defaults to circle
+    --marker-size PX  defaults to 6
+    --connect         join the points of each data series with a line
+    --fit MODEL       fit linear, poly:N or an expression with parameters, such as a*exp(b*x)+c,
+                      to each data series and plot the fitted curves
+    --guess NAME=VALUE,...
+                      starting values of parameters for --fit, which otherwise start at 1
+    --residuals FILE  write the residuals of each fit to a csv file
+    --slope EXPR      draw the slope field of dy/dx = EXPR, a function of x and y. may be repeated
+    --vector P,Q      draw the vector field (P, Q) of functions of x and y. may be repeated
+    --magnitude STYLE show the magnitude of vector fields by arrow length or color, defaults to length
+    --trajectory X,Y  draw the solution curve through (X, Y) on each field. may be repeated.
+                      while graphing, click to add more
+    --complex EXPR    colour the plane by the complex function EXPR of z, with hue for the
+                      argument and brightness for the modulus
+    --contours        draw contour lines of the modulus and argument of --complex
+    --heatmap EXPR    colour the graph by z = EXPR, a function of x and y, with a colour bar
+    --colormap NAME   viridis, magma or diverging, defaults to viridis
+    --levels A,B,...  draw labelled contours of --heatmap at these values
+    --surface EXPR    draw the surface z = EXPR in 3D. Tab switches between it and the graph
+    --wireframe       draw grid lines over --surface. W toggles them
+    --sequence EXPR   draw the sequence a(n) = EXPR as a point for each integer n. may be repeated
+    --cobweb EXPR     draw the cobweb diagram of x_{n+1} = EXPR, a function of x_n. may be repeated.
+                      while graphing, click to set the starting value and use up and down to
+                      change the number of iterations
+    --start X         starting value x_0 of --cobweb, defaults to 0.5
+    --iterations N    number of iterations of --cobweb, defaults to 20";
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub expressions: Vec<String>,
+    /// Project opened at startup. Other arguments are added to it or override it.
+    pub project: Option<PathBuf>,
+    /// File of expressions to graph and reload when it changes.
+    pub watch: Option<PathBuf>,
+    pub xscale: Option<AxisScale>,
+    pub yscale: Option<AxisScale>,
+    pub xticks: Option<TickMode>,
+    pub yticks: Option<TickMode>,
+    pub svg: Option<PathBuf>,
+    pub png: Option<PathBuf>,
+    /// Path and delimiter for exported samples.
+    pub samples_file: Option<(PathBuf, char)>,
+    pub sample_range: Option<Bounds>,
+    pub sample_count: usize,
+    pub data: Vec<DataSource>,
+    pub marker: Option<MarkerShape>,
+    /// Diameter of markers in logical pixels.
+    pub marker_size: f32,
+    pub connect: bool,
+    pub fit: Option<Model>,
+    pub guesses: Vec<(String, f64)>,
+    pub residuals_file: Option<PathBuf>,
+    pub fields: Vec<FieldKind>,
+    pub magnitude: MagnitudeStyle,
+    /// Initial conditions of solution curves on each field.
+    pub trajectories: Vec<[f32; 2]>,
+    pub complex: Option<String>,
+    pub contours: bool,
+    pub heatmap: Option<String>,
+    pub colormap: Colormap,
+    /// Values of the heatmap's contours.
+    pub levels: Vec<f32>,
+    pub surface: Option<String>,
+    pub wireframe: bool,
+    pub sequences: Vec<String>,
+    pub cobwebs: Vec<String>,
+    /// Starting value of each cobweb.
+    pub start: Option<f32>,
+    pub iterations: Option<usize>,
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut expressions = Vec::new();
+    let mut project = None;
+    let mut watch = None;
+    let mut xscale = None;
+    let mut yscale = None;
+    let mut xticks = None;
+    let mut yticks = None;
+    let mut svg = None;
+    let mut png = None;
+    let mut samples_file = None;
+    let mut sample_range = None;
+    let mut sample_count = 256;
+    let mut data = Vec::new();
+    let mut marker = Some(MarkerShape::Circle);
+    let mut marker_size = 6.0;
+    let mut connect = false;
+    let mut fit = None;
+    let mut guesses = Vec::new();
+    let mut residuals_file = None;
+    let mut fields = Vec::new();
+    let mut magnitude = MagnitudeStyle::Length;
+    let mut trajectories = Vec::new();
+    let mut complex = None;
+    let mut contours = false;
+    let mut heatmap = None;
+    let mut colormap = Colormap::Viridis;
+    let mut levels = Vec::new();
+    let mut surface = None;
+    let mut wireframe = false;
+    let mut sequences = Vec::new();
+    let mut cobwebs = Vec::new();
+    let mut start = None;
+    let mut iterations = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next()
+            .ok_or_else(|| format!("Missing value for {}", flag));
+
+        match arg.as_str() {
+            "--watch" => watch = Some(value("--watch")?.into()),
+            "--xscale" => xscale = Some(value("--xscale")?.parse()?),
+            "--yscale" => yscale = Some(value("--yscale")?.parse()?),
+            "--xticks" => xticks = Some(value("--xticks")?.parse()?),
+            "--yticks" => yticks = Some(value("--yticks")?.parse()?),
+            "--svg" => svg = Some(value("--svg")?.into()),
+            "--png" => png = Some(value("--png")?.into()),
+            "--csv" => samples_file = Some((value("--csv")?.into(), ',')),
+            "--tsv" => samples_file = Some((value("--tsv")?.into(), '\t')),
+            "--range" => sample_range = Some(parse_range(&value("--range")?)?),
+            "--samples" => sample_count = match value("--samples")?.parse::<usize>() {
+                Ok(n) if n > 0 => n,
+                _ => return Err("--samples must be a positive integer".to_string()),
+            },
+            "--data" => data.push(value("--data")?.parse()?),
+            "--marker" => marker = match value("--marker")?.as_str() {
+                "none" => None,
+                shape => Some(shape.parse()?),
+            },
+            "--marker-size" => marker_size = match value("--marker-size")?.parse::<f32>() {
+                Ok(size) if size > 0.0 => size,
+                _ => return Err("--marker-size must be a positive number".to_string()),
+            },
+            "--connect" => connect = true,
+            "--fit" => fit = Some(value("--fit")?.parse()?),
+            "--guess" => guesses.extend(parse_guesses(&value("--guess")?)?),
+            "--residuals" => residuals_file = Some(value("--residuals")?.into()),
+            "--slope" => fields.push(FieldKind::slope(&value("--slope")?)?),
+            "--vector" => fields.push(FieldKind::vector(&value("--vector")?)?),
+            "--magnitude" => magnitude = value("--magnitude")?.parse()?,
+            "--trajectory" => trajectories.push(parse_point(&value("--trajectory")?)?),
+            "--complex" => complex = Some(value("--complex")?),
+            "--contours" => contours = true,
+            "--heatmap" => heatmap = Some(value("--heatmap")?),
+            "--colormap" => colormap = value("--colormap")?.parse()?,
+            "--levels" => levels = parse_levels(&value("--levels")?)?,
+            "--surface" => surface = Some(value("--surface")?),
+            "--wireframe" => wireframe = true,
+            "--sequence" => sequences.push(value("--sequence")?),
+            "--cobweb" => cobwebs.push(value("--cobweb")?),
+            "--start" => start = match value("--start")?.parse::<f32>() {
+                Ok(x) if x.is_finite() => Some(x),
+                _ => return Err("--start must be a number".to_string()),
+            },
+            "--iterations" => iterations = match value("--iterations")?.parse::<usize>() {
+                Ok(n) if n > 0 => Some(n),
+                _ => return Err("--iterations must be a positive integer".to_string()),
+            },
+            flag if flag.starts_with("--") => return Err(format!("Unknown flag {}", flag)),
+            "" => (),
+            path if path.ends_with(PROJECT_EXTENSION) && project.is_none() => project = Some(path.into()),
+            _ => expressions.push(arg),
+        }
+    }
+
+    if expressions.is_empty() && data.is_empty() && fields.is_empty() && complex.is_none() && heatmap.is_none() && surface.is_none()
+        && sequences.is_empty() && cobwebs.is_empty() && project.is_none() && watch.is_none() {
+        return Err("No expression or data passed".to_string());
+    }
+
+    if fit.is_some() && data.is_empty() && project.is_none() {
+        return Err("--fit needs a data series from --data".to_string());
+    }
+
+    if !trajectories.is_empty() && fields.is_empty() {
+        return Err("--trajectory needs a field from --slope or --vector".to_string());
+    }
+
+    if contours && complex.is_none() {
+        return Err("--contours needs a function from --complex".to_string());
+    }
+
+    if !levels.is_empty() && heatmap.is_none() {
+        return Err("--levels needs a function from --heatmap".to_string());
+    }
+
+    if wireframe && surface.is_none() {
+        return Err("--wireframe needs a function from --surface".to_string());
+    }
+
+    if (start.is_some() || iterations.is_some()) && cobwebs.is_empty() {
+        return Err("--start and --iterations need a recurrence from --cobweb".to_string());
+    }
+
+    // the range of a project's own scale is checked once it is loaded
+    if let (Some(range), Some(scale)) = (sample_range, xscale) {
+        check_range(range, scale)?;
+    }
+
+    Ok(Args {
+        expressions,
+        project,
+        watch,
+        xscale,
+        yscale,
+        xticks,
+        yticks,
+        svg,
+        png,
+        samples_file,
+        sample_range,
+        sample_count,
+        data,
+        marker,
+        marker_size,
+        connect,
+        fit,
+        guesses,
+        residuals_file,
+        fields,
+        magnitude,
+        trajectories,
+        complex,
+        contours,
+        heatmap,
+        colormap,
+        levels,
+        surface,
+        wireframe,
+        sequences,
+        cobwebs,
+        start,
+        iterations,
+    })
+}
+
+fn parse_guesses(guesses: &str) -> Result<Vec<(String, f64)>, String> {
+    guesses.split(',')
+        .map(|guess| {
+            let err = || format!("Invalid guess '{}', expected NAME=VALUE", guess);
+            let (name, value) = guess.split_once('=').ok_or_else(err)?;
+            let value = value.trim().parse::<f64>().map_err(|_| err())?;
+            Ok((name.trim().to_string(), value))
+        })
+        .collect()
+}
+
+fn parse_levels(levels: &str) -> Result<Vec<f32>, String> {
+    levels.split(',')
+        .map(|level| level.trim().parse::<f32>()
+            .map_err(|_| format!("Expected a number in --levels, found '{}'", level.trim())))
+        .collect()
+}
+
+fn parse_point(point: &str) -> Result<[f32; 2], String> {
+    let err = || format!("Invalid point '{}', expected X,Y", point);
+    let (x, y) = point.split_once(',').ok_or_else(err)?;
+    let x = x.trim().parse::<f32>().map_err(|_| err())?;
+    let y = y.trim().parse::<f32>().map_err(|_| err())?;
+    Ok([x, y])
+}
+
+/// Rejects sample ranges reaching outside the domain of a logarithmic x scale.
+pub fn check_range(range: Bounds, xscale: AxisScale) -> Result<(), String> {
+    if xscale.contains(range.start) && xscale.contains(range.end) {
+        Ok(())
+    } else {
+        Err(format!("--range {}:{} must be positive on a logarithmic x scale", range.start, range.end))
+    }
+}
+
+fn parse_range(range: &str) -> Result<Bounds, String> {
+    let err = || format!("Invalid range '{}', expected START:END", range);
+    let (start, end) = range.split_once(':').ok_or_else(err)?;
+    let start = start.trim().parse::<f32>().map_err(|_| err())?;
+    let end = end.trim().parse::<f32>().map_err(|_| err())?;
+
+    if start < end {
+        Ok(Bounds { start, end })
+    } else {
+        Err(err())
+    }
+}
@@ -1,18 +1,90 @@
 use bevy::prelude::*;
 use bevy::render::render_resource::*;
+use bevy::render::render_resource::std140::{AsStd140, Std140};
 use bevy::render::renderer::RenderDevice;
 use bevy::render::render_asset::{PrepareAssetError, RenderAsset};
 use bevy::pbr::MaterialPipeline;
 use bevy::reflect::TypeUuid;
+use bevy::asset::HandleId;
 use bevy::ecs::system::lifetimeless::SRes;
 use bevy::ecs::system::SystemParamItem;
+use serde::{Deserialize, Serialize};
 
-const CURVE_WIDTH: f32 = 0.03;
+use crate::scaling::{DEFAULT_WINDOW_WIDTH, DEFAULT_WINDOW_HEIGHT};
 
 #[derive(Copy, Clone, Debug, TypeUuid)]
 #[uuid = "0000002a-000c-0005-0c03-0938362b0809"]
 pub struct CurveMaterial {
-    //pub color: Color,
+    pub color: Color,
+    /// Thickness of the curve in logical pixels.
+    pub width: f32,
+    /// Distance the halo extends past the edge of the curve, as a multiple of half
+    /// the curve width. No halo is drawn if this is zero.
+    pub glow: f32,
+    pub line_style: LineStyle,
+    /// Size of the primary window in physical pixels.
+    /// Kept up to date by `update_curve_viewport_system`.
+    pub viewport: Vec2,
+    pub scale_factor: f32,
+}
+
+/// Colours given to curves in the order they are entered.
+pub const CURVE_COLORS: [Color; 6] = [
+    Color::WHITE,
+    Color::rgb(0.95, 0.45, 0.35),
+    Color::rgb(0.35, 0.65, 0.95),
+    Color::rgb(0.45, 0.85, 0.45),
+    Color::rgb(0.95, 0.8, 0.3),
+    Color::rgb(0.75, 0.5, 0.95),
+];
+
+pub fn curve_color(index: usize) -> Color {
+    CURVE_COLORS[index % CURVE_COLORS.len()]
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LineStyle {
+    Solid,
+    Dashed,
+    Dotted,
+    DashDot,
+}
+
+impl LineStyle {
+    /// Alternating on and off lengths of the pattern, as multiples of the curve width.
+    /// All zero for a solid line.
+    pub fn dash_pattern(self) -> [f32; 4] {
+        match self {
+            LineStyle::Solid => [0.0; 4],
+            LineStyle::Dashed => [6.0, 4.0, 6.0, 4.0],
+            LineStyle::Dotted => [1.0, 2.5, 1.0, 2.5],
+            LineStyle::DashDot => [6.0, 3.0, 1.0, 3.0],
+        }
+    }
+}
+
+impl Default for CurveMaterial {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            width: 3.0,
+            glow: 0.0,
+            line_style: LineStyle::Solid,
+            viewport: Vec2::new(DEFAULT_WINDOW_WIDTH, DEFAULT_WINDOW_HEIGHT),
+            scale_factor: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, AsStd140)]
+struct CurveMaterialUniformData {
+    color: Vec4,
+    /// Dash pattern in logical pixels.
+    dash: Vec4,
+    viewport: Vec2,
+    curve_width: f32,
+    glow: f32,
+    scale_factor: f32,
 }
 
 #[derive(Clone)]
@@ -31,11 +103,22 @@ impl RenderAsset for CurveMaterial {
     }
 
     fn prepare_asset(
-        _extracted_asset: Self::ExtractedAsset,
+        material: Self::ExtractedAsset,
         (render_device, material_pipeline): &mut SystemParamItem<Self::Param>,
     ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
+        let dash = material.line_style.dash_pattern().map(|l| l * material.width);
+        let value = CurveMaterialUniformData {
+            color: material.color.as_linear_rgba_f32().into(),
+            dash: dash.into(),
+            viewport: material.viewport,
+            curve_width: material.width,
+            glow: material.glow.max(0.0),
+            scale_factor: material.scale_factor,
+        };
+        let value_std140 = value.as_std140();
+
         let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            contents: &CURVE_WIDTH.to_ne_bytes(),
+            contents: value_std140.as_bytes(),
             label: None,
             usage: BufferUsages::UNIFORM,
         });
@@ -64,6 +147,11 @@ impl Material for CurveMaterial {
         Some(asset_server.load("curve_shader.wgsl"))
     }
 
+    fn alpha_mode(_render_asset: &<Self as RenderAsset>::PreparedAsset) -> AlphaMode {
+        // Edges are antialiased in the fragment shader.
+        AlphaMode::Blend
+    }
+
     fn bind_group(render_asset: &<Self as RenderAsset>::PreparedAsset) -> &BindGroup {
         &render_asset.bind_group
     }
@@ -72,11 +160,11 @@ impl Material for CurveMaterial {
         render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             entries: &[BindGroupLayoutEntry {
                 binding: 0,
-                visibility: ShaderStages::VERTEX,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                 ty: BindingType::Buffer {
                     ty: BufferBindingType::Uniform,
                     has_dynamic_offset: false,
-                    min_binding_size: BufferSize::new(4),
+                    min_binding_size: BufferSize::new(CurveMaterialUniformData::std140_size_static() as u64),
                 },
                 count: None,
             }],
@@ -84,3 +172,31 @@ impl Material for CurveMaterial {
         })
     }
 }
+
+/// Curve widths are specified in logical pixels, so the shader needs
+/// the current viewport size and scale factor.
+pub fn update_curve_viewport_system(
+    windows: Res<Windows>,
+    mut materials: ResMut<Assets<CurveMaterial>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let viewport = Vec2::new(window.physical_width() as f32, window.physical_height() as f32);
+    let scale_factor = window.scale_factor() as f32;
+
+    // Only touch outdated materials, as every modified material is reuploaded.
+    let outdated = materials.iter()
+        .filter(|(_, m)| m.viewport != viewport || m.scale_factor != scale_factor)
+        .map(|(id, _)| id)
+        .collect::<Vec<HandleId>>();
+
+    for id in outdated {
+        if let Some(material) = materials.get_mut(id) {
+            material.viewport = viewport;
+            material.scale_factor = scale_factor;
+        }
+    }
+}
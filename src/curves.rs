@@ -0,0 +1,297 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::curve_material::CurveMaterial;
+use crate::data_series::{gen_series_line, spawn_markers, DataSeries, Markers, SeriesMesh};
+use crate::domain_coloring::DomainColoring;
+use crate::field::Field;
+use crate::gen_mesh::{self, Expression, ExpressionText, StrokeStyle};
+use crate::geometry::{defines_variable, uses_any, Definitions};
+use crate::heatmap::Heatmap;
+use crate::project::{CurveEntry, DataEntry, Project};
+use crate::scaling::{GraphingBounds, GraphingView};
+use crate::sequence::{Cobweb, Sequence};
+use crate::surface::Surface;
+
+/// Adds, removes and updates curves from a system.
+///
+/// ```ignore
+/// fn add_sine(mut curves: Curves) {
+///     curves.add(&CurveEntry { expression: "sin(x)".to_string(), ..Default::default() }).unwrap();
+/// }
+/// ```
+#[derive(SystemParam)]
+pub struct Curves<'w, 's> {
+    commands: Commands<'w, 's>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<CurveMaterial>>,
+    project: Res<'w, Project>,
+    definitions: Res<'w, Definitions>,
+    graphing_bounds: Res<'w, GraphingBounds>,
+    view: Res<'w, GraphingView>,
+    curves: Query<'w, 's, (
+        Entity,
+        &'static mut Expression,
+        &'static mut ExpressionText,
+        &'static mut StrokeStyle,
+        &'static Handle<CurveMaterial>,
+    )>,
+}
+
+impl<'w, 's> Curves<'w, 's> {
+    /// Graphs a new curve. Parameters of the project are substituted into its expression.
+    ///
+    /// The curve may use or make definitions, like `f(x) = x^2` or `a = 3`, resolved
+    /// across the curves already graphed and this one.
+    pub fn add(&mut self, curve: &CurveEntry) -> Result<Entity, String> {
+        let definitions = self.resolve_with(None, &curve.expression)?;
+        let expression = self.project.parse_curve(curve, &definitions)?;
+        Ok(spawn_curve(
+            &mut self.commands,
+            &mut self.meshes,
+            &mut self.materials,
+            expression,
+            curve,
+            &self.graphing_bounds,
+            &self.view,
+        ))
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if self.curves.get(entity).is_ok() {
+            self.commands.entity(entity).despawn();
+        }
+    }
+
+    /// Replaces the expression and style of a curve. Its mesh is rebuilt by
+    /// `regenerate_meshes_system`. Nothing is changed if the expression does not parse.
+    pub fn update(&mut self, entity: Entity, curve: &CurveEntry) -> Result<(), String> {
+        let definitions = self.resolve_with(Some(entity), &curve.expression)?;
+        let parsed = self.project.parse_curve(curve, &definitions)?;
+        let (_, mut expression, mut text, mut stroke, material) = self.curves.get_mut(entity)
+            .map_err(|_| format!("{:?} is not a curve", entity))?;
+
+        *expression = parsed;
+        text.0 = curve.expression.clone();
+        *stroke = curve.stroke;
+
+        if let Some(old) = self.materials.get_mut(material) {
+            *old = CurveMaterial {
+                viewport: old.viewport,
+                scale_factor: old.scale_factor,
+                ..curve.material()
+            };
+        }
+
+        Ok(())
+    }
+
+    /// The definitions among the curves, with `line` added or replacing the line of `entity`.
+    /// Curves using definitions it changes are reparsed by `resolve_definitions_system`.
+    fn resolve_with(&self, entity: Option<Entity>, line: &str) -> Result<Definitions, String> {
+        let others = self.curves.iter()
+            .filter(|(other, ..)| Some(*other) != entity)
+            .map(|(_, _, text, ..)| text.0.as_str());
+        Definitions::resolve(others.chain([line]))
+    }
+
+    /// The current expression and style of a curve.
+    pub fn get(&self, entity: Entity) -> Option<CurveEntry> {
+        let (_, _, text, stroke, material) = self.curves.get(entity).ok()?;
+        let material = self.materials.get(material).copied().unwrap_or_default();
+        Some(CurveEntry::from_curve(&text.0, *stroke, &material))
+    }
+}
+
+pub fn spawn_curve(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<CurveMaterial>,
+    expression: Expression,
+    curve: &CurveEntry,
+    bounds: &GraphingBounds,
+    view: &GraphingView,
+) -> Entity {
+    let mesh = gen_mesh::gen_expr_mesh(&expression, bounds.xbounds, (view.xscale, view.yscale), &curve.stroke);
+
+    commands.spawn_bundle(MaterialMeshBundle {
+        mesh: meshes.add(mesh),
+        material: materials.add(curve.material()),
+        transform: Transform::from_xyz(0.0, 0.0, 0.1),
+        // variable definitions have nothing to graph
+        visibility: Visibility { is_visible: !defines_variable(&curve.expression) },
+        ..Default::default()
+    })
+        .insert(expression)
+        .insert(ExpressionText(curve.expression.clone()))
+        .insert(curve.stroke)
+        .id()
+}
+
+/// Everything other than curves whose expressions may use definitions.
+#[derive(SystemParam)]
+pub struct DefinitionUsers<'w, 's> {
+    fields: Query<'w, 's, &'static mut Field>,
+    plots: Query<'w, 's, &'static mut DomainColoring>,
+    heatmaps: Query<'w, 's, &'static mut Heatmap>,
+    surfaces: Query<'w, 's, &'static mut Surface>,
+    sequences: Query<'w, 's, &'static mut Sequence>,
+    cobwebs: Query<'w, 's, &'static mut Cobweb>,
+}
+
+impl<'w, 's> DefinitionUsers<'w, 's> {
+    /// Parses those using any of `names` again. Only those that parse differently are
+    /// changed, as changing them regenerates them.
+    fn reparse(&mut self, project: &Project, definitions: &Definitions, names: &[String]) {
+        for mut field in self.fields.iter_mut() {
+            if !field.entry.kind.expressions().into_iter().any(|text| uses_any(text, names)) {
+                continue;
+            }
+            match project.parse_field(&field.entry, definitions) {
+                Ok(kind) if kind != field.kind => field.kind = kind,
+                Ok(_) => (),
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        for mut plot in self.plots.iter_mut() {
+            if !uses_any(&plot.entry.expression, names) {
+                continue;
+            }
+            match project.parse_complex(&plot.entry, definitions) {
+                Ok(expression) if expression != plot.expression => plot.expression = expression,
+                Ok(_) => (),
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        for mut heatmap in self.heatmaps.iter_mut() {
+            if !uses_any(&heatmap.entry.expression, names) {
+                continue;
+            }
+            match project.parse_heatmap(&heatmap.entry, definitions) {
+                Ok(expression) if expression != heatmap.expression => heatmap.expression = expression,
+                Ok(_) => (),
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        for mut surface in self.surfaces.iter_mut() {
+            if !uses_any(&surface.entry.expression, names) {
+                continue;
+            }
+            match project.parse_surface(&surface.entry, definitions) {
+                Ok(expression) if expression != surface.expression => surface.expression = expression,
+                Ok(_) => (),
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        for mut sequence in self.sequences.iter_mut() {
+            if !uses_any(&sequence.entry.expression, names) {
+                continue;
+            }
+            match project.parse_sequence(&sequence.entry, definitions) {
+                Ok(expression) if expression != sequence.expression => sequence.expression = expression,
+                Ok(_) => (),
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        for mut cobweb in self.cobwebs.iter_mut() {
+            if !uses_any(&cobweb.entry.expression, names) {
+                continue;
+            }
+            match project.parse_cobweb(&cobweb.entry, definitions) {
+                Ok(expression) if expression != cobweb.expression => cobweb.expression = expression,
+                Ok(_) => (),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+}
+
+/// Resolves the definitions among all curves again when any curve is added, changed or
+/// removed. Only the curves, fields, sequences and other plots using definitions that
+/// changed are reparsed.
+pub fn resolve_definitions_system(
+    project: Res<Project>,
+    mut definitions: ResMut<Definitions>,
+    changed: Query<Entity, Changed<ExpressionText>>,
+    removed: RemovedComponents<ExpressionText>,
+    mut curves: Query<(&ExpressionText, &mut Expression, &mut Visibility)>,
+    mut users: DefinitionUsers,
+) {
+    if changed.iter().next().is_none() && removed.iter().next().is_none() {
+        return
+    }
+
+    for entity in changed.iter() {
+        if let Ok((text, _, mut visibility)) = curves.get_mut(entity) {
+            visibility.is_visible = !defines_variable(&text.0);
+        }
+    }
+
+    let resolved = match Definitions::resolve(curves.iter().map(|(text, ..)| text.0.as_str())) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            println!("{}", e);
+            return
+        }
+    };
+
+    let names = resolved.changed(&definitions);
+    if names.is_empty() {
+        return
+    }
+
+    for (text, mut expression, _) in curves.iter_mut() {
+        if !uses_any(&text.0, &names) {
+            continue;
+        }
+
+        let entry = CurveEntry { expression: text.0.clone(), ..Default::default() };
+        match project.parse_curve(&entry, &resolved) {
+            Ok(parsed) => *expression = parsed,
+            Err(e) => println!("{}", e),
+        }
+    }
+    users.reparse(&project, &resolved, &names);
+
+    *definitions = resolved;
+}
+
+/// Spawns an entity for the series' line, if it is connected, and one for its markers.
+pub fn spawn_data_series(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<CurveMaterial>,
+    series: DataSeries,
+    entry: &DataEntry,
+    bounds: &GraphingBounds,
+    view: &GraphingView,
+) {
+    let scales = (view.xscale, view.yscale);
+
+    if entry.connect {
+        let stroke = StrokeStyle::default();
+        commands.spawn_bundle(MaterialMeshBundle {
+            mesh: meshes.add(gen_series_line(&series, &stroke, bounds, scales)),
+            material: materials.add(CurveMaterial {
+                color: entry.color,
+                width: 1.5,
+                ..Default::default()
+            }),
+            transform: Transform::from_xyz(0.0, 0.0, 0.1),
+            visibility: Visibility { is_visible: true },
+            ..Default::default()
+        }).insert(series.clone()).insert(SeriesMesh::Line(stroke));
+    }
+
+    if let Some(shape) = entry.marker {
+        let markers = Markers { color: entry.color, size: entry.marker_size };
+        spawn_markers(commands, meshes, shape, markers, 0.15)
+            .insert(series)
+            .insert(SeriesMesh::Markers(shape));
+    }
+}
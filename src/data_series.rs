@@ -0,0 +1,398 @@
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, Mesh};
+use bevy::render::primitives::Aabb;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::render::view::ComputedVisibility;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::field::{bounds_aabb, ndc_half_width, resize_glyphs, GlyphInstance, Glyphs};
+use crate::gen_mesh::{gen_polyline_mesh, StrokeStyle};
+use crate::scaling::{AxisScale, Bounds, GraphingBounds, GraphingView};
+
+/// Number of segments in a round marker.
+const CIRCLE_SEGMENTS: usize = 16;
+
+/// Points loaded from two columns of a csv or tsv file, in data space.
+#[derive(Component, Clone, Debug)]
+pub struct DataSeries {
+    pub name: String,
+    pub points: Vec<[f32; 2]>,
+}
+
+/// Series loaded from the command line, spawned at startup.
+#[derive(Clone, Debug, Default)]
+pub struct LoadedSeries(pub Vec<DataSeries>);
+
+/// Colour and size of the markers an entity draws at the points in its `Glyphs`.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct Markers {
+    pub color: Color,
+    /// Logical pixels across
+    pub size: f32,
+}
+
+/// Which part of a data series an entity draws.
+/// A series is drawn by one entity for its markers and one for its connecting line.
+#[derive(Component, Copy, Clone, Debug, PartialEq)]
+pub enum SeriesMesh {
+    Markers(MarkerShape),
+    Line(StrokeStyle),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MarkerShape {
+    Circle,
+    Square,
+    Diamond,
+    Triangle,
+}
+
+/// A column selected by its zero based index or by its header.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+/// A series given on the command line as `FILE[:XCOL:YCOL]`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DataSource {
+    pub path: PathBuf,
+    pub xcol: Column,
+    pub ycol: Column,
+}
+
+impl FromStr for MarkerShape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "circle" => Ok(MarkerShape::Circle),
+            "square" => Ok(MarkerShape::Square),
+            "diamond" => Ok(MarkerShape::Diamond),
+            "triangle" => Ok(MarkerShape::Triangle),
+            _ => Err(format!("Unknown marker '{}', expected circle, square, diamond or triangle", s)),
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("Empty column name".to_string());
+        }
+
+        Ok(match s.parse::<usize>() {
+            Ok(i) => Column::Index(i),
+            Err(_) => Column::Name(s.to_string()),
+        })
+    }
+}
+
+impl FromStr for DataSource {
+    type Err = String;
+
+    /// Columns default to the first two. Paths containing ':' are only split
+    /// when two columns follow them.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.rsplitn(3, ':').collect::<Vec<_>>();
+
+        if let [ycol, xcol, path] = parts[..] {
+            Ok(DataSource {
+                path: path.into(),
+                xcol: xcol.parse()?,
+                ycol: ycol.parse()?,
+            })
+        } else {
+            Ok(DataSource {
+                path: s.into(),
+                xcol: Column::Index(0),
+                ycol: Column::Index(1),
+            })
+        }
+    }
+}
+
+impl DataSeries {
+    /// Reads the source's columns. Files ending in `.tsv` or with tabs in their
+    /// first line are tab separated, others are comma separated.
+    pub fn load(source: &DataSource) -> Result<Self, String> {
+        let path = &source.path;
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+
+        let tsv = path.extension().map_or(false, |ext| ext == "tsv");
+        let delimiter = if tsv || text.lines().next().map_or(false, |l| l.contains('\t')) {
+            '\t'
+        } else {
+            ','
+        };
+
+        Self::parse(&text, delimiter, &source.xcol, &source.ycol)
+            .map(|mut series| {
+                series.name = format!("{} ({})", series.name, file_name(path));
+                series
+            })
+            .map_err(|e| format!("Error in {}: {}", path.display(), e))
+    }
+
+    /// Empty cells are read as undefined, breaking any line through the series.
+    /// Lines starting with '#' are ignored.
+    pub fn parse(text: &str, delimiter: char, xcol: &Column, ycol: &Column) -> Result<Self, String> {
+        let mut rows = text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|(n, line)| (n + 1, split_record(line, delimiter)))
+            .peekable();
+
+        // the first row is a header if it is not all numbers
+        let has_header = match rows.peek() {
+            Some((_, first)) => first.iter().any(|f| !f.trim().is_empty() && f.trim().parse::<f32>().is_err()),
+            None => return Err("no data".to_string()),
+        };
+        let header = if has_header {
+            rows.next().map(|(_, fields)| fields)
+        } else {
+            None
+        };
+
+        let x = column_index(xcol, header.as_deref())?;
+        let y = column_index(ycol, header.as_deref())?;
+
+        let mut points = Vec::new();
+        for (line, fields) in rows {
+            let value = |i: usize| match fields.get(i).map(|f| f.trim()) {
+                // without a header, rows are the only check that a column exists
+                None if header.is_none() => {
+                    Err(format!("line {}: column {} out of range, the row has {} columns", line, i, fields.len()))
+                }
+                None | Some("") => Ok(f32::NAN),
+                Some(f) => f.parse::<f32>()
+                    .map_err(|_| format!("line {}: '{}' is not a number", line, f)),
+            };
+            points.push([value(x)?, value(y)?]);
+        }
+
+        let name = match &header {
+            Some(header) => header[y].clone(),
+            None => format!("column {}", y),
+        };
+
+        Ok(DataSeries { name, points })
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn column_index(column: &Column, header: Option<&[String]>) -> Result<usize, String> {
+    match (column, header) {
+        (Column::Index(i), Some(header)) if *i >= header.len() => {
+            Err(format!("column {} out of range, there are {} columns", i, header.len()))
+        }
+        (Column::Index(i), _) => Ok(*i),
+        (Column::Name(name), Some(header)) => header.iter()
+            .position(|h| h.trim() == name)
+            .ok_or_else(|| format!("no column named '{}'", name)),
+        (Column::Name(name), None) => Err(format!("no header to find column '{}' in", name)),
+    }
+}
+
+/// Splits a line into fields, removing quotes around fields.
+/// Quotes inside quoted fields are escaped by doubling them.
+fn split_record(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+
+    fields.push(field);
+    fields
+}
+
+/// Draws lines through series again when the bounds change, and places their markers.
+pub fn regenerate_data_series_system(
+    graphing_bounds: Res<GraphingBounds>,
+    view: Res<GraphingView>,
+    windows: Res<Windows>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut lines: Query<(&DataSeries, &SeriesMesh, &mut Handle<Mesh>)>,
+    mut markers: Query<(&DataSeries, &Markers, ChangeTrackers<Markers>, &mut Glyphs, &mut Aabb)>,
+) {
+    let scales = (view.xscale, view.yscale);
+
+    if graphing_bounds.is_changed() {
+        for (data, part, mut mesh_handle) in lines.iter_mut() {
+            if let SeriesMesh::Line(stroke) = part {
+                let mesh = gen_series_line(data, stroke, &graphing_bounds, scales);
+                *mesh_handle = meshes.set(mesh_handle.clone(), mesh);
+            }
+        }
+    }
+
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    for (data, style, tracker, mut glyphs, mut aabb) in markers.iter_mut() {
+        if graphing_bounds.is_changed() || tracker.is_changed() {
+            glyphs.0 = style.instances(&visible_points(data, &graphing_bounds, scales), window);
+            *aabb = bounds_aabb(&graphing_bounds);
+        } else {
+            resize_glyphs(&mut glyphs, ndc_half_width(style.size, window));
+        }
+    }
+}
+
+/// The line through a series, within the bounds.
+pub fn gen_series_line(
+    series: &DataSeries,
+    stroke: &StrokeStyle,
+    bounds: &GraphingBounds,
+    scales: (AxisScale, AxisScale),
+) -> Mesh {
+    gen_polyline_mesh(&cull_line(&graph_points(series, scales), bounds.xbounds), stroke)
+}
+
+/// Points of the series within the bounds, in graph space.
+fn visible_points(series: &DataSeries, bounds: &GraphingBounds, scales: (AxisScale, AxisScale)) -> Vec<[f32; 2]> {
+    graph_points(series, scales).into_iter()
+        .filter(|&[x, y]| contains(bounds.xbounds, x) && contains(bounds.ybounds, y))
+        .collect()
+}
+
+/// The series in graph space. Points that cannot be shown on a logarithmic axis are not finite.
+fn graph_points(series: &DataSeries, (xscale, yscale): (AxisScale, AxisScale)) -> Vec<[f32; 2]> {
+    series.points.iter()
+        .map(|&[x, y]| [xscale.forward(x), yscale.forward(y)])
+        .collect()
+}
+
+fn contains(bounds: Bounds, t: f32) -> bool {
+    bounds.start <= t && t <= bounds.end
+}
+
+/// Drops points outside of `xbounds`, like curves are only sampled inside them.
+/// Points next to a visible point are kept so the line reaches the edge of the bounds.
+/// Dropped points are replaced by NaN to break the line.
+fn cull_line(points: &[[f32; 2]], xbounds: Bounds) -> Vec<[f32; 2]> {
+    let inside = points.iter()
+        .map(|&[x, _]| contains(xbounds, x))
+        .collect::<Vec<_>>();
+
+    (0..points.len())
+        .map(|i| {
+            let near = inside[i]
+                || (i > 0 && inside[i - 1])
+                || (i + 1 < points.len() && inside[i + 1]);
+            if near { points[i] } else { [f32::NAN; 2] }
+        })
+        .collect()
+}
+
+/// Spawns an entity drawing a marker at each instance in its `Glyphs`,
+/// placed by the system that regenerates its owner.
+pub fn spawn_markers<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    meshes: &mut Assets<Mesh>,
+    shape: MarkerShape,
+    markers: Markers,
+    z: f32,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut entity = commands.spawn_bundle((
+        meshes.add(gen_marker_mesh(shape)),
+        Transform::from_xyz(0.0, 0.0, z),
+        GlobalTransform::default(),
+        Visibility::default(),
+        ComputedVisibility::default(),
+    ));
+    entity.insert(markers)
+        .insert(Glyphs::default())
+        .insert(Aabb::default());
+    entity
+}
+
+impl Markers {
+    /// A marker instance at each finite point in graph space.
+    pub fn instances(&self, points: &[[f32; 2]], window: &Window) -> Vec<GlyphInstance> {
+        let color = self.color.as_linear_rgba_f32();
+        let half_width = ndc_half_width(self.size, window);
+
+        points.iter()
+            .filter(|[x, y]| x.is_finite() && y.is_finite())
+            .map(|&position| GlyphInstance {
+                position,
+                // unrotated, and the marker mesh has no length to stretch
+                direction: [1.0, 0.0],
+                color,
+                half_width,
+            })
+            .collect()
+    }
+}
+
+/// One marker as a triangle fan around the origin, drawn as a glyph at each point.
+/// Its normals are the offsets of the corners in half marker sizes.
+pub fn gen_marker_mesh(shape: MarkerShape) -> Mesh {
+    let corners = marker_corners(shape);
+
+    let mut normals = vec![[0.0, 0.0, 0.0]];
+    normals.extend(corners.iter().map(|&[cx, cy]| [cx, cy, 0.0]));
+    let positions = vec![[0.0, 0.0, 0.0]; normals.len()];
+    let uvs = vec![[0.0, 0.0]; normals.len()];
+
+    // corners are counter clockwise
+    let count = corners.len() as u16;
+    let indices = (0..count)
+        .flat_map(|k| [0, 1 + k, 1 + (k + 1) % count])
+        .collect::<Vec<u16>>();
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U16(indices)));
+
+    mesh
+}
+
+/// Corners of the marker on the unit circle, counter clockwise.
+fn marker_corners(shape: MarkerShape) -> Vec<[f32; 2]> {
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, TAU};
+
+    let polygon = |count: usize, start: f32| (0..count)
+        .map(|k| {
+            let (s, c) = (start + TAU * k as f32 / count as f32).sin_cos();
+            [c, s]
+        })
+        .collect::<Vec<_>>();
+
+    match shape {
+        MarkerShape::Circle => polygon(CIRCLE_SEGMENTS, 0.0),
+        MarkerShape::Square => polygon(4, FRAC_PI_4),
+        MarkerShape::Diamond => polygon(4, 0.0),
+        MarkerShape::Triangle => polygon(3, FRAC_PI_2),
+    }
+}
@@ -0,0 +1,114 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, FilterMode, TextureDimension, TextureFormat};
+
+use crate::geometry::{domain_coloring, ComplexExpression, Definitions};
+use crate::project::{ComplexEntry, Project};
+use crate::scaling::{GraphingBounds, GraphingView};
+
+/// Logical pixels per texel of the domain colouring. Rows of functions of both x and y
+/// are each compiled, so the image is coarser than the window and smoothed when drawn.
+pub const PIXELS_PER_TEXEL: f32 = 4.0;
+
+/// A complex function drawn by domain colouring behind everything else, coloured over
+/// the graphing bounds so panning within them keeps the view filled.
+#[derive(Component, Clone, Debug)]
+pub struct DomainColoring {
+    pub entry: ComplexEntry,
+    /// The expression with the definitions expanded and the parameters substituted.
+    pub expression: ComplexExpression,
+}
+
+/// Spawns a quad textured with the domain colouring of the expression.
+/// The texture is drawn by `regenerate_domain_coloring_system`.
+pub fn spawn_domain_coloring(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    entry: &ComplexEntry,
+    expression: ComplexExpression,
+) {
+    commands.spawn_bundle(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::ONE))),
+        material: materials.add(StandardMaterial {
+            unlit: true,
+            // pixels where the function is undefined are transparent
+            alpha_mode: AlphaMode::Blend,
+            ..Default::default()
+        }),
+        transform: Transform::from_xyz(0.0, 0.0, -0.05),
+        ..Default::default()
+    })
+        .insert(DomainColoring {
+            entry: entry.clone(),
+            expression,
+        });
+}
+
+/// Colours the graphing bounds again when they or the function change.
+pub fn regenerate_domain_coloring_system(
+    graphing_bounds: Res<GraphingBounds>,
+    view: Res<GraphingView>,
+    window: Res<WindowDescriptor>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut plots: Query<(&DomainColoring, ChangeTrackers<DomainColoring>, &Handle<StandardMaterial>, &mut Transform)>,
+) {
+    let pixels_per_unit = window.width / (2.0 * view.scale);
+    let (xbounds, ybounds) = (graphing_bounds.xbounds, graphing_bounds.ybounds);
+    let width = ((xbounds.end - xbounds.start) * pixels_per_unit / PIXELS_PER_TEXEL).ceil().max(1.0) as usize;
+    let height = ((ybounds.end - ybounds.start) * pixels_per_unit / PIXELS_PER_TEXEL).ceil().max(1.0) as usize;
+
+    for (plot, tracker, material, mut transform) in plots.iter_mut() {
+        if !(graphing_bounds.is_changed() || tracker.is_changed()) {
+            continue;
+        }
+
+        let pixels = match domain_coloring(
+            &plot.expression, &graphing_bounds, (view.xscale, view.yscale), width, height, plot.entry.contours,
+        ) {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+
+        let mut image = Image::new(
+            Extent3d { width: width as u32, height: height as u32, depth_or_array_layers: 1 },
+            TextureDimension::D2,
+            pixels,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        image.sampler_descriptor.mag_filter = FilterMode::Linear;
+        image.sampler_descriptor.min_filter = FilterMode::Linear;
+
+        // a new image rather than replacing the old one's data, as the material's
+        // bind group only picks up a changed texture when the material changes
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color_texture = Some(images.add(image));
+        }
+
+        transform.translation.x = xbounds.centre();
+        transform.translation.y = ybounds.centre();
+        transform.scale = Vec3::new(xbounds.end - xbounds.start, ybounds.end - ybounds.start, 1.0);
+    }
+}
+
+/// Substitutes the project's parameters into the complex function again when they change.
+pub fn update_domain_coloring_parameters_system(
+    project: Res<Project>,
+    definitions: Res<Definitions>,
+    mut plots: Query<&mut DomainColoring>,
+) {
+    if !project.is_changed() {
+        return
+    }
+
+    for mut plot in plots.iter_mut() {
+        match project.parse_complex(&plot.entry, &definitions) {
+            Ok(expression) if expression != plot.expression => plot.expression = expression,
+            Ok(_) => (),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
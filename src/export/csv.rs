@@ -0,0 +1,82 @@
+use std::io::{self, Write};
+
+use crate::geometry::{eval_expression, Expression};
+use crate::geometry::{AxisScale, Bounds};
+
+/// Writes an x column followed by one column of samples for each curve.
+/// Samples are evenly spaced in graph space between the ends of `range`, inclusive.
+/// Values where a curve is undefined are left empty.
+pub fn write_samples(
+    mut writer: impl Write,
+    curves: &[(&str, Expression)],
+    range: Bounds,
+    samples: usize,
+    xscale: AxisScale,
+    delimiter: char,
+) -> io::Result<()> {
+    let start = xscale.forward(range.start);
+    let end = xscale.forward(range.end);
+    let step = if samples > 1 { (end - start) / (samples - 1) as f32 } else { 0.0 };
+    let xs = (0..samples)
+        .map(|i| xscale.inverse(start + step * i as f32))
+        .collect::<Vec<f32>>();
+
+    let columns = curves.iter()
+        .map(|(_, expression)| eval_expression(expression, &xs))
+        .collect::<Vec<Vec<f32>>>();
+
+    let mut header = vec![field("x", delimiter)];
+    header.extend(curves.iter().map(|(name, _)| field(name, delimiter)));
+    writeln!(writer, "{}", header.join(&delimiter.to_string()))?;
+
+    for (i, x) in xs.iter().enumerate() {
+        write!(writer, "{}", x)?;
+        for column in columns.iter() {
+            let y = column[i];
+            if y.is_finite() {
+                write!(writer, "{}{}", delimiter, y)?;
+            } else {
+                write!(writer, "{}", delimiter)?;
+            }
+        }
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+/// Quotes a field if it contains the delimiter, quotes or newlines.
+pub fn field(text: &str, delimiter: char) -> String {
+    if text.contains(|c| c == delimiter || c == '"' || c == '\n') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_series::{Column, DataSeries};
+
+    #[test]
+    fn samples_read_back_as_data() {
+        let curves = [
+            ("x*x", Expression::parse("x*x").unwrap()),
+            ("sqrt(x), real", Expression::parse("sqrt(x)").unwrap()),
+        ];
+        let mut text = Vec::new();
+        write_samples(&mut text, &curves, Bounds { start: -1.0, end: 1.0 }, 5, AxisScale::Linear, ',').unwrap();
+        let text = String::from_utf8(text).unwrap();
+
+        let x = Column::Name("x".to_string());
+        let squares = DataSeries::parse(&text, ',', &x, &Column::Name("x*x".to_string())).unwrap();
+        assert_eq!(squares.points, [[-1.0, 1.0], [-0.5, 0.25], [0.0, 0.0], [0.5, 0.25], [1.0, 1.0]]);
+
+        // the quoted header keeps its delimiter, and undefined values are empty
+        let roots = DataSeries::parse(&text, ',', &x, &Column::Name("sqrt(x), real".to_string())).unwrap();
+        assert_eq!(roots.name, "sqrt(x), real");
+        assert!(roots.points[..2].iter().all(|[_, y]| y.is_nan()));
+        assert_eq!(roots.points[2..], [[0.0, 0.0], [0.5, 0.5f32.sqrt()], [1.0, 1.0]]);
+    }
+}
@@ -0,0 +1,433 @@
+use bevy::prelude::*;
+use std::path::Path;
+
+use crate::cli::{check_range, Args};
+use crate::curve_material::CurveMaterial;
+use crate::domain_coloring::DomainColoring;
+use crate::heatmap::{Heatmap, COLOR_BAR_HEIGHT, COLOR_BAR_MARGIN, COLOR_BAR_WIDTH, CONTOUR_WIDTH};
+use crate::field::{glyph_color, Field, ARROW_HEAD_LENGTH};
+use crate::geometry::{self, defines_variable, Bounds, Expression, FieldKind, Grid, GraphingBounds, GraphingView, MidAxisInfo, StrokeStyle};
+use crate::grid::GridSettings;
+use crate::project::{FieldEntry, Project};
+use crate::trajectory::{trajectory_points, Trajectories};
+
+pub mod svg;
+pub mod png;
+pub mod raster;
+pub mod csv;
+
+/// File written by the export keybinding.
+pub const EXPORT_SVG_PATH: &str = "fge.svg";
+
+/// Everything visible in the graph, independent of the renderer.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub view: GraphingView,
+    /// Logical pixels
+    pub width: f32,
+    pub height: f32,
+    pub grid: GridSettings,
+    pub curves: Vec<SceneCurve>,
+    /// Fields with the parameters substituted.
+    pub fields: Vec<FieldEntry>,
+    /// Drawn behind everything else.
+    pub complex: Option<DomainColoring>,
+    pub heatmap: Option<Heatmap>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneCurve {
+    pub expression: Expression,
+    pub stroke: StrokeStyle,
+    pub material: CurveMaterial,
+}
+
+/// A heatmap sampled at each pixel of a scene, with its contours and colour bar.
+#[derive(Clone, Debug)]
+pub struct HeatmapLayers {
+    /// rgba8 covering the scene from the top left.
+    pub pixels: Vec<u8>,
+    pub contours: LineLayer,
+    pub contour_labels: Vec<Label>,
+    /// rgba8, `COLOR_BAR_WIDTH` by `COLOR_BAR_HEIGHT`.
+    pub color_bar: Vec<u8>,
+    /// Top left corner in pixels.
+    pub color_bar_position: [f32; 2],
+    pub color_bar_labels: Vec<Label>,
+}
+
+/// A set of straight lines sharing a colour and width.
+#[derive(Clone, Debug)]
+pub struct LineLayer {
+    /// Pairs of endpoints in pixels.
+    pub endpoints: Vec<[f32; 2]>,
+    pub color: Color,
+    pub width: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+#[derive(Clone, Debug)]
+pub struct Label {
+    /// Position of the baseline in pixels.
+    pub position: [f32; 2],
+    pub text: String,
+    pub anchor: Anchor,
+}
+
+pub const LABEL_FONT_SIZE: f32 = 12.0;
+
+/// The default project in a window small enough that only the main axes and
+/// their labels are drawn, compared against the files in `golden`.
+#[cfg(test)]
+fn golden_scene() -> Scene {
+    use crate::project::WindowSize;
+
+    let project = Project {
+        window: WindowSize { width: 20.0, height: 20.0 },
+        ..Default::default()
+    };
+    Scene::from_project(&project).unwrap()
+}
+
+impl Scene {
+    /// The scene shown at startup for a project.
+    pub fn from_project(project: &Project) -> Result<Self, String> {
+        let definitions = project.definitions()?;
+        let curves = project.curves.iter()
+            .filter(|curve| !defines_variable(&curve.expression))
+            .map(|curve| Ok(SceneCurve {
+                expression: project.parse_curve(curve, &definitions)?,
+                stroke: curve.stroke,
+                material: curve.material(),
+            }))
+            .collect::<Result<Vec<SceneCurve>, String>>()?;
+
+        let fields = project.fields.iter()
+            .map(|field| Ok(FieldEntry { kind: project.parse_field(field, &definitions)?, ..field.clone() }))
+            .collect::<Result<Vec<FieldEntry>, String>>()?;
+
+        let complex = project.complex.as_ref()
+            .map(|entry| Ok(DomainColoring { entry: entry.clone(), expression: project.parse_complex(entry, &definitions)? }))
+            .transpose()?;
+
+        let heatmap = project.heatmap.as_ref()
+            .map(|entry| Ok(Heatmap { entry: entry.clone(), expression: project.parse_heatmap(entry, &definitions)? }))
+            .transpose()?;
+
+        Ok(Self {
+            view: project.graphing_view(),
+            width: project.window.width,
+            height: project.window.height,
+            grid: project.grid.clone(),
+            curves,
+            fields,
+            complex,
+            heatmap,
+        })
+    }
+
+    pub fn visible_bounds(&self) -> GraphingBounds {
+        self.view.visible_bounds(self.width, self.height)
+    }
+
+    pub fn mid_axis_info(&self) -> MidAxisInfo {
+        geometry::mid_axis_info(&self.visible_bounds(), &self.view, self.grid.xticks, self.grid.yticks)
+    }
+
+    pub fn pixels_per_unit(&self) -> f32 {
+        self.width / (2.0 * self.view.scale)
+    }
+
+    /// Converts a point in graph space to pixels, with y downwards.
+    pub fn to_pixels(&self, [x, y]: [f32; 2]) -> [f32; 2] {
+        let bounds = self.visible_bounds();
+        let ppu = self.pixels_per_unit();
+        [(x - bounds.xbounds.start) * ppu, (bounds.ybounds.end - y) * ppu]
+    }
+
+    /// Minor, mid and main axis lines, in drawing order.
+    pub fn grid_layers(&self) -> Vec<LineLayer> {
+        let bounds = self.visible_bounds();
+        let info = self.mid_axis_info();
+        let (mid_color, min_color) = self.grid.faded_colors(&info, self.view.scale, self.pixels_per_unit());
+        let scales = (self.view.xscale, self.view.yscale);
+
+        let to_pixels = |endpoints: Vec<[f32; 2]>| endpoints.into_iter()
+            .map(|p| self.to_pixels(p))
+            .collect::<Vec<[f32; 2]>>();
+
+        vec![
+            LineLayer {
+                endpoints: to_pixels(geometry::axis_line_endpoints(info.calculate_min_axis_info(), &bounds, true)),
+                color: min_color,
+                width: self.grid.min_width,
+            },
+            LineLayer {
+                endpoints: to_pixels(geometry::axis_line_endpoints(info, &bounds, false)),
+                color: mid_color,
+                width: self.grid.mid_width,
+            },
+            LineLayer {
+                endpoints: to_pixels(geometry::main_axis_endpoints(bounds.xbounds, bounds.ybounds, scales)),
+                color: self.grid.main_color,
+                width: self.grid.main_width,
+            },
+        ]
+    }
+
+    /// The pieces of a curve where it is defined, in pixels.
+    pub fn curve_polylines(&self, curve: &SceneCurve) -> Vec<Vec<[f32; 2]>> {
+        let bounds = self.visible_bounds();
+        geometry::sample_polylines(&curve.expression, bounds.xbounds, (self.view.xscale, self.view.yscale))
+            .into_iter()
+            .map(|piece| piece.into_iter().map(|p| self.to_pixels(p)).collect())
+            .collect()
+    }
+
+    /// Lines drawing the glyphs of a field in graph space, grouped by colour.
+    pub fn field_segments(&self, field: &FieldEntry) -> Vec<(Color, Vec<[f32; 2]>)> {
+        let arrow = matches!(field.kind, FieldKind::Vector(..));
+        let head = ARROW_HEAD_LENGTH * field.width / 2.0 / self.pixels_per_unit();
+
+        // the field's expressions were checked when it was parsed
+        let glyphs = geometry::field_glyphs(&field.kind, &self.visible_bounds(), &self.mid_axis_info(), field.magnitude)
+            .unwrap_or_default();
+
+        let mut groups: Vec<(Color, Vec<[f32; 2]>)> = Vec::new();
+        for glyph in glyphs.iter() {
+            let color = glyph_color(field, glyph);
+            let segments = geometry::glyph_segments(glyph, arrow, head);
+            match groups.last_mut() {
+                Some((last, endpoints)) if *last == color => endpoints.extend(segments),
+                _ => groups.push((color, segments)),
+            }
+        }
+
+        groups
+    }
+
+    /// Domain colouring of the complex function, if there is one, as rgba8 pixels
+    /// covering the scene from the top left.
+    pub fn complex_image(&self) -> Option<(usize, usize, Vec<u8>)> {
+        let plot = self.complex.as_ref()?;
+        let width = self.width.round() as usize;
+        let height = self.height.round() as usize;
+        let scales = (self.view.xscale, self.view.yscale);
+
+        // the expression was checked when it was parsed
+        geometry::domain_coloring(&plot.expression, &self.visible_bounds(), scales, width, height, plot.entry.contours)
+            .ok()
+            .map(|pixels| (width, height, pixels))
+    }
+
+    /// The heatmap, if there is one, sampled at each pixel.
+    pub fn heatmap_layers(&self) -> Option<HeatmapLayers> {
+        let heatmap = self.heatmap.as_ref()?;
+        let entry = &heatmap.entry;
+        let width = self.width.round() as usize;
+        let height = self.height.round() as usize;
+        let scales = (self.view.xscale, self.view.yscale);
+
+        // the expression was checked when it was parsed
+        let grid = Grid::sample(&heatmap.expression, &self.visible_bounds(), scales, width, height).ok()?;
+        let range = entry.range(&grid);
+
+        let mut endpoints = Vec::new();
+        let mut contour_labels = Vec::new();
+        let centre = self.view.centre;
+        for &level in entry.levels.iter() {
+            let contour = grid.contour(level);
+            if let Some(point) = geometry::contour_label_position(&contour, centre) {
+                let [x, y] = self.to_pixels(point);
+                contour_labels.push(Label {
+                    position: [x + 2.0, y - 2.0],
+                    text: geometry::format_level(level),
+                    anchor: Anchor::Start,
+                });
+            }
+            endpoints.extend(contour.into_iter().map(|p| self.to_pixels(p)));
+        }
+
+        let strip = entry.colormap.strip(COLOR_BAR_HEIGHT as usize);
+        let color_bar = strip.chunks_exact(4)
+            .flat_map(|color| color.repeat(COLOR_BAR_WIDTH as usize))
+            .collect();
+        let [x, y] = [self.width - COLOR_BAR_MARGIN - COLOR_BAR_WIDTH, COLOR_BAR_MARGIN];
+        let color_bar_labels = entry.color_bar_ticks(range).into_iter()
+            .map(|(value, offset)| Label {
+                position: [x - 4.0, y + offset * COLOR_BAR_HEIGHT + LABEL_FONT_SIZE * 0.35],
+                text: geometry::format_level(value),
+                anchor: Anchor::End,
+            })
+            .collect();
+
+        Some(HeatmapLayers {
+            pixels: grid.colors(entry.colormap, range),
+            contours: LineLayer { endpoints, color: entry.contour_color, width: CONTOUR_WIDTH },
+            contour_labels,
+            color_bar,
+            color_bar_position: [x, y],
+            color_bar_labels,
+        })
+    }
+
+    /// The glyphs of a field as lines in pixels.
+    pub fn field_layers(&self, field: &FieldEntry) -> Vec<LineLayer> {
+        self.field_segments(field)
+            .into_iter()
+            .map(|(color, endpoints)| LineLayer {
+                endpoints: endpoints.into_iter().map(|p| self.to_pixels(p)).collect(),
+                color,
+                width: field.width,
+            })
+            .collect()
+    }
+
+    /// The solution curves through a field's initial conditions, in graph space,
+    /// separated by NaN.
+    pub fn trajectory_points(&self, field: &FieldEntry) -> Vec<[f32; 2]> {
+        trajectory_points(&field.kind, &field.trajectories, &self.visible_bounds(), (self.view.xscale, self.view.yscale))
+    }
+
+    /// The pieces of a field's solution curves, in pixels.
+    pub fn trajectory_polylines(&self, field: &FieldEntry) -> Vec<Vec<[f32; 2]>> {
+        geometry::curve_pieces(&self.trajectory_points(field))
+            .map(|piece| piece.iter().map(|&p| self.to_pixels(p)).collect())
+            .collect()
+    }
+
+    /// Labels of the mid axis lines, next to the main axes.
+    /// Labels are kept on screen when the main axes are not.
+    pub fn labels(&self) -> Vec<Label> {
+        let bounds = self.visible_bounds();
+        let info = self.mid_axis_info();
+        let mut labels = Vec::new();
+
+        let clamp = |n: f32, b: Bounds| n.clamp(b.start, b.end);
+        let x0 = info.xscale.forward(0.0);
+        let y0 = info.yscale.forward(0.0);
+        let xaxis_y = if y0.is_finite() { clamp(y0, bounds.ybounds) } else { bounds.ybounds.start };
+        let yaxis_x = if x0.is_finite() { clamp(x0, bounds.xbounds) } else { bounds.xbounds.start };
+
+        let xs = geometry::axis_line_positions(
+            info.xscale, bounds.xbounds, info.xseparation, info.xline_count, info.rounded_xcentre, false
+        );
+        for x in xs.into_iter().filter(|&x| x != x0 && bounds.xbounds.start < x && x < bounds.xbounds.end) {
+            let [px, py] = self.to_pixels([x, xaxis_y]);
+            labels.push(Label {
+                position: [px, (py + LABEL_FONT_SIZE + 2.0).min(self.height - 2.0)],
+                text: info.xticks.format(info.xscale.inverse(x), info.xseparation, info.xscale),
+                anchor: Anchor::Middle,
+            });
+        }
+
+        let ys = geometry::axis_line_positions(
+            info.yscale, bounds.ybounds, info.yseparation, info.yline_count, info.rounded_ycentre, false
+        );
+        for y in ys.into_iter().filter(|&y| y != y0 && bounds.ybounds.start < y && y < bounds.ybounds.end) {
+            let [px, py] = self.to_pixels([yaxis_x, y]);
+            let (x, anchor) = if px < 40.0 { (px + 4.0, Anchor::Start) } else { (px - 4.0, Anchor::End) };
+            labels.push(Label {
+                position: [x, py + LABEL_FONT_SIZE * 0.35],
+                text: info.yticks.format(info.yscale.inverse(y), info.yseparation, info.yscale),
+                anchor,
+            });
+        }
+
+        labels
+    }
+}
+
+/// Writes the svg, png and samples asked for on the command line.
+pub fn write_files(project: &Project, args: &Args) -> Result<(), String> {
+    let scene = Scene::from_project(project)?;
+    let error = |path: &Path, e: std::io::Error| format!("Error writing {}: {}", path.display(), e);
+
+    if let Some(path) = &args.svg {
+        svg::write_svg_file(&scene, path).map_err(|e| error(path, e))?;
+    }
+
+    if let Some(path) = &args.png {
+        png::write_png_file(&scene, path).map_err(|e| error(path, e))?;
+    }
+
+    if let Some((path, delimiter)) = &args.samples_file {
+        // the scene has no curves for variable definitions
+        let curves = project.curves.iter()
+            .map(|c| c.expression.as_str())
+            .filter(|expression| !defines_variable(expression))
+            .zip(scene.curves.iter().map(|c| c.expression.clone()))
+            .collect::<Vec<_>>();
+
+        if let Some(range) = args.sample_range {
+            check_range(range, scene.view.xscale)?;
+        }
+
+        // the visible bounds are in graph space
+        let range = args.sample_range.unwrap_or_else(|| {
+            let visible = scene.visible_bounds().xbounds;
+            Bounds {
+                start: scene.view.xscale.inverse(visible.start),
+                end: scene.view.xscale.inverse(visible.end),
+            }
+        });
+
+        std::fs::File::create(path)
+            .map(std::io::BufWriter::new)
+            .and_then(|file| csv::write_samples(file, &curves, range, args.sample_count, scene.view.xscale, *delimiter))
+            .map_err(|e| error(path, e))?;
+    }
+
+    Ok(())
+}
+
+/// Writes the current graph to `EXPORT_SVG_PATH` on ctrl+E.
+#[allow(clippy::too_many_arguments)]
+pub fn export_keybind_system(
+    keys: Res<Input<KeyCode>>,
+    view: Res<GraphingView>,
+    window_descriptor: Res<WindowDescriptor>,
+    grid: Res<GridSettings>,
+    curves: Query<(&Expression, &StrokeStyle, &Handle<CurveMaterial>, &Visibility)>,
+    fields: Query<(&Field, &Trajectories)>,
+    complex: Query<&DomainColoring>,
+    heatmaps: Query<&Heatmap>,
+    materials: Res<Assets<CurveMaterial>>,
+) {
+    let ctrl = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl);
+    if !(ctrl && keys.just_pressed(KeyCode::E)) {
+        return
+    }
+
+    let scene = Scene {
+        view: view.clone(),
+        width: window_descriptor.width,
+        height: window_descriptor.height,
+        grid: grid.clone(),
+        // variable definitions are hidden curves
+        curves: curves.iter()
+            .filter(|(_, _, _, visibility)| visibility.is_visible)
+            .map(|(expression, stroke, material, _)| SceneCurve {
+                expression: expression.clone(),
+                stroke: *stroke,
+                material: materials.get(material).copied().unwrap_or_default(),
+            })
+            .collect(),
+        fields: fields.iter()
+            .map(|(field, trajectories)| FieldEntry { kind: field.kind.clone(), ..field.to_entry(trajectories) })
+            .collect(),
+        complex: complex.iter().next().cloned(),
+        heatmap: heatmaps.iter().next().cloned(),
+    };
+
+    match svg::write_svg_file(&scene, EXPORT_SVG_PATH) {
+        Ok(()) => println!("Exported graph to {}", EXPORT_SVG_PATH),
+        Err(e) => println!("Error exporting graph: {}", e),
+    }
+}
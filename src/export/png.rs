@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use super::raster::{Canvas, CurveBrush};
+use super::Scene;
+use crate::gen_mesh::{self, StrokeStyle};
+use crate::heatmap::COLOR_BAR_WIDTH;
+use crate::trajectory::TRAJECTORY_WIDTH;
+
+/// Rasterises the domain colouring, heatmap, grid, fields and curves of the scene, without labels.
+pub fn render_scene(scene: &Scene) -> Canvas {
+    let width = scene.width.round() as usize;
+    let height = scene.height.round() as usize;
+    let mut canvas = Canvas::new(width, height);
+
+    let bounds = scene.visible_bounds();
+    let info = scene.mid_axis_info();
+    let scales = (scene.view.xscale, scene.view.yscale);
+    let pixels_per_unit = scene.pixels_per_unit();
+    let to_pixels = |p| scene.to_pixels(p);
+    let (mid_color, min_color) = scene.grid.faded_colors(&info, scene.view.scale, pixels_per_unit);
+
+    if let Some((width, _, pixels)) = scene.complex_image() {
+        canvas.draw_image([0, 0], width, &pixels);
+    }
+
+    let heatmap = scene.heatmap_layers();
+    if let Some(heatmap) = &heatmap {
+        canvas.draw_image([0, 0], width, &heatmap.pixels);
+    }
+
+    let grid = [
+        (gen_mesh::gen_min_axis(info.calculate_min_axis_info(), &bounds), min_color, scene.grid.min_width),
+        (gen_mesh::gen_mid_axis(info, &bounds), mid_color, scene.grid.mid_width),
+        (gen_mesh::gen_main_axis(bounds.xbounds, bounds.ybounds, scales), scene.grid.main_color, scene.grid.main_width),
+    ];
+
+    for (mesh, color, width) in grid {
+        canvas.draw_curve_mesh(&mesh, &CurveBrush {
+            to_pixels: &to_pixels,
+            pixels_per_unit,
+            color,
+            width,
+            dash: [0.0; 4],
+        });
+    }
+
+    if let Some(heatmap) = &heatmap {
+        let contours = &heatmap.contours;
+        canvas.draw_curve_mesh(&gen_mesh::line_quads(&contours.endpoints), &CurveBrush {
+            to_pixels: &|p| p,
+            pixels_per_unit: 1.0,
+            color: contours.color,
+            width: contours.width,
+            dash: [0.0; 4],
+        });
+    }
+
+    for field in scene.fields.iter() {
+        for (color, endpoints) in scene.field_segments(field) {
+            canvas.draw_curve_mesh(&gen_mesh::line_quads(&endpoints), &CurveBrush {
+                to_pixels: &to_pixels,
+                pixels_per_unit,
+                color,
+                width: field.width,
+                dash: [0.0; 4],
+            });
+        }
+
+        let mesh = gen_mesh::gen_polyline_mesh(&scene.trajectory_points(field), &StrokeStyle::default());
+        canvas.draw_curve_mesh(&mesh, &CurveBrush {
+            to_pixels: &to_pixels,
+            pixels_per_unit,
+            color: field.trajectory_color,
+            width: TRAJECTORY_WIDTH,
+            dash: [0.0; 4],
+        });
+    }
+
+    for curve in scene.curves.iter() {
+        let mesh = gen_mesh::gen_expr_mesh(&curve.expression, bounds.xbounds, scales, &curve.stroke);
+        let material = &curve.material;
+
+        canvas.draw_curve_mesh(&mesh, &CurveBrush {
+            to_pixels: &to_pixels,
+            pixels_per_unit,
+            color: material.color,
+            width: material.width,
+            dash: material.line_style.dash_pattern().map(|l| l * material.width),
+        });
+    }
+
+    if let Some(heatmap) = &heatmap {
+        let [x, y] = heatmap.color_bar_position;
+        canvas.draw_image([x as usize, y as usize], COLOR_BAR_WIDTH as usize, &heatmap.color_bar);
+    }
+
+    canvas
+}
+
+pub fn write_png_file(scene: &Scene, path: impl AsRef<Path>) -> io::Result<()> {
+    let canvas = render_scene(scene);
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, canvas.width as u32, canvas.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::from)?;
+    writer.write_image_data(&canvas.to_rgba8()).map_err(io::Error::from)?;
+    Ok(())
+}
+
+/// Encodes rgba8 pixels as a png in memory.
+pub fn encode_png(width: usize, height: usize, rgba: &[u8]) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::from)?;
+    writer.write_image_data(rgba).map_err(io::Error::from)?;
+    writer.finish().map_err(io::Error::from)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::golden_scene;
+
+    #[test]
+    fn scene_matches_golden_png() {
+        let canvas = render_scene(&golden_scene());
+
+        let decoder = png::Decoder::new(&include_bytes!("golden/axes.png")[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut golden = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut golden).unwrap();
+
+        assert_eq!([canvas.width, canvas.height], [20, 20]);
+        assert_eq!(canvas.to_rgba8(), golden);
+    }
+}
@@ -0,0 +1,198 @@
+use bevy::prelude::Color;
+use bevy::render::mesh::{Indices, Mesh, VertexAttributeValues};
+use bevy::render::render_resource::PrimitiveTopology;
+
+/// Samples per pixel along each axis, for antialiasing.
+const SUBSAMPLES: usize = 4;
+
+/// Software rasteriser for meshes drawn with `CurveMaterial`.
+/// Pixels are premultiplied rgba.
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<[f32; 4]>,
+}
+
+/// How to place a mesh generated for `CurveMaterial` on the canvas.
+pub struct CurveBrush<'a> {
+    /// Converts graph space to pixels.
+    pub to_pixels: &'a dyn Fn([f32; 2]) -> [f32; 2],
+    /// Pixels per unit of arc length, for dash patterns.
+    pub pixels_per_unit: f32,
+    pub color: Color,
+    /// Width of the curve in pixels.
+    pub width: f32,
+    /// Alternating on and off lengths in pixels, all zero for solid lines.
+    pub dash: [f32; 4],
+}
+
+struct Vertex {
+    position: [f32; 2],
+    arc_length: f32,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; width * height],
+        }
+    }
+
+    /// Draws an image of straight alpha rgba8 pixels `width` wide over the canvas,
+    /// with its top left corner at `[x, y]`.
+    pub fn draw_image(&mut self, [x, y]: [usize; 2], width: usize, rgba: &[u8]) {
+        for (k, color) in rgba.chunks_exact(4).enumerate() {
+            let (px, py) = (x + k % width, y + k / width);
+            if px >= self.width || py >= self.height {
+                continue;
+            }
+
+            let pixel = &mut self.pixels[py * self.width + px];
+            let [r, g, b, a] = [0, 1, 2, 3].map(|i| color[i] as f32 / 255.0);
+            let keep = 1.0 - a;
+            *pixel = [
+                r * a + pixel[0] * keep,
+                g * a + pixel[1] * keep,
+                b * a + pixel[2] * keep,
+                a + pixel[3] * keep,
+            ];
+        }
+    }
+
+    /// Draws a mesh with each vertex offset by its normal times half the curve width.
+    /// Overlapping triangles of the same mesh are only drawn once.
+    pub fn draw_curve_mesh(&mut self, mesh: &Mesh, brush: &CurveBrush) {
+        let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(p)) => p,
+            _ => return,
+        };
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(n)) => n,
+            _ => return,
+        };
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uv)) => uv.clone(),
+            _ => vec![[0.0; 2]; positions.len()],
+        };
+
+        let half_width = brush.width / 2.0;
+        let vertices = positions.iter().zip(normals).zip(uvs)
+            .map(|((p, n), uv)| {
+                let [x, y] = (brush.to_pixels)([p[0], p[1]]);
+                // pixel y is downwards
+                Vertex {
+                    position: [x + n[0] * half_width, y - n[1] * half_width],
+                    arc_length: uv[0] * brush.pixels_per_unit,
+                }
+            })
+            .collect::<Vec<Vertex>>();
+
+        let indices = match mesh.indices() {
+            Some(Indices::U16(i)) => i.iter().map(|&i| i as usize).collect(),
+            Some(Indices::U32(i)) => i.iter().map(|&i| i as usize).collect(),
+            None => (0..vertices.len()).collect::<Vec<usize>>(),
+        };
+
+        let triangles = match mesh.primitive_topology() {
+            PrimitiveTopology::TriangleList => indices.chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect::<Vec<[usize; 3]>>(),
+            PrimitiveTopology::TriangleStrip => indices.windows(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect(),
+            _ => return,
+        };
+
+        let mut coverage = vec![0u16; self.width * self.height];
+        for [a, b, c] in triangles {
+            self.cover_triangle([&vertices[a], &vertices[b], &vertices[c]], &brush.dash, &mut coverage);
+        }
+
+        let [r, g, b, a] = brush.color.as_rgba_f32();
+        for (pixel, mask) in self.pixels.iter_mut().zip(coverage) {
+            if mask == 0 {
+                continue;
+            }
+
+            let alpha = a * mask.count_ones() as f32 / (SUBSAMPLES * SUBSAMPLES) as f32;
+            let keep = 1.0 - alpha;
+            *pixel = [
+                r * alpha + pixel[0] * keep,
+                g * alpha + pixel[1] * keep,
+                b * alpha + pixel[2] * keep,
+                alpha + pixel[3] * keep,
+            ];
+        }
+    }
+
+    /// Sets a bit in `coverage` for each subsample inside the triangle and a dash.
+    fn cover_triangle(&self, [a, b, c]: [&Vertex; 3], dash: &[f32; 4], coverage: &mut [u16]) {
+        let [ax, ay] = a.position;
+        let [bx, by] = b.position;
+        let [cx, cy] = c.position;
+
+        let area = (bx - ax) * (cy - ay) - (by - ay) * (cx - ax);
+        if area.abs() < 1e-6 {
+            return;
+        }
+
+        let x_start = ax.min(bx).min(cx).floor().max(0.0) as usize;
+        let y_start = ay.min(by).min(cy).floor().max(0.0) as usize;
+        let x_end = (ax.max(bx).max(cx).ceil().max(0.0) as usize).min(self.width);
+        let y_end = (ay.max(by).max(cy).ceil().max(0.0) as usize).min(self.height);
+
+        for py in y_start..y_end {
+            for px in x_start..x_end {
+                let mut mask = 0u16;
+
+                for s in 0..(SUBSAMPLES * SUBSAMPLES) {
+                    let x = px as f32 + ((s % SUBSAMPLES) as f32 + 0.5) / SUBSAMPLES as f32;
+                    let y = py as f32 + ((s / SUBSAMPLES) as f32 + 0.5) / SUBSAMPLES as f32;
+
+                    // barycentric weights, all positive inside the triangle for either winding
+                    let wa = ((bx - x) * (cy - y) - (by - y) * (cx - x)) / area;
+                    let wb = ((cx - x) * (ay - y) - (cy - y) * (ax - x)) / area;
+                    let wc = 1.0 - wa - wb;
+                    if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                        continue;
+                    }
+
+                    let arc_length = wa * a.arc_length + wb * b.arc_length + wc * c.arc_length;
+                    if in_dash(arc_length, dash) {
+                        mask |= 1 << s;
+                    }
+                }
+
+                coverage[py * self.width + px] |= mask;
+            }
+        }
+    }
+
+    /// Straight alpha rgba with 8 bits per channel.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        self.pixels.iter()
+            .flat_map(|&[r, g, b, a]| {
+                if a > 0.0 {
+                    [byte(r / a), byte(g / a), byte(b / a), byte(a)]
+                } else {
+                    [0; 4]
+                }
+            })
+            .collect()
+    }
+}
+
+/// Matches `in_dash` in curve_shader.wgsl.
+fn in_dash(arc_length: f32, dash: &[f32; 4]) -> bool {
+    let period = dash.iter().sum::<f32>();
+    if period <= 0.0 {
+        return true;
+    }
+
+    let p = arc_length - (arc_length / period).floor() * period;
+    p < dash[0] || (p >= dash[0] + dash[1] && p < dash[0] + dash[1] + dash[2])
+}
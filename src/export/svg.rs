@@ -0,0 +1,244 @@
+use bevy::prelude::Color;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+use super::png::encode_png;
+use super::{Anchor, Label, LineLayer, Scene, LABEL_FONT_SIZE};
+use crate::geometry::{CapStyle, JoinStyle, StrokeStyle};
+use crate::heatmap::{COLOR_BAR_HEIGHT, COLOR_BAR_WIDTH};
+use crate::trajectory::TRAJECTORY_WIDTH;
+
+pub fn write_svg_file(scene: &Scene, path: impl AsRef<Path>) -> io::Result<()> {
+    std::fs::write(path, scene_to_svg(scene))
+}
+
+pub fn scene_to_svg(scene: &Scene) -> String {
+    let mut svg = String::new();
+
+    // writing to a String cannot fail
+    let _ = write_scene(scene, &mut svg);
+    svg
+}
+
+fn write_scene(scene: &Scene, svg: &mut String) -> std::fmt::Result {
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = scene.width,
+        h = scene.height,
+    )?;
+
+    if let Some((width, height, pixels)) = scene.complex_image() {
+        write_image([0.0, 0.0], [width, height], &pixels, svg)?;
+    }
+
+    let heatmap = scene.heatmap_layers();
+    if let Some(heatmap) = &heatmap {
+        let width = scene.width.round() as usize;
+        let height = scene.height.round() as usize;
+        write_image([0.0, 0.0], [width, height], &heatmap.pixels, svg)?;
+    }
+
+    for layer in scene.grid_layers() {
+        write_lines(&layer, "", svg)?;
+    }
+
+    if let Some(heatmap) = &heatmap {
+        write_lines(&heatmap.contours, r#"stroke-linecap="round" "#, svg)?;
+    }
+
+    for field in scene.fields.iter() {
+        for layer in scene.field_layers(field) {
+            write_lines(&layer, r#"stroke-linecap="round" "#, svg)?;
+        }
+
+        for polyline in scene.trajectory_polylines(field) {
+            let points = polyline.iter()
+                .map(|[x, y]| format!("{:.2},{:.2}", x, y))
+                .collect::<Vec<String>>();
+
+            writeln!(
+                svg,
+                r#"<polyline points="{}" fill="none" {}stroke-width="{}" {}/>"#,
+                points.join(" "),
+                stroke_color(field.trajectory_color),
+                TRAJECTORY_WIDTH,
+                stroke_style(&StrokeStyle::default()),
+            )?;
+        }
+    }
+
+    for curve in scene.curves.iter() {
+        let material = &curve.material;
+        let dash = material.line_style.dash_pattern();
+        let dash_array = if dash.iter().any(|&l| l > 0.0) {
+            let lengths = dash.iter()
+                .map(|l| (l * material.width).to_string())
+                .collect::<Vec<String>>();
+            format!(r#"stroke-dasharray="{}" "#, lengths.join(" "))
+        } else {
+            String::new()
+        };
+
+        for polyline in scene.curve_polylines(curve) {
+            let points = polyline.iter()
+                .map(|[x, y]| format!("{:.2},{:.2}", x, y))
+                .collect::<Vec<String>>();
+
+            writeln!(
+                svg,
+                r#"<polyline points="{}" fill="none" {}stroke-width="{}" {}{}/>"#,
+                points.join(" "),
+                stroke_color(material.color),
+                material.width,
+                stroke_style(&curve.stroke),
+                dash_array,
+            )?;
+        }
+    }
+
+    for label in scene.labels() {
+        write_label(&label, scene.grid.main_color, svg)?;
+    }
+
+    if let Some(heatmap) = &heatmap {
+        for label in heatmap.contour_labels.iter() {
+            write_label(label, heatmap.contours.color, svg)?;
+        }
+
+        let size = [COLOR_BAR_WIDTH as usize, COLOR_BAR_HEIGHT as usize];
+        write_image(heatmap.color_bar_position, size, &heatmap.color_bar, svg)?;
+        for label in heatmap.color_bar_labels.iter() {
+            write_label(label, scene.grid.main_color, svg)?;
+        }
+    }
+
+    writeln!(svg, "</svg>")
+}
+
+fn write_label(label: &Label, color: Color, svg: &mut String) -> std::fmt::Result {
+    let anchor = match label.anchor {
+        Anchor::Start => "start",
+        Anchor::Middle => "middle",
+        Anchor::End => "end",
+    };
+
+    writeln!(
+        svg,
+        r#"<text x="{:.2}" y="{:.2}" font-family="Lato, sans-serif" font-size="{}" text-anchor="{}" {}>{}</text>"#,
+        label.position[0],
+        label.position[1],
+        LABEL_FONT_SIZE,
+        anchor,
+        fill_color(color),
+        escape(&label.text),
+    )
+}
+
+/// Embeds rgba8 pixels as a png, with its top left corner at `position`.
+fn write_image(position: [f32; 2], [width, height]: [usize; 2], rgba: &[u8], svg: &mut String) -> std::fmt::Result {
+    // encoding to memory only fails on a bad image size
+    match encode_png(width, height, rgba) {
+        Ok(data) => writeln!(
+            svg,
+            r#"<image x="{}" y="{}" width="{}" height="{}" href="data:image/png;base64,{}"/>"#,
+            position[0],
+            position[1],
+            width,
+            height,
+            base64(&data),
+        ),
+        Err(_) => Ok(()),
+    }
+}
+
+fn write_lines(layer: &LineLayer, style: &str, svg: &mut String) -> std::fmt::Result {
+    if layer.endpoints.is_empty() || layer.color.a() <= 0.0 {
+        return Ok(());
+    }
+
+    let mut d = String::new();
+    for line in layer.endpoints.chunks_exact(2) {
+        write!(d, "M{:.2} {:.2}L{:.2} {:.2}", line[0][0], line[0][1], line[1][0], line[1][1])?;
+    }
+
+    writeln!(
+        svg,
+        r#"<path d="{}" fill="none" {}{}stroke-width="{}"/>"#,
+        d,
+        stroke_color(layer.color),
+        style,
+        layer.width,
+    )
+}
+
+fn hex(color: Color) -> (String, f32) {
+    let [r, g, b, a] = color.as_rgba_f32();
+    let byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    (format!("#{:02x}{:02x}{:02x}", byte(r), byte(g), byte(b)), a)
+}
+
+fn stroke_color(color: Color) -> String {
+    let (hex, a) = hex(color);
+    format!(r#"stroke="{}" stroke-opacity="{}" "#, hex, a)
+}
+
+fn fill_color(color: Color) -> String {
+    let (hex, a) = hex(color);
+    format!(r#"fill="{}" fill-opacity="{}""#, hex, a)
+}
+
+fn stroke_style(stroke: &StrokeStyle) -> String {
+    let cap = match stroke.cap {
+        CapStyle::Butt => "butt",
+        CapStyle::Round => "round",
+    };
+
+    let join = match stroke.join {
+        // svg miter joins always fall back to bevel joins
+        JoinStyle::Miter { limit, .. } => {
+            format!(r#"stroke-linejoin="miter" stroke-miterlimit="{}""#, limit)
+        }
+        JoinStyle::Bevel => r#"stroke-linejoin="bevel""#.to_string(),
+        JoinStyle::Round => r#"stroke-linejoin="round""#.to_string(),
+    };
+
+    format!(r#"stroke-linecap="{}" {} "#, cap, join)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::golden_scene;
+
+    #[test]
+    fn scene_matches_golden_svg() {
+        assert_eq!(scene_to_svg(&golden_scene()), include_str!("golden/axes.svg"));
+    }
+}
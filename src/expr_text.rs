@@ -0,0 +1,139 @@
+use std::ops::Range;
+
+/// Names reserved for constants, which are never free.
+pub const CONSTANTS: [&str; 2] = ["pi", "e"];
+
+/// The variable expressions are functions of.
+pub const VARIABLE: &str = "x";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Token<'a> {
+    Number(&'a str),
+    /// A name, and whether it is called as a function.
+    Identifier(&'a str, bool),
+    Symbol(char),
+}
+
+/// Splits an expression into tokens, with their byte ranges. Whitespace is skipped.
+pub fn tokens(text: &str) -> Vec<(Range<usize>, Token)> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let take_while = |mut i: usize, f: &dyn Fn(u8) -> bool| {
+        while i < bytes.len() && f(bytes[i]) {
+            i += 1;
+        }
+        i
+    };
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == b'.' && bytes.get(i + 1).map_or(false, u8::is_ascii_digit)) {
+            i = take_while(i, &|b| b.is_ascii_digit() || b == b'.');
+
+            // exponent, so the e in 1e5 is not read as a name
+            if matches!(bytes.get(i), Some(b'e') | Some(b'E')) {
+                let digits = match bytes.get(i + 1) {
+                    Some(b'+') | Some(b'-') => i + 2,
+                    _ => i + 1,
+                };
+                if bytes.get(digits).map_or(false, u8::is_ascii_digit) {
+                    i = take_while(digits, &|b| b.is_ascii_digit());
+                }
+            }
+            tokens.push((start..i, Token::Number(&text[start..i])));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            i = take_while(i, &|b| b.is_ascii_alphanumeric() || b == b'_');
+            let next = take_while(i, &|b| b.is_ascii_whitespace());
+            let called = bytes.get(next) == Some(&b'(');
+            tokens.push((start..i, Token::Identifier(&text[start..i], called)));
+        } else {
+            // keep multibyte characters whole
+            let ch = text[i..].chars().next().unwrap();
+            i += ch.len_utf8();
+            tokens.push((start..i, Token::Symbol(ch)));
+        }
+    }
+
+    tokens
+}
+
+/// Writes symbols mathjit doesn't read in ascii: π as pi, multiplied by anything
+/// directly before it, and ≤ and ≥ as <= and >=.
+pub fn expand_symbols(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for (range, token) in tokens(text) {
+        let replacement = match token {
+            Token::Symbol('π') => {
+                let before = text[..range.start].trim_end();
+                let product = before.ends_with(|c: char| c.is_ascii_alphanumeric() || c == ')' || c == '_');
+                if product { "*pi" } else { "pi" }
+            }
+            Token::Symbol('≤') => "<=",
+            Token::Symbol('≥') => ">=",
+            _ => continue,
+        };
+        out.push_str(&text[last..range.start]);
+        out.push_str(replacement);
+        last = range.end;
+    }
+
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Names that are not functions, constants or the variable, in order of first use.
+pub fn free_identifiers(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for (_, token) in tokens(text) {
+        if let Token::Identifier(name, false) = token {
+            if name != VARIABLE && !CONSTANTS.contains(&name) && !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+
+    names
+}
+
+/// Replaces each use of the given names, other than as a function, with its value.
+/// mathjit compiles functions of x alone, so parameters are substituted before parsing.
+pub fn substitute(text: &str, values: &[(&str, f64)]) -> String {
+    let values = values.iter()
+        .map(|&(name, value)| (name, format_value(value)))
+        .collect::<Vec<_>>();
+    replace(text, &values)
+}
+
+/// Replaces each use of the given names, other than as a function, with the given text.
+pub fn replace(text: &str, replacements: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for (range, token) in tokens(text) {
+        if let Token::Identifier(name, false) = token {
+            if let Some((_, replacement)) = replacements.iter().find(|(n, _)| *n == name) {
+                out.push_str(&text[last..range.start]);
+                out.push_str(replacement);
+                last = range.end;
+            }
+        }
+    }
+
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Formats a value to be placed in an expression, in brackets so it binds tightly.
+pub fn format_value(value: f64) -> String {
+    // mathjit evaluates in f32, so more digits would be lost
+    format!("({})", value as f32)
+}
@@ -0,0 +1,451 @@
+use bevy::core_pipeline::Transparent3d;
+use bevy::ecs::system::lifetimeless::{Read, SQuery, SRes};
+use bevy::ecs::system::SystemParamItem;
+use bevy::pbr::{MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup};
+use bevy::prelude::*;
+use bevy::render::mesh::{GpuBufferInfo, Indices};
+use bevy::render::primitives::Aabb;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_phase::{
+    AddRenderCommand, DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase,
+    SetItemPipeline, TrackedRenderPass,
+};
+use bevy::render::render_resource::*;
+use bevy::render::renderer::RenderDevice;
+use bevy::render::view::{ComputedVisibility, ExtractedView, Msaa};
+use bevy::render::{RenderApp, RenderStage};
+use bevy::utils::HashMap;
+use bytemuck::{Pod, Zeroable};
+
+use crate::axis_text::MidAxisInfo;
+use crate::curve_material::CurveMaterial;
+use crate::geometry::{field_glyphs, Definitions, FieldKind, Glyph, MagnitudeStyle};
+use crate::project::{FieldEntry, Project};
+use crate::scaling::GraphingBounds;
+use crate::trajectory::{spawn_trajectory_mesh, Trajectories};
+
+/// Length of an arrow head, in half line widths.
+pub const ARROW_HEAD_LENGTH: f32 = 8.0;
+
+/// Half the width of an arrow head, in half line widths.
+const ARROW_HEAD_WIDTH: f32 = 4.0;
+
+/// Brightness of the smallest glyphs of a field coloured by magnitude.
+const MIN_BRIGHTNESS: f32 = 0.25;
+
+/// A slope or vector field, drawn with one instance of its glyph mesh per grid point.
+#[derive(Component, Clone, Debug)]
+pub struct Field {
+    /// The field as written, which may use parameters and definitions.
+    /// Its initial conditions are kept in the entity's `Trajectories` instead.
+    pub entry: FieldEntry,
+    /// The field with the definitions expanded and the parameters substituted.
+    pub kind: FieldKind,
+}
+
+/// Instances of an entity's glyph mesh within the current `GraphingBounds`: the arrows
+/// or slopes of a field, or the markers of a data series or sequence.
+/// Rebuilt by the system regenerating their owner, such as `regenerate_fields_system`.
+#[derive(Component, Clone, Debug, Default)]
+pub struct Glyphs(pub Vec<GlyphInstance>);
+
+/// Per instance vertex data, matching locations 3 to 5 of `field_shader.wgsl`.
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct GlyphInstance {
+    /// Centre of the glyph in graph space.
+    pub position: [f32; 2],
+    /// Tail to tip of the glyph in graph space.
+    pub direction: [f32; 2],
+    /// Linear rgba
+    pub color: [f32; 4],
+    /// Half the line width in normalized device coordinates, which differs
+    /// in x and y unless the window is square.
+    pub half_width: [f32; 2],
+}
+
+impl Field {
+    /// The field as written, with the given initial conditions.
+    pub fn to_entry(&self, trajectories: &Trajectories) -> FieldEntry {
+        FieldEntry {
+            trajectories: trajectories.0.clone(),
+            ..self.entry.clone()
+        }
+    }
+}
+
+/// Spawns the field, and an entity for the solution curves through its initial conditions.
+pub fn spawn_field(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<CurveMaterial>,
+    entry: &FieldEntry,
+    kind: FieldKind,
+    bounds: &GraphingBounds,
+) -> Entity {
+    let arrow = matches!(kind, FieldKind::Vector(..));
+
+    // glyphs are generated by `regenerate_fields_system` once the field is added
+    let field = commands.spawn_bundle((
+        meshes.add(glyph_mesh(arrow)),
+        Transform::from_xyz(0.0, 0.0, 0.05),
+        GlobalTransform::default(),
+        Visibility::default(),
+        ComputedVisibility::default(),
+    ))
+        .insert(Field {
+            entry: FieldEntry { trajectories: Vec::new(), ..entry.clone() },
+            kind,
+        })
+        .insert(Glyphs::default())
+        .insert(Trajectories(entry.trajectories.clone()))
+        .insert(bounds_aabb(bounds))
+        .id();
+
+    spawn_trajectory_mesh(commands, meshes, materials, field, entry.trajectory_color);
+    field
+}
+
+/// A glyph one unit long, along x from -0.5 to 0.5. It is scaled and rotated for each
+/// instance in the shader. Normals are offsets in half line widths, along and across the glyph.
+fn glyph_mesh(arrow: bool) -> Mesh {
+    let mut positions = vec![[-0.5, 0.0, 0.0], [-0.5, 0.0, 0.0], [0.5, 0.0, 0.0], [0.5, 0.0, 0.0]];
+    let mut normals = vec![[0.0, 1.0, 0.0], [0.0, -1.0, 0.0], [0.0, 1.0, 0.0], [0.0, -1.0, 0.0]];
+    let mut indices = vec![0u16, 1, 2, 1, 3, 2];
+
+    if arrow {
+        // stop the shaft at the base of the head, so the tip is sharp
+        normals[2][0] = -ARROW_HEAD_LENGTH;
+        normals[3][0] = -ARROW_HEAD_LENGTH;
+
+        positions.extend_from_slice(&[[0.5, 0.0, 0.0]; 3]);
+        normals.extend_from_slice(&[
+            [0.0, 0.0, 0.0],
+            [-ARROW_HEAD_LENGTH, ARROW_HEAD_WIDTH, 0.0],
+            [-ARROW_HEAD_LENGTH, -ARROW_HEAD_WIDTH, 0.0],
+        ]);
+        indices.extend_from_slice(&[4, 5, 6]);
+    }
+
+    let uvs = vec![[0.0, 0.0]; positions.len()];
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U16(indices)));
+
+    mesh
+}
+
+/// Glyphs only cover the graphing bounds, but move around within the one mesh,
+/// so the bounds are used for culling instead of the mesh.
+pub fn bounds_aabb(bounds: &GraphingBounds) -> Aabb {
+    Aabb::from_min_max(
+        Vec3::new(bounds.xbounds.start, bounds.ybounds.start, 0.0),
+        Vec3::new(bounds.xbounds.end, bounds.ybounds.end, 0.0),
+    )
+}
+
+/// Colour of a glyph, darker the smaller its magnitude when coloured by magnitude.
+pub fn glyph_color(entry: &FieldEntry, glyph: &Glyph) -> Color {
+    match (&entry.kind, entry.magnitude) {
+        (FieldKind::Vector(..), MagnitudeStyle::Color) => {
+            let t = MIN_BRIGHTNESS + (1.0 - MIN_BRIGHTNESS) * glyph.magnitude;
+            let color = entry.color;
+            Color::rgba(color.r() * t, color.g() * t, color.b() * t, color.a())
+        }
+        _ => entry.color,
+    }
+}
+
+/// Half of `width` logical pixels in normalized device coordinates.
+pub fn ndc_half_width(width: f32, window: &Window) -> [f32; 2] {
+    let physical = width * window.scale_factor() as f32;
+    [physical / window.physical_width() as f32, physical / window.physical_height() as f32]
+}
+
+/// Keeps the width of glyphs constant in pixels when the window is resized.
+/// Glyphs are only written to if their width changed, so they aren't marked as changed every frame.
+pub fn resize_glyphs(glyphs: &mut Mut<Glyphs>, half_width: [f32; 2]) {
+    if glyphs.0.first().map_or(false, |g| g.half_width != half_width) {
+        for glyph in glyphs.0.iter_mut() {
+            glyph.half_width = half_width;
+        }
+    }
+}
+
+/// Regenerates glyphs when the bounds or the field change, and keeps their
+/// line width constant in pixels when the window is resized.
+pub fn regenerate_fields_system(
+    graphing_bounds: Res<GraphingBounds>,
+    mid_axis_info: Res<MidAxisInfo>,
+    windows: Res<Windows>,
+    mut fields: Query<(&Field, ChangeTrackers<Field>, &mut Glyphs, &mut Aabb)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    for (field, tracker, mut glyphs, mut aabb) in fields.iter_mut() {
+        let half_width = ndc_half_width(field.entry.width, window);
+
+        if graphing_bounds.is_changed() || tracker.is_changed() {
+            let generated = field_glyphs(&field.kind, &graphing_bounds, &mid_axis_info, field.entry.magnitude);
+            match generated {
+                Ok(generated) => {
+                    glyphs.0 = generated.iter()
+                        .map(|glyph| GlyphInstance {
+                            position: glyph.position,
+                            direction: glyph.direction,
+                            color: glyph_color(&field.entry, glyph).as_linear_rgba_f32(),
+                            half_width,
+                        })
+                        .collect();
+                }
+                Err(e) => println!("{}", e),
+            }
+            *aabb = bounds_aabb(&graphing_bounds);
+        } else {
+            resize_glyphs(&mut glyphs, half_width);
+        }
+    }
+}
+
+/// Substitutes the project's parameters into fields again when they change,
+/// which regenerates their glyphs and solution curves.
+pub fn update_field_parameters_system(
+    project: Res<Project>,
+    definitions: Res<Definitions>,
+    mut fields: Query<&mut Field>,
+) {
+    if !project.is_changed() {
+        return
+    }
+
+    for mut field in fields.iter_mut() {
+        match project.parse_field(&field.entry, &definitions) {
+            // only touch fields that changed, as changed fields are regenerated
+            Ok(kind) if kind != field.kind => field.kind = kind,
+            Ok(_) => (),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Draws `Glyphs` with instancing, so each field or set of markers is a single
+/// draw call however many glyphs it has.
+pub struct FieldPlugin;
+
+impl Plugin for FieldPlugin {
+    fn build(&self, app: &mut App) {
+        app.sub_app_mut(RenderApp)
+            .add_render_command::<Transparent3d, DrawField>()
+            .init_resource::<FieldPipeline>()
+            .init_resource::<SpecializedPipelines<FieldPipeline>>()
+            .init_resource::<FieldBuffers>()
+            .add_system_to_stage(RenderStage::Extract, extract_glyphs)
+            .add_system_to_stage(RenderStage::Prepare, prepare_field_buffers)
+            .add_system_to_stage(RenderStage::Queue, queue_fields);
+    }
+}
+
+/// Glyphs copied to the render world each frame. Only glyphs that changed are copied,
+/// along with every entity that still has glyphs, so buffers of despawned entities are dropped.
+struct ExtractedGlyphs {
+    entities: Vec<Entity>,
+    changed: Vec<(Entity, Vec<GlyphInstance>)>,
+}
+
+fn extract_glyphs(
+    mut commands: Commands,
+    all: Query<Entity, With<Glyphs>>,
+    changed: Query<(Entity, &Glyphs), Changed<Glyphs>>,
+) {
+    commands.insert_resource(ExtractedGlyphs {
+        entities: all.iter().collect(),
+        changed: changed.iter()
+            .map(|(entity, glyphs)| (entity, glyphs.0.clone()))
+            .collect(),
+    });
+}
+
+pub struct FieldBuffer {
+    buffer: Buffer,
+    length: usize,
+}
+
+/// Instance buffers of the entities with glyphs, kept in the render world across frames
+/// and only uploaded again when the glyphs change.
+#[derive(Default)]
+pub struct FieldBuffers(HashMap<Entity, FieldBuffer>);
+
+fn prepare_field_buffers(
+    mut extracted: ResMut<ExtractedGlyphs>,
+    mut buffers: ResMut<FieldBuffers>,
+    render_device: Res<RenderDevice>,
+) {
+    for (entity, glyphs) in extracted.changed.drain(..) {
+        if glyphs.is_empty() {
+            buffers.0.remove(&entity);
+            continue;
+        }
+
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("field glyph buffer"),
+            contents: bytemuck::cast_slice(glyphs.as_slice()),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        });
+        buffers.0.insert(entity, FieldBuffer {
+            buffer,
+            length: glyphs.len(),
+        });
+    }
+
+    let entities = &extracted.entities;
+    buffers.0.retain(|entity, _| entities.contains(entity));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_fields(
+    draw_functions: Res<DrawFunctions<Transparent3d>>,
+    field_pipeline: Res<FieldPipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedPipelines<FieldPipeline>>,
+    mut pipeline_cache: ResMut<RenderPipelineCache>,
+    buffers: Res<FieldBuffers>,
+    fields: Query<&MeshUniform, With<Handle<Mesh>>>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+) {
+    let draw_field = draw_functions.read().get_id::<DrawField>().unwrap();
+
+    let key = MeshPipelineKey::from_msaa_samples(msaa.samples)
+        | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList)
+        | MeshPipelineKey::TRANSPARENT_MAIN_PASS;
+    let pipeline = pipelines.specialize(&mut pipeline_cache, &field_pipeline, key);
+
+    for (view, mut transparent_phase) in views.iter_mut() {
+        let view_row_2 = view.transform.compute_matrix().row(2);
+        for &entity in buffers.0.keys() {
+            // meshes are only extracted while visible
+            let mesh_uniform = match fields.get(entity) {
+                Ok(mesh_uniform) => mesh_uniform,
+                Err(_) => continue,
+            };
+
+            transparent_phase.add(Transparent3d {
+                entity,
+                pipeline,
+                draw_function: draw_field,
+                distance: view_row_2.dot(mesh_uniform.transform.col(3)),
+            });
+        }
+    }
+}
+
+pub struct FieldPipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for FieldPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let world = world.cell();
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        let mesh_pipeline = world.get_resource::<MeshPipeline>().unwrap();
+
+        FieldPipeline {
+            shader: asset_server.load("field_shader.wgsl"),
+            mesh_pipeline: mesh_pipeline.clone(),
+        }
+    }
+}
+
+impl SpecializedPipeline for FieldPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut descriptor = self.mesh_pipeline.specialize(key);
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: std::mem::size_of::<GlyphInstance>() as u64,
+            step_mode: VertexStepMode::Instance,
+            // locations 0 to 2 are the position, normal and uv of the mesh
+            attributes: vec![
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 3,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: VertexFormat::Float32x4.size(),
+                    shader_location: 4,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x2,
+                    offset: VertexFormat::Float32x4.size() * 2,
+                    shader_location: 5,
+                },
+            ],
+        });
+        descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
+        descriptor.layout = Some(vec![
+            self.mesh_pipeline.view_layout.clone(),
+            self.mesh_pipeline.mesh_layout.clone(),
+        ]);
+        // the winding on screen comes from offsets added in the shader, so nothing is culled
+        descriptor.primitive.cull_mode = None;
+        descriptor
+    }
+}
+
+type DrawField = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    DrawGlyphsInstanced,
+);
+
+pub struct DrawGlyphsInstanced;
+
+impl EntityRenderCommand for DrawGlyphsInstanced {
+    type Param = (
+        SRes<RenderAssets<Mesh>>,
+        SQuery<Read<Handle<Mesh>>>,
+        SRes<FieldBuffers>,
+    );
+
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        (meshes, mesh_query, buffers): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let (mesh_handle, field_buffer) = match (mesh_query.get(item), buffers.into_inner().0.get(&item)) {
+            (Ok(mesh_handle), Some(field_buffer)) => (mesh_handle, field_buffer),
+            _ => return RenderCommandResult::Failure,
+        };
+
+        let gpu_mesh = match meshes.into_inner().get(mesh_handle) {
+            Some(gpu_mesh) => gpu_mesh,
+            None => return RenderCommandResult::Failure,
+        };
+
+        let instances = 0..field_buffer.length as u32;
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, field_buffer.buffer.slice(..));
+
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed { buffer, index_format, count } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, instances);
+            }
+            GpuBufferInfo::NonIndexed { vertex_count } => {
+                pass.draw(0..*vertex_count, instances);
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
@@ -0,0 +1,403 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::data_series::DataSeries;
+use crate::expr_text;
+use crate::export::csv::field;
+use crate::geometry::{eval_expression, Expression};
+
+const MAX_ITERATIONS: usize = 200;
+const INITIAL_DAMPING: f64 = 1e-3;
+const MAX_DAMPING: f64 = 1e12;
+
+/// Stop once an iteration improves the sum of squares by less than this fraction.
+const TOLERANCE: f64 = 1e-10;
+
+/// What is fitted to a data series.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Model {
+    /// Polynomial of the given degree, fitted directly by linear least squares.
+    /// A degree of 1 is linear regression.
+    Polynomial(usize),
+    /// Expression of x with free parameters, fitted by Levenberg-Marquardt.
+    Expression {
+        text: String,
+        params: Vec<String>,
+    },
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    /// `linear`, `poly:N` or an expression. Any names in the expression other than x,
+    /// constants and functions are its parameters.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "linear" {
+            return Ok(Model::Polynomial(1));
+        }
+
+        if let Some(degree) = s.strip_prefix("poly:") {
+            return degree.trim().parse::<usize>()
+                .map(Model::Polynomial)
+                .map_err(|_| format!("Invalid polynomial degree '{}'", degree));
+        }
+
+        let params = expr_text::free_identifiers(s);
+        if params.is_empty() {
+            return Err(format!("No parameters to fit in '{}'", s));
+        }
+
+        // check the expression parses before fitting
+        let ones = params.iter().map(|p| (p.as_str(), 1.0)).collect::<Vec<_>>();
+        Expression::parse(&expr_text::substitute(s, &ones))?;
+
+        Ok(Model::Expression { text: s.to_string(), params })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FitResult {
+    /// Parameter names and fitted values. Polynomial coefficients are named c0, c1, ...
+    pub params: Vec<(String, f64)>,
+    /// The model with its parameters substituted.
+    pub expression: String,
+    /// The finite data points that were fitted.
+    pub points: Vec<[f32; 2]>,
+    /// Data y minus fitted y at each point.
+    pub residuals: Vec<f64>,
+    /// 1 for a fit through every point of constant data, which has no variance to explain.
+    pub r_squared: f64,
+    pub iterations: usize,
+}
+
+impl FitResult {
+    pub fn rms_residual(&self) -> f64 {
+        let sum = self.residuals.iter().map(|r| r * r).sum::<f64>();
+        (sum / self.residuals.len() as f64).sqrt()
+    }
+
+    pub fn report(&self, name: &str) -> String {
+        let mut report = format!("Fit to {}: {}\n", name, self.expression);
+        for (param, value) in &self.params {
+            report.push_str(&format!("    {} = {}\n", param, value));
+        }
+
+        let max_residual = self.residuals.iter().fold(0.0f64, |max, r| max.max(r.abs()));
+        let constant = self.points.iter().all(|p| p[1] == self.points[0][1]);
+        report.push_str(&format!(
+            "    R² = {:.6}{}, rms residual = {}, max residual = {}, {} points, {} iterations",
+            self.r_squared,
+            if constant { " (constant data)" } else { "" },
+            self.rms_residual(), max_residual, self.points.len(), self.iterations,
+        ));
+        report
+    }
+}
+
+/// Fits the model to each series, printing a report of each fit, and writes
+/// their residuals to `residuals_file` if there is one.
+pub fn fit_series(
+    model: &Model,
+    series: &[DataSeries],
+    guesses: &[(String, f64)],
+    residuals_file: Option<&Path>,
+) -> Result<Vec<FitResult>, String> {
+    let fits = series.iter()
+        .map(|data| {
+            let result = fit(model, &data.points, guesses)
+                .map_err(|e| format!("Error fitting {}: {}", data.name, e))?;
+            println!("{}", result.report(&data.name));
+            Ok(result)
+        })
+        .collect::<Result<Vec<FitResult>, String>>()?;
+
+    if let Some(path) = residuals_file {
+        let named = series.iter().map(|data| data.name.as_str()).zip(&fits).collect::<Vec<_>>();
+        std::fs::File::create(path)
+            .map(io::BufWriter::new)
+            .and_then(|file| write_residuals(file, &named))
+            .map_err(|e| format!("Error writing {}: {}", path.display(), e))?;
+    }
+
+    Ok(fits)
+}
+
+/// Writes the residual at each fitted point, one row per point.
+pub fn write_residuals(mut writer: impl Write, fits: &[(&str, &FitResult)]) -> io::Result<()> {
+    writeln!(writer, "series,x,y,fitted,residual")?;
+    for (name, fit) in fits {
+        let name = field(name, ',');
+
+        for (&[x, y], r) in fit.points.iter().zip(&fit.residuals) {
+            writeln!(writer, "{},{},{},{},{}", name, x, y, y as f64 - r, r)?;
+        }
+    }
+    writer.flush()
+}
+
+/// Fits the model to the finite points of `points`.
+/// `guesses` are starting values of expression parameters, which otherwise start at one.
+pub fn fit(model: &Model, points: &[[f32; 2]], guesses: &[(String, f64)]) -> Result<FitResult, String> {
+    let points = points.iter()
+        .copied()
+        .filter(|[x, y]| x.is_finite() && y.is_finite())
+        .collect::<Vec<_>>();
+
+    let xs = points.iter().map(|p| p[0]).collect::<Vec<f32>>();
+    let ys = points.iter().map(|p| p[1] as f64).collect::<Vec<f64>>();
+
+    let (params, expression, fitted, iterations) = match model {
+        Model::Polynomial(degree) => {
+            let coefficients = polynomial_fit(&xs, &ys, *degree)?;
+            let fitted = xs.iter().map(|&x| eval_polynomial(&coefficients, x as f64)).collect();
+            let params = coefficients.iter().enumerate()
+                .map(|(i, &c)| (format!("c{}", i), c))
+                .collect();
+            (params, polynomial_expression(&coefficients), fitted, 1)
+        }
+        Model::Expression { text, params } => {
+            let start = params.iter()
+                .map(|p| guesses.iter().find(|(g, _)| g == p).map_or(1.0, |g| g.1))
+                .collect::<Vec<f64>>();
+
+            let (values, fitted, iterations) = levenberg_marquardt(text, params, start, &xs, &ys)?;
+            let named = params.iter().map(|p| p.as_str()).zip(values.iter().copied()).collect::<Vec<_>>();
+            let params = named.iter().map(|&(p, v)| (p.to_string(), v)).collect();
+            (params, expr_text::substitute(text, &named), fitted, iterations)
+        }
+    };
+
+    let residuals = ys.iter().zip(fitted.iter()).map(|(y, f)| y - f).collect::<Vec<f64>>();
+
+    Ok(FitResult {
+        params,
+        expression,
+        points,
+        r_squared: r_squared(&ys, &residuals),
+        residuals,
+        iterations,
+    })
+}
+
+/// The fraction of the variance of `ys` explained by a fit with these residuals.
+/// Constant data has no variance to explain, so a fit through every point scores 1
+/// and any other fit 0.
+fn r_squared(ys: &[f64], residuals: &[f64]) -> f64 {
+    let mean = ys.iter().sum::<f64>() / ys.len() as f64;
+    let total = ys.iter().map(|y| (y - mean).powi(2)).sum::<f64>();
+    let unexplained = residuals.iter().map(|r| r * r).sum::<f64>();
+
+    if total > 0.0 {
+        1.0 - unexplained / total
+    } else {
+        // fitted values are only as precise as mathjit's f32
+        let tolerance = 1e-10 * ys.iter().map(|y| y * y).sum::<f64>();
+        if unexplained <= tolerance { 1.0 } else { 0.0 }
+    }
+}
+
+/// Evaluates the model through mathjit with the parameters substituted.
+/// mathjit compiles functions of x alone, so the parameters are written into the
+/// expression as constants and it is compiled again for each set of values. Callers
+/// keep the values it returns rather than evaluating the same parameters twice.
+fn eval_model(text: &str, params: &[String], values: &[f64], xs: &[f32]) -> Result<Vec<f64>, String> {
+    let named = params.iter().map(|p| p.as_str()).zip(values.iter().copied()).collect::<Vec<_>>();
+    let expression = Expression::parse(&expr_text::substitute(text, &named))?;
+    Ok(eval_expression(&expression, xs).into_iter().map(|y| y as f64).collect())
+}
+
+/// Sum of squared residuals, infinite if the model is undefined at any point.
+fn sum_of_squares(fitted: &[f64], ys: &[f64]) -> f64 {
+    let sum = fitted.iter().zip(ys).map(|(f, y)| (y - f).powi(2)).sum::<f64>();
+    if sum.is_finite() { sum } else { f64::INFINITY }
+}
+
+/// Returns the fitted parameter values, the model's values at `xs` with them and the
+/// number of iterations taken. The jacobian is found by forward differences, compiling
+/// the model once for each stepped parameter.
+fn levenberg_marquardt(
+    text: &str,
+    params: &[String],
+    mut values: Vec<f64>,
+    xs: &[f32],
+    ys: &[f64],
+) -> Result<(Vec<f64>, Vec<f64>, usize), String> {
+    let n = params.len();
+    if xs.len() < n {
+        return Err(format!("{} points are not enough to fit {} parameters", xs.len(), n));
+    }
+
+    let mut fitted = eval_model(text, params, &values, xs)?;
+    let mut cost = sum_of_squares(&fitted, ys);
+    if !cost.is_finite() {
+        return Err("Model is undefined at the data with the starting parameters".to_string());
+    }
+
+    let mut damping = INITIAL_DAMPING;
+    let mut iterations = 0;
+
+    while iterations < MAX_ITERATIONS {
+        iterations += 1;
+
+        // jacobian columns, one per parameter
+        let mut jacobian = Vec::with_capacity(n);
+        for (j, value) in values.iter().enumerate() {
+            // mathjit evaluates in f32, so steps are much larger than f64 would need
+            let h = 1e-3 * value.abs().max(1e-3);
+            let mut stepped = values.clone();
+            stepped[j] += h;
+            let column = eval_model(text, params, &stepped, xs)?.iter()
+                .zip(&fitted)
+                .map(|(a, b)| (a - b) / h)
+                .collect::<Vec<f64>>();
+            jacobian.push(column);
+        }
+
+        let residuals = ys.iter().zip(&fitted).map(|(y, f)| y - f).collect::<Vec<f64>>();
+        let jtj = gram(&jacobian);
+        let jtr = jacobian.iter().map(|c| dot(c, &residuals)).collect::<Vec<f64>>();
+
+        // raise the damping until a step lowers the sum of squares
+        let improvement = loop {
+            let mut a = jtj.clone();
+            for (i, row) in a.iter_mut().enumerate() {
+                row[i] += damping * jtj[i][i].max(1e-12);
+            }
+
+            if let Some(step) = solve(a, jtr.clone()) {
+                let candidate = values.iter().zip(&step).map(|(v, s)| v + s).collect::<Vec<f64>>();
+                let candidate_fitted = eval_model(text, params, &candidate, xs)?;
+                let candidate_cost = sum_of_squares(&candidate_fitted, ys);
+
+                if candidate_cost < cost {
+                    let improvement = cost - candidate_cost;
+                    values = candidate;
+                    fitted = candidate_fitted;
+                    cost = candidate_cost;
+                    damping = (damping * 0.1).max(1e-12);
+                    break Some(improvement);
+                }
+            }
+
+            damping *= 10.0;
+            if damping > MAX_DAMPING {
+                break None;
+            }
+        };
+
+        match improvement {
+            Some(improvement) if improvement > TOLERANCE * cost => (),
+            _ => break,
+        }
+    }
+
+    Ok((values, fitted, iterations))
+}
+
+/// Least squares polynomial coefficients, constant term first.
+/// x is centred and scaled first so the normal equations stay well conditioned.
+fn polynomial_fit(xs: &[f32], ys: &[f64], degree: usize) -> Result<Vec<f64>, String> {
+    if xs.len() <= degree {
+        return Err(format!("{} points are not enough to fit a polynomial of degree {}", xs.len(), degree));
+    }
+
+    let (min, max) = xs.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &x| {
+        (lo.min(x as f64), hi.max(x as f64))
+    });
+    let centre = 0.5 * (min + max);
+    let half_range = (0.5 * (max - min)).max(f64::MIN_POSITIVE);
+
+    let columns = (0..=degree)
+        .map(|k| xs.iter().map(|&x| ((x as f64 - centre) / half_range).powi(k as i32)).collect::<Vec<f64>>())
+        .collect::<Vec<_>>();
+
+    let rhs = columns.iter().map(|c| dot(c, ys)).collect();
+    let scaled = solve(gram(&columns), rhs)
+        .ok_or_else(|| "Polynomial fit is singular, the data may have too few distinct x values".to_string())?;
+
+    // expand sum a_k ((x - centre) / half_range)^k into powers of x
+    let mut coefficients = vec![0.0; degree + 1];
+    for (k, a) in scaled.iter().enumerate() {
+        let scale = a / half_range.powi(k as i32);
+        // binomial expansion of (x - centre)^k
+        let mut binomial = 1.0;
+        for i in 0..=k {
+            coefficients[i] += scale * binomial * (-centre).powi((k - i) as i32);
+            binomial = binomial * (k - i) as f64 / (i + 1) as f64;
+        }
+    }
+
+    Ok(coefficients)
+}
+
+fn eval_polynomial(coefficients: &[f64], x: f64) -> f64 {
+    coefficients.iter().rev().fold(0.0, |acc, c| acc * x + c)
+}
+
+/// Horner form with powers written as products, which mathjit can always compile.
+fn polynomial_expression(coefficients: &[f64]) -> String {
+    let mut expression = expr_text::format_value(coefficients[coefficients.len() - 1]);
+    for &c in coefficients.iter().rev().skip(1) {
+        expression = format!("{}+x*({})", expr_text::format_value(c), expression);
+    }
+    expression
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// Matrix of dot products between each pair of columns.
+fn gram(columns: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    columns.iter()
+        .map(|a| columns.iter().map(|b| dot(a, b)).collect())
+        .collect()
+}
+
+/// Solves `a x = b` by gaussian elimination with partial pivoting.
+/// Returns `None` if `a` is singular.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-300 || !a[pivot][col].is_finite() {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let pivot_row = a[col].clone();
+        for row in (col + 1)..n {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, p) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum = ((row + 1)..n).map(|k| a[row][k] * x[k]).sum::<f64>();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_data_is_explained_by_a_constant() {
+        let points = [[0.0, 2.0], [1.0, 2.0], [2.0, 2.0]];
+        let result = fit(&Model::Polynomial(0), &points, &[]).unwrap();
+
+        assert_eq!(result.r_squared, 1.0);
+        assert!(result.report("data").contains("(constant data)"));
+    }
+}
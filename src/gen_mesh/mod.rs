@@ -1,11 +1,17 @@
-use super::scaling::{Bounds, GraphingBounds};
+use crate::scaling::{AxisScale, Bounds, GraphingBounds, GraphingView};
+use bevy::ecs::component::TableStorage;
 use bevy::render::mesh::{Indices, Mesh};
 use bevy::render::render_resource::PrimitiveTopology;
 use bevy::prelude::*;
 use crate::axis_text::{MinAxisInfo, MidAxisInfo};
 
 mod gen_expr_mesh;
-pub use gen_expr_mesh::gen_expr_mesh;
+pub use gen_expr_mesh::{gen_expr_mesh, gen_polyline_mesh};
+pub use crate::geometry::{
+    axis_line_endpoints, axis_line_positions, curve_pieces, eval_expression, finer_axis_diff,
+    log_axis_lines, main_axis_endpoints, mid_axis_count, mid_axis_diff, sample_expression, sample_xs,
+    CapStyle, Expression, FallbackJoin, JoinStyle, StrokeStyle,
+};
 
 #[derive(Clone, Debug)]
 pub struct GridMeshHandles {
@@ -14,20 +20,36 @@ pub struct GridMeshHandles {
     pub min_axis: Handle<Mesh>,
 }
 
+// the geometry types don't depend on bevy_ecs, so are made components here
+impl Component for Expression {
+    type Storage = TableStorage;
+}
+
+impl Component for StrokeStyle {
+    type Storage = TableStorage;
+}
+
+/// An expression as it was written, before parameters were substituted.
 #[derive(Component, Clone, Debug)]
-pub struct Expression(pub mathjit::expr_parse::Expression);
+pub struct ExpressionText(pub String);
 
 pub fn regenerate_meshes_system(
     graphing_bounds: Res<GraphingBounds>,
+    view: Res<GraphingView>,
     mid_axis_info: Res<MidAxisInfo>,
     mut grid_mesh_handles: ResMut<GridMeshHandles>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut graphs: Query<(&Expression, &mut Handle<Mesh>)>
+    mut graphs: Query<(&Expression, ChangeTrackers<Expression>, &StrokeStyle, &mut Handle<Mesh>)>
 ) {
+    let xbounds = graphing_bounds.xbounds;
+    let ybounds = graphing_bounds.ybounds;
+    let scales = (view.xscale, view.yscale);
+
     if graphing_bounds.is_changed() {
-        let xbounds = graphing_bounds.xbounds;
-        let ybounds = graphing_bounds.ybounds;
-        grid_mesh_handles.main_axis = meshes.set(grid_mesh_handles.main_axis.clone(), gen_main_axis(xbounds, ybounds));
+        grid_mesh_handles.main_axis = meshes.set(
+            grid_mesh_handles.main_axis.clone(),
+            gen_main_axis(xbounds, ybounds, scales)
+        );
         grid_mesh_handles.mid_axis = meshes.set(
             grid_mesh_handles.mid_axis.clone(),
             gen_mid_axis(*mid_axis_info, &graphing_bounds)
@@ -36,111 +58,133 @@ pub fn regenerate_meshes_system(
             grid_mesh_handles.min_axis.clone(),
             gen_min_axis(mid_axis_info.calculate_min_axis_info(), &graphing_bounds)
         );
+    }
 
-        for (expr, mut mesh_handle) in graphs.iter_mut() {
-            *mesh_handle = meshes.set(mesh_handle.clone(), gen_expr_mesh(expr, xbounds));
+    // curves are also rebuilt in place when their expression is replaced
+    for (expr, expr_tracker, stroke, mut mesh_handle) in graphs.iter_mut() {
+        if graphing_bounds.is_changed() || expr_tracker.is_changed() {
+            *mesh_handle = meshes.set(mesh_handle.clone(), gen_expr_mesh(expr, xbounds, scales, stroke));
         }
     }
 }
 
-pub fn gen_main_axis(xbounds: Bounds, ybounds: Bounds) -> Mesh {
-    let Bounds { start: xstart, end: xend } = xbounds;
-    let Bounds { start: ystart, end: yend } = ybounds;
-
-    let vertices = vec![
-        [xstart, 0.0, 0.0], [xend, 0.0, 0.0],
-        [0.0, ystart, 0.0], [0.0, yend, 0.0]
-    ];
-
-    let len = vertices.len();
-    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
-    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 0.0]]);
-    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; len]);
-    mesh.set_indices(Some(Indices::U16((0..(len as u16)).collect::<Vec<u16>>())));
-
-    mesh
+pub fn gen_main_axis(xbounds: Bounds, ybounds: Bounds, scales: (AxisScale, AxisScale)) -> Mesh {
+    line_quads(&main_axis_endpoints(xbounds, ybounds, scales))
 }
 
-// Fix for large and small numbers.
-// Good enough for now
-pub fn mid_axis_diff(scale: f32) -> f32 {
-    let l2 = (scale/10.0).log2() as i32 + 1;
-    let l5 = (l2+1) / 3;
-    2.0f32.powi(l2-2*l5)*5.0f32.powi(l5)
-}
+/// Expands pairs of line endpoints into quads, offset by their normals
+/// in the vertex shader so lines have a constant width on screen.
+pub fn line_quads(endpoints: &[[f32; 2]]) -> Mesh {
+    assert!(endpoints.len() % 2 == 0);
+    let len = endpoints.len() * 2;
+
+    let mut vertices = Vec::with_capacity(len);
+    let mut normals = Vec::with_capacity(len);
+    let mut uvs = Vec::with_capacity(len);
+    let mut indices = Vec::with_capacity(endpoints.len() * 3);
+
+    for line in endpoints.chunks_exact(2) {
+        let [ax, ay] = line[0];
+        let [bx, by] = line[1];
+        let length = ((bx - ax).powi(2) + (by - ay).powi(2)).sqrt();
+        if length == 0.0 {
+            continue;
+        }
 
-pub fn mid_axis_count(xbounds: Bounds, ybounds: Bounds, axis_separation: f32) -> (usize, usize) {
-    let Bounds { start: xstart, end: xend } = xbounds;
-    let Bounds { start: ystart, end: yend } = ybounds;
+        // left of the line direction, keeping the winding counter clockwise
+        // z is the distance across the line
+        let n = [(ay - by) / length, (bx - ax) / length, 1.0];
+        let neg_n = [-n[0], -n[1], -1.0];
 
-    // count on one side of centre of bounds
-    // add one more line to fix occasional off by one
-    let xline_count = ((xend - xstart) / (2.0 * axis_separation)) as usize + 1;
-    let yline_count = ((yend - ystart) / (2.0 * axis_separation)) as usize + 1;
+        let i = vertices.len() as u32;
+        vertices.extend_from_slice(&[[ax, ay, 0.0], [ax, ay, 0.0], [bx, by, 0.0], [bx, by, 0.0]]);
+        normals.extend_from_slice(&[n, neg_n, n, neg_n]);
+        uvs.extend_from_slice(&[[0.0, 0.0], [0.0, 0.0], [length, 0.0], [length, 0.0]]);
+        indices.extend_from_slice(&[i, i + 1, i + 2, i + 1, i + 3, i + 2]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
 
-    (xline_count, yline_count)
+    mesh
 }
 
 pub fn gen_min_axis(info: MinAxisInfo, bounds: &GraphingBounds) -> Mesh {
-    gen_mid_axis(info, bounds)
+    line_quads(&axis_line_endpoints(info, bounds, true))
 }
 
 pub fn gen_mid_axis(info: MidAxisInfo, bounds: &GraphingBounds) -> Mesh {
-    let Bounds { start: xstart, end: xend } = bounds.xbounds;
-    let Bounds { start: ystart, end: yend } = bounds.ybounds;
-
-    let MidAxisInfo {
-        separation,
-        xline_count,
-        yline_count,
-        rounded_xcentre,
-        rounded_ycentre,
-    } = info;
-
-    let vertex_count = 2 + xline_count * 2 + yline_count * 2;
-    let mut vertices = Vec::with_capacity(vertex_count);
-    let mut normals = Vec::with_capacity(vertex_count);
-
-    { 
-        let mut add_horiz_line = |n| {
-            vertices.push([xstart, n, 0.0]);
-            vertices.push([xend, n, 0.0]);
-        };
+    line_quads(&axis_line_endpoints(info, bounds, false))
+}
 
-        add_horiz_line(rounded_ycentre);
-        for i in 1..yline_count {
-            let j = i as f32;
-            add_horiz_line(rounded_ycentre + separation * j);
-            add_horiz_line(rounded_ycentre - separation * j);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::mid_axis_info;
+    use crate::grid::TickMode;
+    use bevy::render::mesh::VertexAttributeValues;
+    use proptest::prelude::*;
+
+    /// Numbers of positions, normals, uvs and indices.
+    fn counts(mesh: &Mesh) -> [usize; 4] {
+        let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(p)) => p.len(),
+            _ => panic!("missing positions"),
+        };
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(n)) => n.len(),
+            _ => panic!("missing normals"),
+        };
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uv)) => uv.len(),
+            _ => panic!("missing uvs"),
+        };
+        let indices = match mesh.indices() {
+            Some(Indices::U32(i)) => i.len(),
+            _ => panic!("missing indices"),
+        };
 
-        normals.extend_from_slice(&vec![[0.0, 1.0, 0.0]; yline_count * 4 - 2])
+        [positions, normals, uvs, indices]
     }
 
-    { 
-        let mut add_vert_line = |n| {
-            vertices.push([n, ystart, 0.0]);
-            vertices.push([n, yend, 0.0]);
-        };
-
-        add_vert_line(rounded_xcentre);
-        for i in 1..xline_count {
-            let j = i as f32;
-            add_vert_line(rounded_xcentre + separation * j);
-            add_vert_line(rounded_xcentre - separation * j);
+    proptest! {
+        #[test]
+        fn axis_mesh_counts_match(
+            e in -6.0f32..6.0,
+            x in -100.0f32..100.0,
+            y in -100.0f32..100.0,
+            aspect in 0.2f32..5.0,
+        ) {
+            let scale = 10.0f32.powf(e);
+            let view = GraphingView {
+                centre: [x * scale, y * scale],
+                scale,
+                xscale: AxisScale::Linear,
+                yscale: AxisScale::Linear,
+            };
+            let bounds = view.pregenerated_bounds(640.0, 640.0 * aspect);
+            let info = mid_axis_info(&bounds, &view, TickMode::Decimal, TickMode::Decimal);
+
+            // a centre line and `line_count - 1` lines either side on each axis,
+            // each drawn as a quad of 4 vertices and 6 indices
+            for (info, mesh) in [
+                (info, gen_mid_axis(info, &bounds)),
+                (info.calculate_min_axis_info(), gen_min_axis(info.calculate_min_axis_info(), &bounds)),
+            ] {
+                let lines = (2 * info.xline_count - 1) + (2 * info.yline_count - 1);
+                prop_assert_eq!(counts(&mesh), [lines * 4, lines * 4, lines * 4, lines * 6]);
+            }
         }
 
-        normals.extend_from_slice(&vec![[1.0, 0.0, 0.0]; xline_count * 4 - 2])
+        #[test]
+        fn main_axis_counts_match(xstart in -100.0f32..0.0, ystart in -100.0f32..0.0, w in 0.1f32..100.0, h in 0.1f32..100.0) {
+            let xbounds = Bounds { start: xstart, end: xstart + w };
+            let ybounds = Bounds { start: ystart, end: ystart + h };
+            let mesh = gen_main_axis(xbounds, ybounds, (AxisScale::Linear, AxisScale::Linear));
+            prop_assert_eq!(counts(&mesh), [8, 8, 8, 12]);
+        }
     }
-
-    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
-
-    let len = vertices.len();
-    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; len]);
-    mesh.set_indices(Some(Indices::U16((0..(len as u16)).collect::<Vec<u16>>())));
-
-    mesh
 }
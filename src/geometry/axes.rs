@@ -0,0 +1,358 @@
+use super::{AxisScale, Bounds, GraphingBounds, GraphingView, TickMode};
+
+#[derive(Copy, Clone, Debug)]
+pub struct MidAxisInfo {
+    pub xseparation: f32,
+    pub yseparation: f32,
+    pub xline_count: usize,
+    pub yline_count: usize,
+    pub rounded_xcentre: f32,
+    pub rounded_ycentre: f32,
+    pub xscale: AxisScale,
+    pub yscale: AxisScale,
+    pub xticks: TickMode,
+    pub yticks: TickMode,
+}
+
+pub type MinAxisInfo = MidAxisInfo;
+
+/// Spacing and placement of the mid axis lines within `bounds`.
+pub fn mid_axis_info(
+    bounds: &GraphingBounds,
+    view: &GraphingView,
+    xticks: TickMode,
+    yticks: TickMode,
+) -> MidAxisInfo {
+    let xseparation = xticks.axis_diff(view.scale);
+    let yseparation = yticks.axis_diff(view.scale);
+    let (xline_count, yline_count) = mid_axis_count(bounds.xbounds, bounds.ybounds, xseparation, yseparation);
+    // lines are spread either side of the bounds, which may be stale relative to the view
+    let rounded_ycentre = (bounds.ybounds.centre() / yseparation).round() * yseparation;
+    let rounded_xcentre = (bounds.xbounds.centre() / xseparation).round() * xseparation;
+
+    MidAxisInfo {
+        xseparation,
+        yseparation,
+        xline_count,
+        yline_count,
+        rounded_xcentre,
+        rounded_ycentre,
+        xscale: view.xscale,
+        yscale: view.yscale,
+        xticks,
+        yticks,
+    }
+}
+
+impl MidAxisInfo {
+    /// Minor lines use the next finer spacing after the mid lines,
+    /// so they become the mid lines when zooming in.
+    pub fn calculate_min_axis_info(&self) -> MinAxisInfo {
+        let xseparation = self.xticks.finer_diff(self.xseparation);
+        let yseparation = self.yticks.finer_diff(self.yseparation);
+        let xratio = (self.xseparation / xseparation).ceil() as usize;
+        let yratio = (self.yseparation / yseparation).ceil() as usize;
+
+        MinAxisInfo {
+            xseparation,
+            yseparation,
+            xline_count: self.xline_count * xratio,
+            yline_count: self.yline_count * yratio,
+            rounded_xcentre: (self.rounded_xcentre / xseparation).round() * xseparation,
+            rounded_ycentre: (self.rounded_ycentre / yseparation).round() * yseparation,
+            ..*self
+        }
+    }
+}
+
+/// Fewest mid axis lines on either side of the centre of the view.
+/// There are fewer than `2.5 * MIN_MID_LINES` lines on either side.
+pub const MIN_MID_LINES: f32 = 4.0;
+
+/// Separation of the mid axis lines at the view scale.
+/// Always 1, 2 or 5 times a power of ten.
+pub fn mid_axis_diff(scale: f32) -> f32 {
+    let target = scale / MIN_MID_LINES;
+    let magnitude = 10.0f32.powf(target.log10().floor());
+    let mantissa = target / magnitude;
+
+    if mantissa >= 5.0 {
+        5.0 * magnitude
+    } else if mantissa >= 2.0 {
+        2.0 * magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// The next smaller spacing in the 1, 2, 5 sequence used by `mid_axis_diff`.
+pub fn finer_axis_diff(diff: f32) -> f32 {
+    let magnitude = 10.0f32.powf(diff.log10().floor());
+    let mantissa = (diff / magnitude).round();
+
+    if mantissa >= 5.0 {
+        2.0 * magnitude
+    } else if mantissa >= 2.0 {
+        magnitude
+    } else {
+        0.5 * magnitude
+    }
+}
+
+pub fn mid_axis_count(xbounds: Bounds, ybounds: Bounds, xseparation: f32, yseparation: f32) -> (usize, usize) {
+    let Bounds { start: xstart, end: xend } = xbounds;
+    let Bounds { start: ystart, end: yend } = ybounds;
+
+    // count on one side of centre of bounds, including the centre line.
+    // the centre line is rounded to the separation, so can be half a separation
+    // from the centre of the bounds - an extra half line covers the far side.
+    let xline_count = ((xend - xstart) / (2.0 * xseparation) + 0.5).ceil() as usize + 1;
+    let yline_count = ((yend - ystart) / (2.0 * yseparation) + 0.5).ceil() as usize + 1;
+
+    (xline_count, yline_count)
+}
+
+/// Pairs of endpoints of the main axes.
+/// The main axes are not drawn on logarithmic axes, which have no zero.
+pub fn main_axis_endpoints(
+    xbounds: Bounds,
+    ybounds: Bounds,
+    (xscale, yscale): (AxisScale, AxisScale)
+) -> Vec<[f32; 2]> {
+    let Bounds { start: xstart, end: xend } = xbounds;
+    let Bounds { start: ystart, end: yend } = ybounds;
+
+    let mut endpoints = Vec::with_capacity(4);
+
+    let y0 = yscale.forward(0.0);
+    if y0.is_finite() {
+        endpoints.extend_from_slice(&[[xstart, y0], [xend, y0]]);
+    }
+
+    let x0 = xscale.forward(0.0);
+    if x0.is_finite() {
+        endpoints.extend_from_slice(&[[x0, ystart], [x0, yend]]);
+    }
+
+    endpoints
+}
+
+/// Pairs of endpoints of the mid or minor axis lines.
+pub fn axis_line_endpoints(info: MidAxisInfo, bounds: &GraphingBounds, minor: bool) -> Vec<[f32; 2]> {
+    let Bounds { start: xstart, end: xend } = bounds.xbounds;
+    let Bounds { start: ystart, end: yend } = bounds.ybounds;
+
+    let ys = axis_line_positions(
+        info.yscale, bounds.ybounds, info.yseparation, info.yline_count, info.rounded_ycentre, minor
+    );
+    let xs = axis_line_positions(
+        info.xscale, bounds.xbounds, info.xseparation, info.xline_count, info.rounded_xcentre, minor
+    );
+
+    let mut endpoints = Vec::with_capacity((xs.len() + ys.len()) * 2);
+
+    for y in ys {
+        endpoints.push([xstart, y]);
+        endpoints.push([xend, y]);
+    }
+
+    for x in xs {
+        endpoints.push([x, ystart]);
+        endpoints.push([x, yend]);
+    }
+
+    endpoints
+}
+
+/// Positions of the grid lines along one axis, in graph space.
+/// On a linear axis there is one line through the rounded centre,
+/// and `line_count - 1` lines on either side.
+pub fn axis_line_positions(
+    scale: AxisScale,
+    bounds: Bounds,
+    separation: f32,
+    line_count: usize,
+    rounded_centre: f32,
+    minor: bool,
+) -> Vec<f32> {
+    if !scale.is_linear() {
+        return log_axis_lines(scale, bounds, separation, minor);
+    }
+
+    let mut lines = Vec::with_capacity(line_count * 2 - 1);
+    lines.push(rounded_centre);
+    for i in 1..line_count {
+        let j = i as f32;
+        lines.push(rounded_centre + separation * j);
+        lines.push(rounded_centre - separation * j);
+    }
+
+    lines
+}
+
+/// Grid line positions in graph space for a logarithmic axis.
+/// Mid lines are at powers of ten, skipping decades that would be closer together
+/// than `separation`. Minor lines are at 2 to 9 times each power of ten, and are
+/// only drawn when no decades are skipped.
+pub fn log_axis_lines(scale: AxisScale, bounds: Bounds, separation: f32, minor: bool) -> Vec<f32> {
+    let lo = scale.inverse(bounds.start);
+    let hi = scale.inverse(bounds.end);
+
+    let (smallest, signs): (f32, &[f32]) = match scale {
+        AxisScale::Symlog { threshold } => (threshold, &[1.0, -1.0]),
+        _ => (lo, &[1.0]),
+    };
+    let largest = lo.abs().max(hi.abs());
+
+    // f32 only covers about 38 decades either side of 1
+    let first_decade = (smallest.log10().floor() as i32).clamp(-38, 38);
+    let last_decade = (largest.log10().ceil() as i32).clamp(-38, 38);
+
+    // graph space distance between decades, far from zero on a symlog axis
+    let decade_width = scale.forward(10.0f32.powi(last_decade)) - scale.forward(10.0f32.powi(last_decade - 1));
+    let step = (separation / decade_width).ceil().max(1.0) as i32;
+
+    let mut lines = Vec::new();
+    if minor && step > 1 {
+        return lines;
+    }
+
+    if !minor && matches!(scale, AxisScale::Symlog { .. }) {
+        lines.push(0.0);
+    }
+
+    for decade in first_decade..=last_decade {
+        if !minor && decade.rem_euclid(step) != 0 {
+            continue;
+        }
+
+        let power = 10.0f32.powi(decade);
+        let multiples: &[f32] = if minor {
+            &[2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]
+        } else {
+            &[1.0]
+        };
+
+        for sign in signs {
+            for m in multiples {
+                let t = scale.forward(sign * m * power);
+                if bounds.start <= t && t <= bounds.end {
+                    lines.push(t);
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::DEFAULT_SCALE;
+    use proptest::prelude::*;
+
+    /// Whether `diff` is 1, 2 or 5 times a power of ten.
+    fn is_one_two_five(diff: f32) -> bool {
+        let diff = diff as f64;
+        let mantissa = diff / 10f64.powf(diff.log10().floor());
+        [1.0, 2.0, 5.0, 10.0].iter().any(|m| (mantissa - m).abs() < 1e-4 * m)
+    }
+
+    /// Whether there is a line at every multiple of the separation within `bounds`.
+    fn covers_multiples(bounds: Bounds, separation: f32, line_count: usize, rounded_centre: f32) -> bool {
+        let lines = axis_line_positions(AxisScale::Linear, bounds, separation, line_count, rounded_centre, false);
+        let first = (bounds.start / separation).ceil() as i64;
+        let last = (bounds.end / separation).floor() as i64;
+
+        (first..=last).all(|k| {
+            let t = k as f32 * separation;
+            lines.iter().any(|&line| (line - t).abs() < separation * 1e-2)
+        })
+    }
+
+    fn view(centre: [f32; 2], scale: f32) -> GraphingView {
+        GraphingView { centre, scale, xscale: AxisScale::Linear, yscale: AxisScale::Linear }
+    }
+
+    #[test]
+    fn default_scale_has_unit_spacing() {
+        assert_eq!(mid_axis_diff(DEFAULT_SCALE), 1.0);
+    }
+
+    #[test]
+    fn mid_axis_diff_steps() {
+        assert_eq!(mid_axis_diff(8.0), 2.0);
+        assert_eq!(mid_axis_diff(20.0), 5.0);
+        assert_eq!(mid_axis_diff(50.0), 10.0);
+    }
+
+    #[test]
+    fn finer_axis_diff_steps() {
+        assert_eq!(finer_axis_diff(500.0), 200.0);
+        assert_eq!(finer_axis_diff(200.0), 100.0);
+        assert_eq!(finer_axis_diff(50.0), 20.0);
+    }
+
+    #[test]
+    fn off_centre_bounds_are_covered() {
+        // the centre line rounds down to 0, a whole separation from the line at 2
+        let bounds = Bounds { start: -1.5, end: 2.3 };
+        let (count, _) = mid_axis_count(bounds, bounds, 1.0, 1.0);
+        assert!(covers_multiples(bounds, 1.0, count, 0.0));
+    }
+
+    proptest! {
+        #[test]
+        fn mid_axis_diff_is_one_two_or_five(e in -20.0f32..20.0) {
+            let diff = mid_axis_diff(10.0f32.powf(e));
+            prop_assert!(is_one_two_five(diff), "{}", diff);
+        }
+
+        #[test]
+        fn mid_axis_diff_line_count_is_bounded(e in -20.0f32..20.0) {
+            let scale = 10.0f32.powf(e);
+            let lines = scale / mid_axis_diff(scale);
+            prop_assert!(lines >= MIN_MID_LINES * (1.0 - 1e-4), "{} lines at scale {}", lines, scale);
+            prop_assert!(lines < 2.5 * MIN_MID_LINES * (1.0 + 1e-4), "{} lines at scale {}", lines, scale);
+        }
+
+        #[test]
+        fn mid_axis_diff_is_monotonic(e in -20.0f32..20.0, factor in 1.01f32..100.0) {
+            let scale = 10.0f32.powf(e);
+            prop_assert!(mid_axis_diff(scale) <= mid_axis_diff(scale * factor));
+        }
+
+        #[test]
+        fn finer_axis_diff_is_one_two_or_five(e in -20.0f32..20.0) {
+            let diff = mid_axis_diff(10.0f32.powf(e));
+            let finer = finer_axis_diff(diff);
+            prop_assert!(is_one_two_five(finer), "{}", finer);
+            prop_assert!(finer < diff);
+        }
+
+        #[test]
+        fn mid_and_minor_lines_cover_bounds(
+            e in -10.0f32..10.0,
+            centre in -100.0f32..100.0,
+            half_width in 0.1f32..5.0,
+            view_scale in 0.3f32..3.0,
+            stale in -2.0f32..2.0,
+        ) {
+            let scale = 10.0f32.powf(e);
+            let c = centre * scale;
+            let bounds = Bounds { start: c - half_width * scale, end: c + half_width * scale };
+            let graphing_bounds = GraphingBounds { xbounds: bounds, ybounds: bounds };
+            // the view may have panned since the bounds were generated
+            let view = view([c + stale * scale; 2], view_scale * scale);
+
+            let info = mid_axis_info(&graphing_bounds, &view, TickMode::Decimal, TickMode::Decimal);
+            prop_assert!(covers_multiples(bounds, info.xseparation, info.xline_count, info.rounded_xcentre));
+            prop_assert!(covers_multiples(bounds, info.yseparation, info.yline_count, info.rounded_ycentre));
+
+            let min = info.calculate_min_axis_info();
+            prop_assert!(covers_multiples(bounds, min.xseparation, min.xline_count, min.rounded_xcentre));
+            prop_assert!(covers_multiples(bounds, min.yseparation, min.yline_count, min.rounded_ycentre));
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use std::f32::consts::{PI, TAU};
+
+use super::{eval_row, AxisScale, GraphingBounds, RowFunction};
+use crate::expr_text::{self, Token};
+
+/// The variable of complex expressions, `x + iy`.
+pub const COMPLEX_VARIABLE: &str = "z";
+
+/// Largest integer power of a complex value, which is expanded into products.
+const MAX_POWER: i32 = 32;
+
+/// Rays of constant argument drawn by contour lines, per turn.
+const ARGUMENT_CONTOURS: f32 = 12.0;
+
+/// Added to the imaginary part when finding the argument, so the negative real axis
+/// has the argument π of the principal branch rather than being undefined.
+const CUT_OFFSET: f64 = 1e-30;
+
+/// A function of a complex variable as two real functions of x and y, z = x + iy,
+/// which mathjit can compile.
+///
+/// Supports `+ - * /`, integer powers, `exp`, `sin`, `cos`, `sinh`, `cosh`, `conj`,
+/// `re`, `im`, `abs`, and the principal branches of `sqrt` and `log` or `ln`.
+/// Other functions may be applied to real values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComplexExpression {
+    pub re: String,
+    pub im: String,
+}
+
+/// Text of a real expression, or `None` where it is exactly zero, so real
+/// values don't double the size of every product.
+type Part = Option<String>;
+
+#[derive(Clone, Debug)]
+struct Value {
+    re: Part,
+    im: Part,
+}
+
+impl ComplexExpression {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = expr_text::tokens(text).into_iter().map(|(_, t)| t).collect::<Vec<_>>();
+        let mut parser = Parser { tokens: &tokens, next: 0 };
+
+        let value = parser.expression()
+            .and_then(|value| match parser.peek() {
+                None => Ok(value),
+                Some(token) => Err(format!("unexpected {}", describe(token))),
+            })
+            .map_err(|e| format!("Error in expression '{}': {}", text, e))?;
+
+        let expression = ComplexExpression {
+            re: value.re.unwrap_or_else(|| "0".to_string()),
+            im: value.im.unwrap_or_else(|| "0".to_string()),
+        };
+
+        // check mathjit can compile the parts
+        for part in [&expression.re, &expression.im] {
+            eval_row(part, &[], 0.0).map_err(|_| format!("Error in expression '{}'", text))?;
+        }
+
+        Ok(expression)
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token<'a>],
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.next).copied()
+    }
+
+    fn take(&mut self) -> Option<Token<'a>> {
+        let token = self.peek();
+        self.next += 1;
+        token
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        match self.take() {
+            Some(Token::Symbol(c)) if c == symbol => Ok(()),
+            Some(token) => Err(format!("expected '{}', found {}", symbol, describe(token))),
+            None => Err(format!("expected '{}'", symbol)),
+        }
+    }
+
+    /// Sums and differences of terms.
+    fn expression(&mut self) -> Result<Value, String> {
+        let mut value = self.term()?;
+        loop {
+            match self.peek() {
+                Some(Token::Symbol('+')) => {
+                    self.take();
+                    value = add(&value, &self.term()?);
+                }
+                Some(Token::Symbol('-')) => {
+                    self.take();
+                    value = sub(&value, &self.term()?);
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// Products and quotients.
+    fn term(&mut self) -> Result<Value, String> {
+        let mut value = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Symbol('*')) => {
+                    self.take();
+                    value = mul(&value, &self.unary()?);
+                }
+                Some(Token::Symbol('/')) => {
+                    self.take();
+                    value = div(&value, &self.unary()?);
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(Token::Symbol('-')) => {
+                self.take();
+                Ok(neg(&self.unary()?))
+            }
+            Some(Token::Symbol('+')) => {
+                self.take();
+                self.unary()
+            }
+            _ => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Value, String> {
+        let base = self.atom()?;
+        if self.peek() != Some(Token::Symbol('^')) {
+            return Ok(base);
+        }
+        self.take();
+
+        // only integer powers have a single value
+        let negative = self.peek() == Some(Token::Symbol('-'));
+        if negative {
+            self.take();
+        }
+        let n = match self.take() {
+            Some(Token::Number(n)) => n.parse::<i32>()
+                .ok()
+                .filter(|n| *n <= MAX_POWER)
+                .ok_or_else(|| format!("powers must be integers up to {}, found {}", MAX_POWER, n))?,
+            Some(token) => return Err(format!("powers must be integers, found {}", describe(token))),
+            None => return Err("expected a power".to_string()),
+        };
+
+        let power = pow(&base, n as u32);
+        if negative {
+            Ok(div(&real("1"), &power))
+        } else {
+            Ok(power)
+        }
+    }
+
+    fn atom(&mut self) -> Result<Value, String> {
+        match self.take() {
+            Some(Token::Number(n)) => Ok(real(n)),
+            Some(Token::Identifier(name, false)) => Ok(match name {
+                COMPLEX_VARIABLE => Value { re: Some("x".to_string()), im: Some("y".to_string()) },
+                "i" => Value { re: None, im: Some("1".to_string()) },
+                // x and y are the real and imaginary parts of z
+                _ => real(name),
+            }),
+            Some(Token::Identifier(name, true)) => {
+                self.expect('(')?;
+                let argument = self.expression()?;
+                self.expect(')')?;
+                call(name, &argument)
+            }
+            Some(Token::Symbol('(')) => {
+                let value = self.expression()?;
+                self.expect(')')?;
+                Ok(value)
+            }
+            Some(token) => Err(format!("unexpected {}", describe(token))),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// A token as it appears in an error message.
+pub(super) fn describe(token: Token) -> String {
+    match token {
+        Token::Number(n) => format!("'{}'", n),
+        Token::Identifier(name, _) => format!("'{}'", name),
+        Token::Symbol(c) => format!("'{}'", c),
+    }
+}
+
+fn real(text: &str) -> Value {
+    Value { re: Some(text.to_string()), im: None }
+}
+
+fn add_parts(a: &Part, b: &Part) -> Part {
+    match (a, b) {
+        (None, b) => b.clone(),
+        (a, None) => a.clone(),
+        (Some(a), Some(b)) => Some(format!("({})+({})", a, b)),
+    }
+}
+
+fn sub_parts(a: &Part, b: &Part) -> Part {
+    match (a, b) {
+        (a, None) => a.clone(),
+        (None, Some(b)) => Some(format!("(-({}))", b)),
+        (Some(a), Some(b)) => Some(format!("({})-({})", a, b)),
+    }
+}
+
+fn mul_parts(a: &Part, b: &Part) -> Part {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(format!("({})*({})", a, b)),
+        _ => None,
+    }
+}
+
+fn div_part(a: &Part, b: &str) -> Part {
+    a.as_ref().map(|a| format!("({})/({})", a, b))
+}
+
+/// Applies a real function, which is not zero at zero.
+fn call_part(f: &str, a: &Part) -> Part {
+    Some(format!("{}({})", f, a.as_deref().unwrap_or("0")))
+}
+
+fn add(a: &Value, b: &Value) -> Value {
+    Value { re: add_parts(&a.re, &b.re), im: add_parts(&a.im, &b.im) }
+}
+
+fn sub(a: &Value, b: &Value) -> Value {
+    Value { re: sub_parts(&a.re, &b.re), im: sub_parts(&a.im, &b.im) }
+}
+
+fn neg(a: &Value) -> Value {
+    Value { re: sub_parts(&None, &a.re), im: sub_parts(&None, &a.im) }
+}
+
+fn mul(a: &Value, b: &Value) -> Value {
+    Value {
+        re: sub_parts(&mul_parts(&a.re, &b.re), &mul_parts(&a.im, &b.im)),
+        im: add_parts(&mul_parts(&a.re, &b.im), &mul_parts(&a.im, &b.re)),
+    }
+}
+
+fn div(a: &Value, b: &Value) -> Value {
+    let (c, d) = (&b.re, &b.im);
+    match d {
+        None => {
+            let c = c.as_deref().unwrap_or("0");
+            Value { re: div_part(&a.re, c), im: div_part(&a.im, c) }
+        }
+        Some(_) => {
+            let denominator = add_parts(&mul_parts(c, c), &mul_parts(d, d)).unwrap_or_default();
+            Value {
+                re: div_part(&add_parts(&mul_parts(&a.re, c), &mul_parts(&a.im, d)), &denominator),
+                im: div_part(&sub_parts(&mul_parts(&a.im, c), &mul_parts(&a.re, d)), &denominator),
+            }
+        }
+    }
+}
+
+/// Raises to a power by repeated squaring, which keeps the expression much
+/// smaller than repeated multiplication.
+fn pow(base: &Value, n: u32) -> Value {
+    match n {
+        0 => real("1"),
+        1 => base.clone(),
+        n => {
+            let half = pow(base, n / 2);
+            let square = mul(&half, &half);
+            if n % 2 == 0 { square } else { mul(&square, base) }
+        }
+    }
+}
+
+fn modulus(a: &Part, b: &Part) -> Part {
+    call_part("sqrt", &add_parts(&mul_parts(a, a), &mul_parts(b, b)))
+}
+
+/// Half the principal argument of a + ib, from -π/2 to π/2, as `atan((|z| - a) / b)`
+/// by the tangent half-angle formula.
+fn half_argument(a: &Part, b: &Part) -> Part {
+    let shifted = add_parts(b, &Some(expr_text::format_value(CUT_OFFSET)));
+    let ratio = div_part(&sub_parts(&modulus(a, b), a), shifted.as_deref().unwrap_or("0"));
+    call_part("atan", &ratio)
+}
+
+fn call(name: &str, z: &Value) -> Result<Value, String> {
+    let (a, b) = (&z.re, &z.im);
+    // cosh and sinh of the imaginary part, from exponentials
+    let cosh_b = || Some(format!("(exp({0})+exp(-({0})))/2", b.as_deref().unwrap_or("0")));
+    let sinh_b = || Some(format!("(exp({0})-exp(-({0})))/2", b.as_deref().unwrap_or("0")));
+
+    let value = match name {
+        "re" => Value { re: a.clone(), im: None },
+        "im" => Value { re: b.clone(), im: None },
+        "conj" => Value { re: a.clone(), im: sub_parts(&None, b) },
+        "abs" => Value { re: modulus(a, b), im: None },
+        // sqrt(|z|) e^(i arg(z) / 2), which is also needed for negative reals
+        "sqrt" => {
+            let root = call_part("sqrt", &modulus(a, b));
+            let half = half_argument(a, b);
+            Value {
+                re: mul_parts(&root, &call_part("cos", &half)),
+                im: mul_parts(&root, &call_part("sin", &half)),
+            }
+        }
+        // ln|z| + i arg(z)
+        "log" | "ln" => Value {
+            re: call_part("ln", &modulus(a, b)),
+            im: mul_parts(&Some("2".to_string()), &half_argument(a, b)),
+        },
+        _ if b.is_none() => match name {
+            "sinh" => Value { re: Some(format!("(exp({0})-exp(-({0})))/2", a.as_deref().unwrap_or("0"))), im: None },
+            "cosh" => Value { re: Some(format!("(exp({0})+exp(-({0})))/2", a.as_deref().unwrap_or("0"))), im: None },
+            _ => Value { re: call_part(name, a), im: None },
+        },
+        "exp" => {
+            let e = call_part("exp", a);
+            Value {
+                re: mul_parts(&e, &call_part("cos", b)),
+                im: mul_parts(&e, &call_part("sin", b)),
+            }
+        }
+        "sin" => Value {
+            re: mul_parts(&call_part("sin", a), &cosh_b()),
+            im: mul_parts(&call_part("cos", a), &sinh_b()),
+        },
+        "cos" => Value {
+            re: mul_parts(&call_part("cos", a), &cosh_b()),
+            im: sub_parts(&None, &mul_parts(&call_part("sin", a), &sinh_b())),
+        },
+        // sinh(z) = -i sin(iz) and cosh(z) = cos(iz)
+        "sinh" => {
+            let sin = call("sin", &Value { re: sub_parts(&None, b), im: a.clone() })?;
+            Value { re: sin.im, im: sub_parts(&None, &sin.re) }
+        }
+        "cosh" => call("cos", &Value { re: sub_parts(&None, b), im: a.clone() })?,
+        _ => return Err(format!("{} is not supported for complex values", name)),
+    };
+
+    Ok(value)
+}
+
+/// Colour of a value of a complex function. The hue is its argument, red on the
+/// positive reals, and the lightness its modulus, black at zeros and white at poles.
+/// Undefined values are transparent.
+pub fn domain_color(re: f32, im: f32) -> [u8; 4] {
+    if re.is_nan() || im.is_nan() {
+        return [0; 4];
+    }
+
+    let hue = (im.atan2(re) / TAU).rem_euclid(1.0);
+    let lightness = 2.0 / PI * re.hypot(im).atan();
+    let [r, g, b] = hsl_to_rgb(hue, 1.0, lightness);
+
+    let byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [byte(r), byte(g), byte(b), 255]
+}
+
+/// `hue`, `saturation` and `lightness` from 0 to 1.
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let h = hue * 6.0;
+    let x = chroma * (1.0 - (h.rem_euclid(2.0) - 1.0).abs());
+    let [r, g, b] = match h as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    let m = lightness - chroma / 2.0;
+    [r + m, g + m, b + m]
+}
+
+/// Domain colouring of `expression` over `bounds`, as `width` by `height` rgba8 pixels
+/// from the top left. Each part is compiled once by a `RowFunction`, so only parts
+/// depending on both x and y are compiled again for each row.
+///
+/// Contour lines are drawn where the modulus crosses a power of two, and where
+/// the argument crosses a multiple of 30 degrees.
+pub fn domain_coloring(
+    expression: &ComplexExpression,
+    bounds: &GraphingBounds,
+    (xscale, yscale): (AxisScale, AxisScale),
+    width: usize,
+    height: usize,
+    contours: bool,
+) -> Result<Vec<u8>, String> {
+    let dx = (bounds.xbounds.end - bounds.xbounds.start) / width as f32;
+    let dy = (bounds.ybounds.end - bounds.ybounds.start) / height as f32;
+    let xs = (0..width)
+        .map(|i| xscale.inverse(bounds.xbounds.start + (i as f32 + 0.5) * dx))
+        .collect::<Vec<f32>>();
+
+    let mut re_function = RowFunction::new(&expression.re)?;
+    let mut im_function = RowFunction::new(&expression.im)?;
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    // the contour each pixel lies between, as the modulus and argument bands
+    let mut bands = Vec::with_capacity(width * height);
+
+    for j in 0..height {
+        let y = yscale.inverse(bounds.ybounds.end - (j as f32 + 0.5) * dy);
+        let res = re_function.eval(&xs, y)?;
+        let ims = im_function.eval(&xs, y)?;
+
+        for (&re, &im) in res.iter().zip(&ims) {
+            pixels.extend_from_slice(&domain_color(re, im));
+            let argument = (im.atan2(re) / TAU).rem_euclid(1.0);
+            bands.push([re.hypot(im).log2().floor(), (argument * ARGUMENT_CONTOURS).floor()]);
+        }
+    }
+
+    if contours {
+        // darken pixels in a different band to the pixel to their left or above
+        for j in 0..height {
+            for i in 0..width {
+                let k = j * width + i;
+                let differs = |other: usize| bands[other] != bands[k] && !bands[other].iter().any(|b| b.is_nan());
+                if (i > 0 && differs(k - 1)) || (j > 0 && differs(k - width)) {
+                    for c in &mut pixels[k * 4..k * 4 + 3] {
+                        *c /= 2;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lower(text: &str) -> (String, String) {
+        let tokens = expr_text::tokens(text).into_iter().map(|(_, t)| t).collect::<Vec<_>>();
+        let value = Parser { tokens: &tokens, next: 0 }.expression().unwrap();
+        (value.re.unwrap_or_default(), value.im.unwrap_or_default())
+    }
+
+    #[test]
+    fn real_values_have_no_imaginary_part() {
+        assert_eq!(lower("2*3"), ("(2)*(3)".to_string(), String::new()));
+    }
+
+    #[test]
+    fn squares_expand() {
+        assert_eq!(lower("z^2"), ("((x)*(x))-((y)*(y))".to_string(), "((x)*(y))+((y)*(x))".to_string()));
+    }
+
+    #[test]
+    fn i_squared_is_minus_one() {
+        assert_eq!(lower("i*i"), ("(-((1)*(1)))".to_string(), String::new()));
+    }
+
+    #[test]
+    fn roots_and_logarithms_take_the_principal_branch() {
+        let eval = |text: &str, [x, y]: [f32; 2]| {
+            let expression = ComplexExpression::parse(text).unwrap();
+            [eval_row(&expression.re, &[x], y).unwrap()[0], eval_row(&expression.im, &[x], y).unwrap()[0]]
+        };
+        let close = |[a, b]: [f32; 2], [c, d]: [f32; 2]| (a - c).abs() < 1e-5 && (b - d).abs() < 1e-5;
+
+        assert!(close(eval("sqrt(z)", [4.0, 0.0]), [2.0, 0.0]));
+        assert!(close(eval("sqrt(z)", [-4.0, 0.0]), [0.0, 2.0]));
+        assert!(close(eval("sqrt(z)", [0.0, -2.0]), [1.0, -1.0]));
+        assert!(close(eval("sqrt(-1)", [0.0, 0.0]), [0.0, 1.0]));
+        assert!(close(eval("log(z)", [1.0, 0.0]), [0.0, 0.0]));
+        assert!(close(eval("ln(z)", [-1.0, 0.0]), [0.0, PI]));
+        assert!(close(eval("log(z)", [0.0, -1.0]), [0.0, -PI / 2.0]));
+    }
+
+    #[test]
+    fn colors_follow_argument_and_modulus() {
+        assert_eq!(domain_color(1.0, 0.0), [255, 0, 0, 255]);
+        assert_eq!(domain_color(0.0, 0.0), [0, 0, 0, 255]);
+        assert_eq!(domain_color(f32::INFINITY, 0.0), [255, 255, 255, 255]);
+        assert_eq!(domain_color(f32::NAN, 0.0), [0; 4]);
+    }
+}
@@ -0,0 +1,335 @@
+use std::ops::Range;
+
+use crate::expr_text::{self, Token, CONSTANTS, VARIABLE};
+
+/// Names that can't be defined, the variable of curves and the one they're plotted against.
+const RESERVED: [&str; 2] = [VARIABLE, "y"];
+
+/// A variable, `a = 3`, or a function of one variable, `f(x) = x^2 + 1`, entered like a curve.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    /// `None` for variables.
+    pub parameter: Option<String>,
+    pub body: String,
+}
+
+impl Definition {
+    /// The definition on a line, or `None` if the line is an expression of x.
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let tokens = expr_text::tokens(line);
+        let equals = match tokens.iter().position(|(_, token)| *token == Token::Symbol('=')) {
+            Some(equals) => equals,
+            None => return Ok(None),
+        };
+
+        // comparisons, as in x <= 1, aren't definitions
+        let (name, parameter) = match &tokens[..equals] {
+            [(_, Token::Identifier(name, false))] => (*name, None),
+            [
+                (_, Token::Identifier(name, true)),
+                (_, Token::Symbol('(')),
+                (_, Token::Identifier(parameter, false)),
+                (_, Token::Symbol(')')),
+            ] => (*name, Some(parameter.to_string())),
+            _ => return Ok(None),
+        };
+        if matches!(tokens.get(equals + 1), Some((_, Token::Symbol('=')))) {
+            return Ok(None);
+        }
+
+        if RESERVED.contains(&name) || CONSTANTS.contains(&name) {
+            return Err(format!("Can't define '{}' in '{}'", name, line));
+        }
+        if let Some(parameter) = parameter.as_deref().filter(|p| CONSTANTS.contains(p)) {
+            return Err(format!("Can't use the constant '{}' as a parameter in '{}'", parameter, line));
+        }
+
+        let body = line[tokens[equals].0.end..].trim();
+        if body.is_empty() {
+            return Err(format!("Nothing is defined in '{}'", line));
+        }
+
+        Ok(Some(Definition { name: name.to_string(), parameter, body: body.to_string() }))
+    }
+
+    /// Names used by the body, other than its parameter.
+    fn uses(&self) -> impl Iterator<Item = &str> {
+        expr_text::tokens(&self.body).into_iter().filter_map(move |(_, token)| match token {
+            Token::Identifier(name, called) if called || Some(name) != self.parameter.as_deref() => Some(name),
+            _ => None,
+        })
+    }
+}
+
+/// Definitions with their bodies expanded, so they use no other definitions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Definitions {
+    /// Each after the definitions it used.
+    definitions: Vec<Definition>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Visit {
+    New,
+    InProgress,
+    Done,
+}
+
+impl Definitions {
+    /// Resolves the definitions among `lines` against each other, skipping lines that
+    /// aren't definitions. Names may be used before the line defining them.
+    pub fn resolve<'a>(lines: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut parsed: Vec<Definition> = Vec::new();
+        for line in lines {
+            if let Some(definition) = Definition::parse(line)? {
+                if parsed.iter().any(|d| d.name == definition.name) {
+                    return Err(format!("'{}' is defined more than once", definition.name));
+                }
+                parsed.push(definition);
+            }
+        }
+
+        let dependencies = parsed.iter()
+            .map(|definition| definition.uses()
+                .filter_map(|name| parsed.iter().position(|d| d.name == name))
+                .collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        let mut visits = vec![Visit::New; parsed.len()];
+        let mut order = Vec::with_capacity(parsed.len());
+        for i in 0..parsed.len() {
+            if let Err(cycle) = visit(i, &dependencies, &mut visits, &mut Vec::new(), &mut order) {
+                let names = cycle.iter().map(|&i| parsed[i].name.as_str()).collect::<Vec<_>>();
+                return Err(format!("Circular definition: {}", names.join(" -> ")));
+            }
+        }
+
+        let mut definitions = Definitions::default();
+        for i in order {
+            let definition = &parsed[i];
+            let body = definitions.expand_bound(&definition.body, definition.parameter.as_deref())?;
+            definitions.definitions.push(Definition { body, ..definition.clone() });
+        }
+
+        Ok(definitions)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Definition> {
+        self.definitions.iter().find(|d| d.name == name)
+    }
+
+    /// Replaces uses of the definitions in `text` with their bodies.
+    pub fn expand(&self, text: &str) -> Result<String, String> {
+        self.expand_bound(text, None)
+    }
+
+    /// The expression of x graphed for a line. Function definitions graph their body
+    /// and variable definitions their value.
+    pub fn curve_expression(&self, line: &str) -> Result<String, String> {
+        match Definition::parse(line)? {
+            None => self.expand(line),
+            Some(Definition { parameter: None, body, .. }) => self.expand(&body),
+            Some(Definition { parameter: Some(parameter), body, .. }) => {
+                let body = self.expand_bound(&body, Some(&parameter))?;
+                Ok(expr_text::replace(&body, &[(parameter.as_str(), VARIABLE.to_string())]))
+            }
+        }
+    }
+
+    /// Names defined differently here and in `other`, including those defined in only one.
+    /// As bodies are expanded, a definition changes with any definition it uses.
+    pub fn changed(&self, other: &Self) -> Vec<String> {
+        let mut names = Vec::new();
+        for (a, b) in [(self, other), (other, self)] {
+            for definition in a.definitions.iter() {
+                if b.get(&definition.name) != Some(definition) && !names.contains(&definition.name) {
+                    names.push(definition.name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    /// Expands `text`, leaving uses of the parameter `bound` as they are.
+    pub fn expand_bound(&self, text: &str, bound: Option<&str>) -> Result<String, String> {
+        let tokens = expr_text::tokens(text);
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+        let mut k = 0;
+
+        while k < tokens.len() {
+            let (range, token) = (tokens[k].0.clone(), tokens[k].1);
+            k += 1;
+
+            let (name, called) = match token {
+                Token::Identifier(name, called) if called || Some(name) != bound => (name, called),
+                _ => continue,
+            };
+            let definition = match self.get(name) {
+                Some(definition) => definition,
+                None => continue,
+            };
+
+            out.push_str(&text[last..range.start]);
+            last = range.end;
+            match &definition.parameter {
+                None => out.push_str(&bracketed(&definition.body)),
+                Some(_) if !called => return Err(format!("'{}' is a function and needs an argument in '{}'", name, text)),
+                Some(parameter) => {
+                    // the next token opens the argument
+                    let close = closing_bracket(&tokens, k)
+                        .ok_or_else(|| format!("Unmatched bracket after '{}' in '{}'", name, text))?;
+                    if top_level_commas(&tokens[k + 1..close]) > 0 {
+                        return Err(format!("'{}' takes one argument in '{}'", name, text));
+                    }
+
+                    let argument = self.expand_bound(&text[tokens[k].0.end..tokens[close].0.start], bound)?;
+                    let call = expr_text::replace(&definition.body, &[(parameter.as_str(), bracketed(&argument))]);
+                    out.push_str(&bracketed(&call));
+                    last = tokens[close].0.end;
+                    k = close + 1;
+                }
+            }
+        }
+
+        out.push_str(&text[last..]);
+        Ok(out)
+    }
+}
+
+/// Visits definition `i` after those it depends on, adding it to `order`.
+/// Returns the cycle through `path` if one is found.
+fn visit(
+    i: usize,
+    dependencies: &[Vec<usize>],
+    visits: &mut [Visit],
+    path: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+) -> Result<(), Vec<usize>> {
+    match visits[i] {
+        Visit::Done => return Ok(()),
+        Visit::InProgress => {
+            // definitions in progress are exactly those on the path
+            let start = path.iter().position(|&j| j == i).unwrap_or(0);
+            let mut cycle = path[start..].to_vec();
+            cycle.push(i);
+            return Err(cycle);
+        }
+        Visit::New => (),
+    }
+
+    visits[i] = Visit::InProgress;
+    path.push(i);
+    for &j in &dependencies[i] {
+        visit(j, dependencies, visits, path, order)?;
+    }
+    path.pop();
+    visits[i] = Visit::Done;
+    order.push(i);
+    Ok(())
+}
+
+/// Index of the bracket closing the one at `open`.
+fn closing_bracket(tokens: &[(Range<usize>, Token)], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (k, (_, token)) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::Symbol('(') => depth += 1,
+            Token::Symbol(')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(k);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+fn bracketed(text: &str) -> String {
+    format!("({})", text)
+}
+
+fn top_level_commas(tokens: &[(Range<usize>, Token)]) -> usize {
+    let mut depth = 0;
+    tokens.iter()
+        .filter(|(_, token)| {
+            match token {
+                Token::Symbol('(') => depth += 1,
+                Token::Symbol(')') => depth -= 1,
+                _ => (),
+            }
+            depth == 0 && *token == Token::Symbol(',')
+        })
+        .count()
+}
+
+/// Whether the line defines a variable, which has nothing to graph.
+pub fn defines_variable(line: &str) -> bool {
+    matches!(Definition::parse(line), Ok(Some(Definition { parameter: None, .. })))
+}
+
+/// Whether `text` uses any of `names`, including as the name it defines.
+pub fn uses_any(text: &str, names: &[String]) -> bool {
+    expr_text::tokens(text).into_iter().any(|(_, token)| match token {
+        Token::Identifier(name, _) => names.iter().any(|n| n == name),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::eval_row;
+
+    fn value(definitions: &Definitions, line: &str, x: f32) -> f32 {
+        let expression = definitions.curve_expression(line).unwrap();
+        eval_row(&expression, &[x], 0.0).unwrap()[0]
+    }
+
+    #[test]
+    fn definitions_resolve_in_any_order() {
+        let lines = ["g(x) = f(x - a)", "a = 3", "f(x) = x^2 + 1"];
+        let definitions = Definitions::resolve(lines).unwrap();
+
+        assert_eq!(value(&definitions, "g(x) + 1", 2.0), 3.0);
+        assert_eq!(value(&definitions, "g(x) = f(x - a)", 5.0), 5.0);
+        assert_eq!(value(&definitions, "a = 3", 0.0), 3.0);
+        assert_eq!(value(&definitions, "f(g(x))", 3.0), 2.0);
+    }
+
+    #[test]
+    fn parameters_shadow_variables() {
+        let definitions = Definitions::resolve(["a = 3", "f(a) = 2 * a"]).unwrap();
+        assert_eq!(value(&definitions, "f(a + x)", 1.0), 8.0);
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let error = Definitions::resolve(["f(x) = g(x)", "g(x) = f(x) + 1"]).unwrap_err();
+        assert!(error.contains("f -> g -> f"), "{}", error);
+        assert!(Definitions::resolve(["a = a + 1"]).is_err());
+        assert!(Definitions::resolve(["a = 1", "a = 2"]).is_err());
+    }
+
+    #[test]
+    fn comparisons_and_reserved_names_are_not_definitions() {
+        assert_eq!(Definition::parse("x <= 1"), Ok(None));
+        assert_eq!(Definition::parse("sin(x)"), Ok(None));
+        assert!(Definition::parse("y = x").is_err());
+        assert!(defines_variable("a = 3"));
+        assert!(!defines_variable("f(x) = 3"));
+    }
+
+    #[test]
+    fn changes_spread_to_users() {
+        let before = Definitions::resolve(["a = 3", "g(x) = x + a", "h(x) = x"]).unwrap();
+        let after = Definitions::resolve(["a = 4", "g(x) = x + a", "h(x) = x"]).unwrap();
+
+        assert_eq!(after.changed(&before), vec!["a".to_string(), "g".to_string()]);
+        assert!(uses_any("g(x) * 2", &after.changed(&before)));
+        assert!(!uses_any("h(x)", &after.changed(&before)));
+    }
+}
@@ -0,0 +1,390 @@
+use serde::{Deserialize, Serialize};
+
+use super::{eval_expression, Bounds, CompiledExpression, Expression, GraphingBounds, MidAxisInfo, XyFunction};
+use crate::expr_text::{self, Token};
+
+/// Glyphs drawn per mid axis separation, along each axis.
+pub const FIELD_SUBDIVISIONS: f32 = 2.0;
+
+/// Length of a glyph as a fraction of the spacing between glyphs.
+const GLYPH_LENGTH: f32 = 0.8;
+
+/// The second variable of field expressions, substituted one row at a time.
+pub const FIELD_VARIABLE: &str = "y";
+
+/// A function of x and y, drawn as glyphs on a grid.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FieldKind {
+    /// `dy/dx = f(x, y)`, drawn as short segments along the slope at each point.
+    Slope(String),
+    /// `(P(x, y), Q(x, y))`, drawn as arrows.
+    Vector(String, String),
+}
+
+/// How the magnitudes of a vector field are shown.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MagnitudeStyle {
+    /// Arrows are scaled by their magnitude.
+    Length,
+    /// Arrows are all the same length, and brighter the larger their magnitude.
+    Color,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Glyph {
+    /// Centre of the glyph in graph space.
+    pub position: [f32; 2],
+    /// Vector from the tail to the tip of the glyph in graph space.
+    pub direction: [f32; 2],
+    /// Magnitude relative to the largest in the field, from 0 to 1.
+    /// Always 1 for slope fields.
+    pub magnitude: f32,
+}
+
+impl FieldKind {
+    /// Parses a slope field, `f(x, y)` or `dy/dx = f(x, y)`.
+    pub fn slope(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        match text.split_once('=') {
+            Some((lhs, rhs)) if lhs.replace(' ', "") == "dy/dx" => Ok(FieldKind::Slope(rhs.trim().to_string())),
+            Some(_) => Err(format!("Expected dy/dx = f(x, y), found '{}'", text)),
+            None => Ok(FieldKind::Slope(text.to_string())),
+        }
+    }
+
+    /// Parses a vector field, `P, Q` or `(P, Q)`.
+    pub fn vector(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let unbracketed = text.strip_prefix('(')
+            .and_then(|t| t.strip_suffix(')'))
+            .filter(|inner| split_pair(inner).is_some());
+
+        match split_pair(unbracketed.unwrap_or(text)) {
+            Some((p, q)) => Ok(FieldKind::Vector(p.to_string(), q.to_string())),
+            None => Err(format!("Expected a vector field P, Q, found '{}'", text)),
+        }
+    }
+
+    pub fn expressions(&self) -> Vec<&str> {
+        match self {
+            FieldKind::Slope(f) => vec![f.as_str()],
+            FieldKind::Vector(p, q) => vec![p.as_str(), q.as_str()],
+        }
+    }
+
+    /// Applies `f` to each expression of the field, stopping at the first error.
+    pub fn try_map(&self, f: impl Fn(&str) -> Result<String, String>) -> Result<Self, String> {
+        Ok(match self {
+            FieldKind::Slope(slope) => FieldKind::Slope(f(slope)?),
+            FieldKind::Vector(p, q) => FieldKind::Vector(f(p)?, f(q)?),
+        })
+    }
+
+    /// Checks that each expression parses as a function of x and y.
+    pub fn check(&self) -> Result<(), String> {
+        for text in self.expressions() {
+            XyFunction::parse(text)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for MagnitudeStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "length" => Ok(MagnitudeStyle::Length),
+            "color" | "colour" => Ok(MagnitudeStyle::Color),
+            _ => Err(format!("Unknown magnitude style '{}', expected length or color", s)),
+        }
+    }
+}
+
+/// Splits `P, Q` at its only comma outside of brackets.
+fn split_pair(text: &str) -> Option<(&str, &str)> {
+    let mut depth = 0i32;
+    let mut comma = None;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return None,
+            ')' => depth -= 1,
+            ',' if depth == 0 && comma.is_some() => return None,
+            ',' if depth == 0 => comma = Some(i),
+            _ => (),
+        }
+    }
+
+    let i = comma.filter(|_| depth == 0)?;
+    let (p, q) = (text[..i].trim(), text[i + 1..].trim());
+    if p.is_empty() || q.is_empty() {
+        None
+    } else {
+        Some((p, q))
+    }
+}
+
+/// Evaluates a function of x and y along a row, with y fixed as a constant.
+pub fn eval_row(text: &str, xs: &[f32], y: f32) -> Result<Vec<f32>, String> {
+    Ok(eval_expression(&parse_row(text, y)?, xs))
+}
+
+/// A function of x and y, compiled to be evaluated many times.
+///
+/// mathjit compiles functions of x alone, so y is substituted as a constant and the
+/// function is compiled again for each y it is evaluated at, keeping the last.
+/// Evaluating row by row compiles once per row, and a function of only one of x
+/// and y is compiled just once.
+pub struct RowFunction {
+    text: String,
+    kind: RowKind,
+}
+
+enum RowKind {
+    /// Compiled once, as a function of x.
+    OfX(CompiledExpression),
+    /// Compiled once, with y renamed to x.
+    OfY(CompiledExpression),
+    /// The last row compiled, and its y.
+    Both(Option<(f32, CompiledExpression)>),
+}
+
+impl RowFunction {
+    pub fn new(text: &str) -> Result<Self, String> {
+        // checks the expression parses before any row is compiled
+        parse_row(text, 1.0)?;
+
+        let tokens = expr_text::tokens(text);
+        let uses = |name: &str| tokens.iter().any(|(_, token)| *token == Token::Identifier(name, false));
+        let kind = match (uses(expr_text::VARIABLE), uses(FIELD_VARIABLE)) {
+            (_, false) => RowKind::OfX(CompiledExpression::new(&parse_row(text, 0.0)?)),
+            (false, true) => {
+                let renamed = expr_text::replace(text, &[(FIELD_VARIABLE, expr_text::VARIABLE.to_string())]);
+                RowKind::OfY(CompiledExpression::new(&parse_row(&renamed, 0.0)?))
+            }
+            (true, true) => RowKind::Both(None),
+        };
+
+        Ok(RowFunction { text: text.to_string(), kind })
+    }
+
+    /// Values along the row at `y`.
+    pub fn eval(&mut self, xs: &[f32], y: f32) -> Result<Vec<f32>, String> {
+        Ok(match &mut self.kind {
+            RowKind::OfX(compiled) => compiled.eval(xs),
+            RowKind::OfY(compiled) => vec![compiled.eval_at(y); xs.len()],
+            RowKind::Both(row) => {
+                if !matches!(row, Some((last, _)) if last.to_bits() == y.to_bits()) {
+                    *row = Some((y, CompiledExpression::new(&parse_row(&self.text, y)?)));
+                }
+                row.as_ref().map_or_else(Vec::new, |(_, compiled)| compiled.eval(xs))
+            }
+        })
+    }
+}
+
+fn parse_row(text: &str, y: f32) -> Result<Expression, String> {
+    let substituted = expr_text::substitute(text, &[(FIELD_VARIABLE, y as f64)]);
+    // report errors in the expression as written
+    Expression::parse(&substituted).map_err(|_| format!("Error in expression '{}'", text))
+}
+
+/// Positions in graph space halfway between multiples of `spacing`, within `bounds`.
+/// Glyphs sit between the grid lines, and stay put as the view is panned.
+pub fn field_positions(bounds: Bounds, spacing: f32) -> Vec<f32> {
+    let first = (bounds.start / spacing - 0.5).ceil() as i64;
+    let last = (bounds.end / spacing - 0.5).floor() as i64;
+    (first..=last).map(|k| (k as f32 + 0.5) * spacing).collect()
+}
+
+/// Glyphs of the field within `bounds`, spaced by a fraction of the mid axis separation.
+/// Points where the field is undefined or zero have no glyph.
+pub fn field_glyphs(
+    kind: &FieldKind,
+    bounds: &GraphingBounds,
+    info: &MidAxisInfo,
+    magnitude_style: MagnitudeStyle,
+) -> Result<Vec<Glyph>, String> {
+    let xspacing = info.xseparation / FIELD_SUBDIVISIONS;
+    let yspacing = info.yseparation / FIELD_SUBDIVISIONS;
+    let length = xspacing.min(yspacing) * GLYPH_LENGTH;
+
+    let ts = field_positions(bounds.xbounds, xspacing);
+    let xs = ts.iter().map(|&t| info.xscale.inverse(t)).collect::<Vec<f32>>();
+    let functions = kind.expressions().into_iter()
+        .map(XyFunction::parse)
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut glyphs = Vec::new();
+
+    for t in field_positions(bounds.ybounds, yspacing) {
+        let y = info.yscale.inverse(t);
+
+        for (&s, &x) in ts.iter().zip(&xs) {
+            let (u, v) = match &functions[..] {
+                [f] => (1.0, f.eval(x, y)),
+                [p, q] => (p.eval(x, y), q.eval(x, y)),
+                _ => unreachable!(),
+            };
+
+            // the direction on screen, which is bent by non-linear axes
+            let gx = u * info.xscale.derivative(x);
+            let gy = v * info.yscale.derivative(y);
+            let norm = (gx * gx + gy * gy).sqrt();
+            if !(norm.is_finite() && norm > 0.0) {
+                continue;
+            }
+
+            let magnitude = match kind {
+                FieldKind::Slope(_) => 1.0,
+                FieldKind::Vector(..) => (u * u + v * v).sqrt(),
+            };
+
+            glyphs.push(Glyph {
+                position: [s, t],
+                direction: [gx / norm, gy / norm],
+                magnitude,
+            });
+        }
+    }
+
+    let largest = glyphs.iter()
+        .map(|g| g.magnitude)
+        .filter(|m| m.is_finite())
+        .fold(0.0f32, f32::max);
+
+    for glyph in glyphs.iter_mut() {
+        glyph.magnitude = if largest > 0.0 { (glyph.magnitude / largest).min(1.0) } else { 1.0 };
+
+        let scale = match (kind, magnitude_style) {
+            (FieldKind::Vector(..), MagnitudeStyle::Length) => length * glyph.magnitude,
+            _ => length,
+        };
+        glyph.direction = [glyph.direction[0] * scale, glyph.direction[1] * scale];
+    }
+
+    Ok(glyphs)
+}
+
+/// Pairs of endpoints of the lines drawing a glyph, in graph space.
+/// Arrows have a head `head` long, drawn as two lines from the tip.
+pub fn glyph_segments(glyph: &Glyph, arrow: bool, head: f32) -> Vec<[f32; 2]> {
+    let [x, y] = glyph.position;
+    let [dx, dy] = glyph.direction;
+    let tail = [x - dx / 2.0, y - dy / 2.0];
+    let tip = [x + dx / 2.0, y + dy / 2.0];
+
+    let mut segments = vec![tail, tip];
+    let length = (dx * dx + dy * dy).sqrt();
+    if arrow && length > 0.0 {
+        let (ux, uy) = (dx / length, dy / length);
+        for side in [-1.0, 1.0] {
+            // half the width of the head either side of the shaft
+            let wing = [
+                tip[0] - head * ux - side * head * 0.5 * uy,
+                tip[1] - head * uy + side * head * 0.5 * ux,
+            ];
+            segments.extend_from_slice(&[tip, wing]);
+        }
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::sampling::COMPILES;
+    use crate::geometry::{mid_axis_info, AxisScale, GraphingView, TickMode};
+    use proptest::prelude::*;
+
+    #[test]
+    fn slopes_parse_with_or_without_lhs() {
+        assert_eq!(FieldKind::slope("dy/dx = x*y"), Ok(FieldKind::Slope("x*y".to_string())));
+        assert_eq!(FieldKind::slope("x - y"), Ok(FieldKind::Slope("x - y".to_string())));
+        assert!(FieldKind::slope("y = x").is_err());
+    }
+
+    #[test]
+    fn vectors_split_at_top_level_comma() {
+        let vector = |p: &str, q: &str| Ok(FieldKind::Vector(p.to_string(), q.to_string()));
+        assert_eq!(FieldKind::vector("-y, x"), vector("-y", "x"));
+        assert_eq!(FieldKind::vector("(-y, x)"), vector("-y", "x"));
+        assert_eq!(FieldKind::vector("(x+1)*y, x"), vector("(x+1)*y", "x"));
+        assert_eq!(FieldKind::vector("(x), (y)"), vector("(x)", "(y)"));
+        assert!(FieldKind::vector("x").is_err());
+        assert!(FieldKind::vector("x, y, 1").is_err());
+        assert!(FieldKind::vector("(x, y").is_err());
+    }
+
+    #[test]
+    fn arrows_have_heads_at_the_tip() {
+        let glyph = Glyph { position: [1.0, 1.0], direction: [2.0, 0.0], magnitude: 1.0 };
+        assert_eq!(glyph_segments(&glyph, false, 0.5), vec![[0.0, 1.0], [2.0, 1.0]]);
+
+        let segments = glyph_segments(&glyph, true, 0.5);
+        assert_eq!(segments.len(), 6);
+        assert_eq!(segments[2], [2.0, 1.0]);
+        assert_eq!(segments[3], [1.5, 0.75]);
+        assert_eq!(segments[5], [1.5, 1.25]);
+    }
+
+    #[test]
+    fn rows_match_substituted_expressions() {
+        let xs = [-1.0, 0.5, 2.0];
+        for text in ["x * y", "x + 1", "y * y", "{x < 0: y, x >= 0: x}"] {
+            let mut function = RowFunction::new(text).unwrap();
+            for y in [-2.0, 0.0, 3.0] {
+                assert_eq!(function.eval(&xs, y), eval_row(text, &xs, y), "{} at y = {}", text, y);
+            }
+        }
+        assert!(RowFunction::new("x +").is_err());
+    }
+
+    #[test]
+    fn glyph_rows_are_not_recompiled() {
+        let bounds = GraphingBounds {
+            xbounds: Bounds { start: -2.0, end: 2.0 },
+            ybounds: Bounds { start: -2.0, end: 2.0 },
+        };
+        let view = GraphingView { centre: [0.0, 0.0], scale: 2.0, xscale: AxisScale::Linear, yscale: AxisScale::Linear };
+        let info = mid_axis_info(&bounds, &view, TickMode::Decimal, TickMode::Decimal);
+
+        COMPILES.with(|compiles| compiles.set(0));
+        let kind = FieldKind::Vector("x*y".to_string(), "y - x".to_string());
+        let glyphs = field_glyphs(&kind, &bounds, &info, MagnitudeStyle::Length).unwrap();
+
+        assert!(glyphs.len() > 4);
+        for glyph in glyphs.iter() {
+            let [x, y] = glyph.position;
+            let [dx, dy] = glyph.direction;
+            // directions are scaled to the glyph's length, so only their angle is compared
+            assert!((dx * (y - x) - dy * (x * y)).abs() < 1e-4, "{:?}", glyph);
+            assert!(dx * (x * y) + dy * (y - x) > 0.0, "{:?}", glyph);
+        }
+        assert_eq!(COMPILES.with(|compiles| compiles.get()), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn positions_fill_bounds(start in -1e3f32..1e3, width in 0.1f32..100.0, e in -1i32..3) {
+            let spacing = 10.0f32.powi(e);
+            let bounds = Bounds { start, end: start + width };
+            let positions = field_positions(bounds, spacing);
+
+            let slack = spacing * 1e-3;
+            for t in positions.iter() {
+                prop_assert!(bounds.start - slack <= *t && *t <= bounds.end + slack, "{} outside {:?}", t, bounds);
+            }
+            for w in positions.windows(2) {
+                prop_assert!((w[1] - w[0] - spacing).abs() < spacing * 1e-2);
+            }
+            // no gap bigger than the spacing at either end
+            let first = positions.first().copied().unwrap_or(bounds.end);
+            let last = positions.last().copied().unwrap_or(bounds.start);
+            prop_assert!(first - bounds.start <= spacing * 1.01);
+            prop_assert!(bounds.end - last <= spacing * 1.01);
+        }
+    }
+}
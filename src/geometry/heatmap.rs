@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+
+use super::{eval_row, AxisScale, GraphingBounds, RowFunction};
+
+/// Colours values of a heatmap from the low to the high end of its range.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    /// Blue below zero and red above, for values of either sign.
+    Diverging,
+}
+
+/// sRGB colours at evenly spaced points along each colormap.
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84], [71, 44, 122], [59, 81, 139], [44, 113, 142], [33, 144, 141],
+    [39, 173, 129], [92, 200, 99], [170, 220, 50], [253, 231, 37],
+];
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4], [28, 16, 68], [79, 18, 123], [129, 37, 129], [181, 54, 122],
+    [229, 80, 100], [251, 135, 97], [254, 194, 135], [252, 253, 191],
+];
+const DIVERGING: [[u8; 3]; 9] = [
+    [5, 48, 97], [33, 102, 172], [67, 147, 195], [146, 197, 222], [247, 247, 247],
+    [244, 165, 130], [214, 96, 77], [178, 24, 43], [103, 0, 31],
+];
+
+impl Colormap {
+    /// sRGB colour from 0 to 1 at `t`, which is clamped to 0 to 1.
+    pub fn color(self, t: f32) -> [f32; 3] {
+        let stops = match self {
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Diverging => &DIVERGING,
+        };
+
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) } * (stops.len() - 1) as f32;
+        let i = (t as usize).min(stops.len() - 2);
+        let f = t - i as f32;
+        [0, 1, 2].map(|c| (stops[i][c] as f32 * (1.0 - f) + stops[i + 1][c] as f32 * f) / 255.0)
+    }
+
+    /// The range of `values` to colour. Diverging colormaps are centred on zero.
+    pub fn fit_range(self, [low, high]: [f32; 2]) -> [f32; 2] {
+        match self {
+            Colormap::Diverging => {
+                let m = low.abs().max(high.abs());
+                [-m, m]
+            }
+            _ => [low, high],
+        }
+    }
+
+    /// A `height` pixel tall strip of the colormap, as rgba8 from the high end at the top.
+    pub fn strip(self, height: usize) -> Vec<u8> {
+        (0..height)
+            .flat_map(|j| {
+                let [r, g, b] = self.color(1.0 - (j as f32 + 0.5) / height as f32);
+                [r, g, b, 1.0].map(|c| (c * 255.0).round() as u8)
+            })
+            .collect()
+    }
+}
+
+impl std::str::FromStr for Colormap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viridis" => Ok(Colormap::Viridis),
+            "magma" => Ok(Colormap::Magma),
+            "diverging" => Ok(Colormap::Diverging),
+            _ => Err(format!("Unknown colormap '{}', expected viridis, magma or diverging", s)),
+        }
+    }
+}
+
+/// Parses `f(x, y)` or `z = f(x, y)`, as drawn by heatmaps and surfaces,
+/// with `expand` applied to `f(x, y)`.
+pub fn parse_xy_function(text: &str, expand: impl Fn(&str) -> Result<String, String>) -> Result<String, String> {
+    let text = text.trim();
+    let expression = expand(match text.split_once('=') {
+        Some((lhs, rhs)) if lhs.trim() == "z" => rhs.trim(),
+        Some(_) => return Err(format!("Expected z = f(x, y), found '{}'", text)),
+        None => text,
+    })?;
+
+    eval_row(&expression, &[], 0.0)?;
+    Ok(expression)
+}
+
+/// Values of a function of x and y at the centres of a `width` by `height` grid of
+/// cells covering `bounds`, from the top left.
+#[derive(Clone, Debug)]
+pub struct Grid {
+    pub values: Vec<f32>,
+    pub width: usize,
+    pub height: usize,
+    pub bounds: GraphingBounds,
+}
+
+impl Grid {
+    /// Evaluates `expression` row by row. It is compiled once by a `RowFunction`,
+    /// or once per row if it depends on both x and y.
+    pub fn sample(
+        expression: &str,
+        bounds: &GraphingBounds,
+        (xscale, yscale): (AxisScale, AxisScale),
+        width: usize,
+        height: usize,
+    ) -> Result<Self, String> {
+        let mut grid = Grid { values: Vec::with_capacity(width * height), width, height, bounds: bounds.clone() };
+        let xs = (0..width)
+            .map(|i| xscale.inverse(grid.point(i as f32, 0.0)[0]))
+            .collect::<Vec<f32>>();
+
+        let mut function = RowFunction::new(expression)?;
+        for j in 0..height {
+            let y = yscale.inverse(grid.point(0.0, j as f32)[1]);
+            grid.values.extend(function.eval(&xs, y)?);
+        }
+
+        Ok(grid)
+    }
+
+    /// The graph space position of the centre of cell `(i, j)`, interpolated between cells.
+    pub fn point(&self, i: f32, j: f32) -> [f32; 2] {
+        let (xbounds, ybounds) = (self.bounds.xbounds, self.bounds.ybounds);
+        [
+            xbounds.start + (i + 0.5) * (xbounds.end - xbounds.start) / self.width as f32,
+            ybounds.end - (j + 0.5) * (ybounds.end - ybounds.start) / self.height as f32,
+        ]
+    }
+
+    /// Smallest and largest finite values, or `None` if there are none.
+    pub fn range(&self) -> Option<[f32; 2]> {
+        self.values.iter()
+            .filter(|v| v.is_finite())
+            .fold(None, |range, &v| match range {
+                None => Some([v, v]),
+                Some([low, high]) => Some([low.min(v), high.max(v)]),
+            })
+    }
+
+    /// Colours of each cell as rgba8. Undefined values are transparent.
+    pub fn colors(&self, colormap: Colormap, [low, high]: [f32; 2]) -> Vec<u8> {
+        let span = if high > low { high - low } else { 1.0 };
+        self.values.iter()
+            .flat_map(|&v| {
+                if v.is_nan() {
+                    return [0; 4];
+                }
+                let [r, g, b] = colormap.color((v - low) / span);
+                [r, g, b, 1.0].map(|c| (c * 255.0).round() as u8)
+            })
+            .collect()
+    }
+
+    /// The curve where the function equals `level`, as pairs of endpoints in graph space,
+    /// by marching squares between the cell centres.
+    pub fn contour(&self, level: f32) -> Vec<[f32; 2]> {
+        let mut endpoints = Vec::new();
+        let value = |i: usize, j: usize| self.values[j * self.width + i];
+
+        for j in 0..self.height.saturating_sub(1) {
+            for i in 0..self.width.saturating_sub(1) {
+                // corners clockwise from the top left, and the edges leaving each
+                let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+                let values = corners.map(|(i, j)| value(i, j));
+                if values.iter().any(|v| !v.is_finite()) {
+                    continue;
+                }
+
+                let mut crossings = Vec::with_capacity(4);
+                for edge in 0..4 {
+                    let (a, b) = (values[edge], values[(edge + 1) % 4]);
+                    if (a >= level) != (b >= level) {
+                        let t = (level - a) / (b - a);
+                        let (ai, aj) = corners[edge];
+                        let (bi, bj) = corners[(edge + 1) % 4];
+                        let lerp = |a: usize, b: usize| a as f32 + (b as f32 - a as f32) * t;
+                        crossings.push(self.point(lerp(ai, bi), lerp(aj, bj)));
+                    }
+                }
+
+                match crossings[..] {
+                    [a, b] => endpoints.extend([a, b]),
+                    [top, right, bottom, left] => {
+                        // a saddle, where the corners on the same side as the centre
+                        // value are joined through the middle of the cell
+                        let centre = values.iter().sum::<f32>() / 4.0;
+                        if (centre >= level) == (values[0] >= level) {
+                            endpoints.extend([top, right, bottom, left]);
+                        } else {
+                            endpoints.extend([left, top, right, bottom]);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        endpoints
+    }
+}
+
+/// Where to label a contour, the middle of its segment nearest `centre`.
+pub fn contour_label_position(endpoints: &[[f32; 2]], centre: [f32; 2]) -> Option<[f32; 2]> {
+    let distance = |[x, y]: [f32; 2]| (x - centre[0]).powi(2) + (y - centre[1]).powi(2);
+
+    endpoints.chunks_exact(2)
+        .map(|line| [(line[0][0] + line[1][0]) / 2.0, (line[0][1] + line[1][1]) / 2.0])
+        .min_by(|a, b| distance(*a).total_cmp(&distance(*b)))
+}
+
+/// A short label for a value of a heatmap.
+pub fn format_level(value: f32) -> String {
+    let magnitude = value.abs();
+    if magnitude != 0.0 && !(1e-3..1e4).contains(&magnitude) {
+        format!("{:.2e}", value)
+    } else {
+        let text = format!("{:.3}", value);
+        let text = text.trim_end_matches('0').trim_end_matches('.');
+        if text == "-0" { "0".to_string() } else { text.to_string() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Bounds;
+
+    fn bounds() -> GraphingBounds {
+        GraphingBounds {
+            xbounds: Bounds { start: 0.0, end: 4.0 },
+            ybounds: Bounds { start: 0.0, end: 4.0 },
+        }
+    }
+
+    #[test]
+    fn colormaps_span_their_stops() {
+        assert_eq!(Colormap::Viridis.color(0.0), VIRIDIS[0].map(|c| c as f32 / 255.0));
+        assert_eq!(Colormap::Magma.color(2.0), MAGMA[8].map(|c| c as f32 / 255.0));
+        assert_eq!(Colormap::Diverging.fit_range([-1.0, 3.0]), [-3.0, 3.0]);
+    }
+
+    #[test]
+    fn functions_of_xy_parse_with_or_without_lhs() {
+        let unchanged = |text: &str| Ok::<_, String>(text.to_string());
+        assert_eq!(parse_xy_function("z = x", unchanged), Ok("x".to_string()));
+        assert!(parse_xy_function("y = x", unchanged).is_err());
+    }
+
+    #[test]
+    fn contours_of_a_plane_are_straight() {
+        // the value is x at the centre of each cell
+        let grid = Grid {
+            values: (0..16).map(|k| (k % 4) as f32 + 0.5).collect(),
+            width: 4,
+            height: 4,
+            bounds: bounds(),
+        };
+
+        let endpoints = grid.contour(2.0);
+        assert_eq!(endpoints.len(), 6);
+        for [x, _] in endpoints {
+            assert!((x - 2.0).abs() < 1e-5, "{}", x);
+        }
+    }
+
+    #[test]
+    fn levels_format_briefly() {
+        assert_eq!(format_level(0.5), "0.5");
+        assert_eq!(format_level(-0.0001), "-1.00e-4");
+        assert_eq!(format_level(2.0), "2");
+    }
+}
@@ -0,0 +1,326 @@
+//! Graph geometry independent of the renderer.
+//! Produces plain polylines, line segments and grid line positions in graph space,
+//! which are turned into meshes by `gen_mesh` and into files by `export`.
+
+use serde::{Deserialize, Serialize};
+
+mod axes;
+mod complex;
+mod definitions;
+mod field;
+mod heatmap;
+mod ode;
+mod piecewise;
+mod sampling;
+mod sequence;
+mod stroke;
+mod surface;
+mod ticks;
+mod xy_function;
+
+pub use axes::{
+    axis_line_endpoints, axis_line_positions, finer_axis_diff, log_axis_lines, main_axis_endpoints,
+    mid_axis_count, mid_axis_diff, mid_axis_info, MidAxisInfo, MinAxisInfo, MIN_MID_LINES,
+};
+pub use complex::{domain_color, domain_coloring, ComplexExpression, COMPLEX_VARIABLE};
+pub use definitions::{defines_variable, uses_any, Definition, Definitions};
+pub use field::{
+    eval_row, field_glyphs, field_positions, glyph_segments, FieldKind, Glyph, MagnitudeStyle, RowFunction,
+    FIELD_SUBDIVISIONS, FIELD_VARIABLE,
+};
+pub use heatmap::{contour_label_position, format_level, parse_xy_function, Colormap, Grid};
+pub use ode::trajectory;
+pub use piecewise::Domain;
+pub use sampling::{
+    curve_pieces, eval_expression, iterate_expression, sample_expression, sample_polylines,
+    sample_xs, CompiledExpression, Expression,
+};
+pub use sequence::{
+    cobweb_points, parse_recurrence, parse_sequence, sequence_points, MAX_SEQUENCE_POINTS, PREVIOUS_TERM,
+    SEQUENCE_VARIABLE,
+};
+pub use stroke::{
+    arc_lengths, normals, segment_normals, stroke_polyline, stroke_strip,
+    CapStyle, FallbackJoin, JoinStyle, StrokeStyle, StrokeVertex,
+};
+pub use surface::{surface_geometry, SurfaceGeometry, SURFACE_HEIGHT};
+pub use ticks::TickMode;
+pub use xy_function::XyFunction;
+
+/// Only renders functions within these xbounds.
+/// May have y bounds in the future.
+#[derive(Clone, Debug)]
+pub struct GraphingBounds {
+    pub xbounds: Bounds,
+    pub ybounds: Bounds,
+}
+
+/// `centre` and `scale` are in graph space, after each axis has been
+/// transformed by its `AxisScale`.
+#[derive(Clone, Debug)]
+pub struct GraphingView {
+    pub centre: [f32; 2],
+    pub scale: f32,
+    pub xscale: AxisScale,
+    pub yscale: AxisScale,
+}
+
+/// Maps values on an axis to graph space.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AxisScale {
+    Linear,
+    Log10,
+    Ln,
+    /// Logarithmic away from zero, linear within `threshold` of zero.
+    Symlog { threshold: f32 },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Bounds {
+    pub start: f32,
+    pub end: f32,
+}
+
+/// The scale is half the magnitude of the total x range visible.
+/// E.g x is between -5 and 5 when starting if the default scale is 5
+pub const DEFAULT_SCALE: f32 = 5.0;
+
+// Pregenerate meshes this factor outside the current window view.
+pub const PREGENERATE_DISTANCE_FACTOR: f32 = 2.0;
+
+// Relative slack when comparing bounds, so rounding doesn't regenerate
+// freshly generated bounds every frame.
+const REGENERATE_TOLERANCE: f32 = 1e-3;
+
+impl GraphingView {
+    /// Bounds visible in a window of `width` by `height` logical pixels.
+    pub fn visible_bounds(&self, width: f32, height: f32) -> GraphingBounds {
+        let dy = self.scale * height / width;
+        GraphingBounds {
+            xbounds: Bounds {
+                start: self.centre[0] - self.scale,
+                end: self.centre[0] + self.scale,
+            },
+            ybounds: Bounds {
+                start: self.centre[1] - dy,
+                end: self.centre[1] + dy,
+            },
+        }
+    }
+
+    /// The point in graph space under a pixel of a window of `width` by `height`
+    /// logical pixels, with the pixel measured from the bottom left.
+    pub fn graph_point(&self, [px, py]: [f32; 2], width: f32, height: f32) -> [f32; 2] {
+        let units_per_pixel = 2.0 * self.scale / width;
+        [
+            self.centre[0] + (px - width / 2.0) * units_per_pixel,
+            self.centre[1] + (py - height / 2.0) * units_per_pixel,
+        ]
+    }
+
+    /// Bounds to generate meshes within, `PREGENERATE_DISTANCE_FACTOR` times
+    /// the size of the visible bounds so small pans don't regenerate them.
+    pub fn pregenerated_bounds(&self, width: f32, height: f32) -> GraphingBounds {
+        let visible = self.visible_bounds(width, height);
+        let grow = |b: Bounds| {
+            let centre = b.centre();
+            let d = (b.end - b.start) * PREGENERATE_DISTANCE_FACTOR / 2.0;
+            Bounds { start: centre - d, end: centre + d }
+        };
+
+        GraphingBounds {
+            xbounds: grow(visible.xbounds),
+            ybounds: grow(visible.ybounds),
+        }
+    }
+}
+
+impl GraphingBounds {
+    /// Whether meshes generated within these bounds no longer suit the `visible` bounds,
+    /// either because part of the view is outside them or because the view has
+    /// zoomed in far enough that they are needlessly large.
+    pub fn need_regenerating(&self, visible: &GraphingBounds) -> bool {
+        let outgrown = |generated: Bounds, visible: Bounds|
+            visible.start < generated.start
+            || visible.end > generated.end
+            || (visible.end - visible.start) * PREGENERATE_DISTANCE_FACTOR
+                < (generated.end - generated.start) * (1.0 - REGENERATE_TOLERANCE);
+
+        outgrown(self.xbounds, visible.xbounds) || outgrown(self.ybounds, visible.ybounds)
+    }
+}
+
+impl AxisScale {
+    pub fn is_linear(self) -> bool {
+        self == AxisScale::Linear
+    }
+
+    /// Maps a value to graph space.
+    /// Values outside the domain of the logarithm map to NaN or negative infinity.
+    pub fn forward(self, v: f32) -> f32 {
+        match self {
+            AxisScale::Linear => v,
+            AxisScale::Log10 => v.log10(),
+            AxisScale::Ln => v.ln(),
+            AxisScale::Symlog { threshold } => v.signum() * (v.abs() / threshold).ln_1p(),
+        }
+    }
+
+    /// Whether a value is within the domain of the scale.
+    /// Logarithmic scales have no zero or negative values.
+    pub fn contains(self, v: f32) -> bool {
+        self.forward(v).is_finite()
+    }
+
+    /// Maps a value in graph space back to the axis.
+    pub fn inverse(self, t: f32) -> f32 {
+        match self {
+            AxisScale::Linear => t,
+            AxisScale::Log10 => 10.0f32.powf(t),
+            AxisScale::Ln => t.exp(),
+            AxisScale::Symlog { threshold } => t.signum() * threshold * t.abs().exp_m1(),
+        }
+    }
+
+    /// Rate of change of `forward` at a value, used to map directions to graph space.
+    pub fn derivative(self, v: f32) -> f32 {
+        match self {
+            AxisScale::Linear => 1.0,
+            AxisScale::Log10 => 1.0 / (v * std::f32::consts::LN_10),
+            AxisScale::Ln => 1.0 / v,
+            AxisScale::Symlog { threshold } => 1.0 / (threshold + v.abs()),
+        }
+    }
+}
+
+impl std::str::FromStr for AxisScale {
+    type Err = String;
+
+    /// Parses `linear`, `log10`, `ln`, `symlog` or `symlog:<threshold>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(AxisScale::Linear),
+            "log" | "log10" => Ok(AxisScale::Log10),
+            "ln" => Ok(AxisScale::Ln),
+            "symlog" => Ok(AxisScale::Symlog { threshold: 1.0 }),
+            _ => match s.strip_prefix("symlog:").map(str::parse::<f32>) {
+                Some(Ok(threshold)) if threshold > 0.0 => Ok(AxisScale::Symlog { threshold }),
+                _ => Err(format!("Unknown axis scale '{}'", s)),
+            }
+        }
+    }
+}
+
+impl Bounds {
+    pub fn centre(&self) -> f32 {
+        (self.start + self.end) / 2.0
+    }
+}
+
+impl From<std::ops::Range<f32>> for Bounds {
+    fn from(range: std::ops::Range<f32>) -> Self {
+        Self {
+            start: range.start,
+            end: range.end,
+        }
+    }
+}
+
+impl Into<std::ops::Range<f32>> for Bounds {
+    fn into(self) -> std::ops::Range<f32> {
+        self.start..self.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn view() -> impl Strategy<Value = GraphingView> {
+        (-6.0f32..6.0, -100.0f32..100.0, -100.0f32..100.0).prop_map(|(e, x, y)| {
+            let scale = 10.0f32.powf(e);
+            GraphingView {
+                centre: [x * scale, y * scale],
+                scale,
+                xscale: AxisScale::Linear,
+                yscale: AxisScale::Linear,
+            }
+        })
+    }
+
+    fn window() -> impl Strategy<Value = (f32, f32)> {
+        (100.0f32..3000.0, 100.0f32..3000.0)
+    }
+
+    proptest! {
+        #[test]
+        fn generated_bounds_are_kept(view in view(), (w, h) in window()) {
+            let generated = view.pregenerated_bounds(w, h);
+            prop_assert!(!generated.need_regenerating(&view.visible_bounds(w, h)));
+        }
+
+        #[test]
+        fn small_pans_keep_bounds(view in view(), (w, h) in window(), dx in -0.45f32..0.45, dy in -0.45f32..0.45) {
+            let generated = view.pregenerated_bounds(w, h);
+            let visible = view.visible_bounds(w, h);
+
+            // pan by less than the margin of half the visible size either side
+            let mut panned = view.clone();
+            panned.centre[0] += dx * (visible.xbounds.end - visible.xbounds.start);
+            panned.centre[1] += dy * (visible.ybounds.end - visible.ybounds.start);
+            prop_assert!(!generated.need_regenerating(&panned.visible_bounds(w, h)));
+        }
+
+        #[test]
+        fn large_pans_regenerate(view in view(), (w, h) in window(), d in 0.55f32..3.0, vertical: bool, sign: bool) {
+            let generated = view.pregenerated_bounds(w, h);
+            let visible = view.visible_bounds(w, h);
+            let d = if sign { d } else { -d };
+
+            let mut panned = view.clone();
+            if vertical {
+                panned.centre[1] += d * (visible.ybounds.end - visible.ybounds.start);
+            } else {
+                panned.centre[0] += d * (visible.xbounds.end - visible.xbounds.start);
+            }
+            prop_assert!(generated.need_regenerating(&panned.visible_bounds(w, h)));
+        }
+
+        #[test]
+        fn zooming_out_within_bounds_keeps_them(view in view(), (w, h) in window(), zoom in 1.0f32..1.95) {
+            let generated = view.pregenerated_bounds(w, h);
+
+            let mut zoomed = view.clone();
+            zoomed.scale *= zoom;
+            prop_assert!(!generated.need_regenerating(&zoomed.visible_bounds(w, h)));
+        }
+
+        #[test]
+        fn zooming_out_past_bounds_regenerates(view in view(), (w, h) in window(), zoom in 2.05f32..10.0) {
+            let generated = view.pregenerated_bounds(w, h);
+
+            let mut zoomed = view.clone();
+            zoomed.scale *= zoom;
+            prop_assert!(generated.need_regenerating(&zoomed.visible_bounds(w, h)));
+        }
+
+        #[test]
+        fn zooming_in_regenerates(view in view(), (w, h) in window(), zoom in 1.05f32..10.0) {
+            // meshes are regenerated so curves keep their resolution on screen
+            let generated = view.pregenerated_bounds(w, h);
+
+            let mut zoomed = view.clone();
+            zoomed.scale /= zoom;
+            prop_assert!(generated.need_regenerating(&zoomed.visible_bounds(w, h)));
+        }
+    }
+
+    #[test]
+    fn logarithms_exclude_zero() {
+        assert!(AxisScale::Log10.contains(0.5));
+        assert!(!AxisScale::Log10.contains(0.0));
+        assert!(!AxisScale::Ln.contains(-1.0));
+        assert!(AxisScale::Symlog { threshold: 1.0 }.contains(-1.0));
+    }
+}
@@ -0,0 +1,262 @@
+use super::{AxisScale, Bounds, FieldKind, GraphingBounds, XyFunction};
+
+/// Steps attempted in each direction from an initial condition, including rejected steps.
+const MAX_STEPS: usize = 500;
+
+/// Local error allowed in a step, relative to the size of the bounds.
+const TOLERANCE: f32 = 1e-5;
+
+/// Curves stop once a step moves less than this, relative to the size of the bounds,
+/// as they are then settling on an equilibrium.
+const MIN_STEP_LENGTH: f32 = 1e-5;
+
+/// Longest step within the bounds, relative to their size, so curves are smooth when drawn.
+const MAX_STEP_LENGTH: f32 = 1.0 / 200.0;
+
+/// Dormand-Prince coefficients. The fields are autonomous, so the nodes are not needed.
+const A: [[f32; 6]; 6] = [
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0, 0.0, 0.0],
+    [9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0, 0.0],
+    [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0],
+];
+
+/// Difference between the fifth and fourth order weights, estimating the error of a step.
+const ERROR: [f32; 7] = [
+    71.0 / 57600.0, 0.0, -71.0 / 16695.0, 71.0 / 1920.0, -17253.0 / 339200.0, 22.0 / 525.0, -1.0 / 40.0,
+];
+
+/// The solution curve through `start`, in graph space.
+/// `start` is on the axes, before they are transformed by their `AxisScale`.
+///
+/// Slope fields are solved for y as a function of x, and planar systems `(x', y') = (P, Q)`
+/// are solved over time. The curve is integrated both ways from `start` with adaptive
+/// Runge-Kutta steps, until it leaves `bounds`, reaches an equilibrium or becomes undefined.
+/// Parts outside `bounds` are replaced by NaN.
+pub fn trajectory(
+    kind: &FieldKind,
+    start: [f32; 2],
+    bounds: &GraphingBounds,
+    (xscale, yscale): (AxisScale, AxisScale),
+) -> Result<Vec<[f32; 2]>, String> {
+    let start = [xscale.forward(start[0]), yscale.forward(start[1])];
+    if !(start[0].is_finite() && start[1].is_finite()) {
+        return Ok(Vec::new());
+    }
+
+    // parsed once for the whole curve, as each stage of each step is at a new x and y
+    let functions = kind.expressions().into_iter()
+        .map(XyFunction::parse)
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut points = integrate(&functions, start, -1.0, bounds, (xscale, yscale));
+    points.reverse();
+    points.pop();
+    points.extend(integrate(&functions, start, 1.0, bounds, (xscale, yscale)));
+
+    Ok(clip(&points, bounds))
+}
+
+/// Velocity of the field at a point in graph space.
+/// Each axis is stretched by the derivative of its scale, as for field glyphs.
+fn velocity(
+    functions: &[XyFunction],
+    [s, t]: [f32; 2],
+    (xscale, yscale): (AxisScale, AxisScale),
+) -> [f32; 2] {
+    let x = xscale.inverse(s);
+    let y = yscale.inverse(t);
+
+    let [u, v] = match functions {
+        [f] => [1.0, f.eval(x, y)],
+        [p, q] => [p.eval(x, y), q.eval(x, y)],
+        _ => unreachable!(),
+    };
+
+    [u * xscale.derivative(x), v * yscale.derivative(y)]
+}
+
+/// Points from `start` following the field, forwards if `sign` is positive and backwards if negative.
+fn integrate(
+    functions: &[XyFunction],
+    start: [f32; 2],
+    sign: f32,
+    bounds: &GraphingBounds,
+    scales: (AxisScale, AxisScale),
+) -> Vec<[f32; 2]> {
+    let size = (bounds.xbounds.end - bounds.xbounds.start).max(bounds.ybounds.end - bounds.ybounds.start);
+    let tolerance = TOLERANCE * size;
+    let max_length = MAX_STEP_LENGTH * size;
+
+    let f = |p: [f32; 2]| {
+        let [u, v] = velocity(functions, p, scales);
+        [sign * u, sign * v]
+    };
+
+    let mut points = vec![start];
+    let mut p = start;
+    let mut k1 = f(p);
+    let mut h = f32::INFINITY;
+    // curves starting outside the bounds are followed until they come back in
+    let mut entered = contains(bounds, p);
+
+    for _ in 0..MAX_STEPS {
+        let speed = length(k1);
+        if !(speed.is_finite() && speed > 0.0) {
+            break;
+        }
+
+        // steps outside the bounds are not drawn, so only need to be accurate
+        if contains(bounds, p) {
+            h = h.min(max_length / speed);
+        } else if h.is_infinite() {
+            h = max_length / speed;
+        }
+
+        let (next, k7, error) = step(&f, p, k1, h);
+
+        if error <= tolerance && next[0].is_finite() && next[1].is_finite() {
+            let moved = length([next[0] - p[0], next[1] - p[1]]);
+            p = next;
+            k1 = k7;
+            points.push(p);
+
+            let inside = contains(bounds, p);
+            if (entered && !inside) || moved < MIN_STEP_LENGTH * size {
+                break;
+            }
+            entered |= inside;
+        }
+
+        // undefined steps are retried with a quarter of the step
+        let factor = if error.is_nan() { 0.25 } else { 0.9 * (tolerance / error).powf(0.2) };
+        h *= factor.clamp(0.2, 5.0);
+
+        // stalled at a singularity
+        if h * speed < tolerance * 1e-3 {
+            break;
+        }
+    }
+
+    points
+}
+
+/// One Dormand-Prince step of length `h` from `p`, where the field is `k1`.
+/// Returns the next point, the field there, and the estimated error.
+fn step(
+    f: &impl Fn([f32; 2]) -> [f32; 2],
+    p: [f32; 2],
+    k1: [f32; 2],
+    h: f32,
+) -> ([f32; 2], [f32; 2], f32) {
+    let mut k = [[0.0; 2]; 7];
+    k[0] = k1;
+
+    for (i, row) in A.iter().enumerate() {
+        let mut q = p;
+        for (a, kj) in row.iter().zip(&k[..=i]) {
+            q[0] += h * a * kj[0];
+            q[1] += h * a * kj[1];
+        }
+
+        // the last row is the fifth order solution
+        if i == A.len() - 1 {
+            k[6] = f(q);
+            let mut error = [0.0; 2];
+            for (e, kj) in ERROR.iter().zip(&k) {
+                error[0] += h * e * kj[0];
+                error[1] += h * e * kj[1];
+            }
+            return (q, k[6], length(error));
+        }
+
+        k[i + 1] = f(q);
+    }
+
+    unreachable!()
+}
+
+/// Replaces points outside `bounds` with NaN, keeping the points next to those
+/// inside so the curve reaches the edge.
+fn clip(points: &[[f32; 2]], bounds: &GraphingBounds) -> Vec<[f32; 2]> {
+    let inside = points.iter().map(|&p| contains(bounds, p)).collect::<Vec<_>>();
+
+    (0..points.len())
+        .map(|i| {
+            let near = inside[i]
+                || (i > 0 && inside[i - 1])
+                || (i + 1 < points.len() && inside[i + 1]);
+            if near { points[i] } else { [f32::NAN; 2] }
+        })
+        .collect()
+}
+
+fn contains(bounds: &GraphingBounds, [s, t]: [f32; 2]) -> bool {
+    let within = |b: Bounds, v: f32| b.start <= v && v <= b.end;
+    within(bounds.xbounds, s) && within(bounds.ybounds, t)
+}
+
+fn length([x, y]: [f32; 2]) -> f32 {
+    (x * x + y * y).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::sampling::COMPILES;
+
+    const LINEAR: (AxisScale, AxisScale) = (AxisScale::Linear, AxisScale::Linear);
+
+    fn bounds(x: f32, y: f32) -> GraphingBounds {
+        GraphingBounds {
+            xbounds: Bounds { start: -x, end: x },
+            ybounds: Bounds { start: -y, end: y },
+        }
+    }
+
+    #[test]
+    fn exponential_growth() {
+        let points = trajectory(&FieldKind::Slope("y".to_string()), [0.0, 1.0], &bounds(2.0, 10.0), LINEAR).unwrap();
+        let finite = points.iter().filter(|p| p[0].is_finite()).collect::<Vec<_>>();
+
+        assert!(finite.first().unwrap()[0] <= -2.0);
+        assert!(finite.last().unwrap()[0] >= 2.0);
+        for &&[x, y] in finite.iter() {
+            assert!((y - x.exp()).abs() < 1e-3 * x.exp().max(1.0), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn stops_at_equilibrium() {
+        let kind = FieldKind::Vector("-x".to_string(), "-y".to_string());
+        let points = trajectory(&kind, [1.0, 1.0], &bounds(2.0, 2.0), LINEAR).unwrap();
+
+        // backwards, the curve leaves the bounds along y = x
+        assert!(points.first().unwrap()[0] >= 2.0);
+        let [x, y] = *points.last().unwrap();
+        assert!(x.abs() < 1e-3 && y.abs() < 1e-3, "({}, {})", x, y);
+        assert!(points.len() < MAX_STEPS);
+    }
+
+    #[test]
+    fn fields_of_x_and_y_are_not_recompiled() {
+        COMPILES.with(|compiles| compiles.set(0));
+        let kind = FieldKind::Slope("x*y".to_string());
+        let points = trajectory(&kind, [0.0, 1.0], &bounds(2.0, 10.0), LINEAR).unwrap();
+
+        assert!(points.len() > 2);
+        assert_eq!(COMPILES.with(|compiles| compiles.get()), 0);
+    }
+
+    #[test]
+    fn clipped_points_reach_the_edge() {
+        let points = [[-3.0, 0.0], [-2.0, 0.0], [-0.5, 0.0], [0.5, 0.0], [2.0, 0.0], [3.0, 0.0]];
+        let clipped = clip(&points, &bounds(1.0, 1.0));
+
+        assert!(clipped[0][0].is_nan());
+        assert_eq!(&clipped[1..5], &points[1..5]);
+        assert!(clipped[5][0].is_nan());
+    }
+}
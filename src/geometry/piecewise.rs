@@ -0,0 +1,230 @@
+use std::ops::Range;
+
+use super::{eval_expression, Expression};
+use crate::expr_text::{self, Token, VARIABLE};
+
+/// The values of x a piece of an expression applies to. The ends may be infinite.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Domain {
+    pub start: f32,
+    pub end: f32,
+    /// Whether the start and end are included.
+    pub closed: [bool; 2],
+}
+
+impl Domain {
+    pub const ALL: Domain = Domain { start: f32::NEG_INFINITY, end: f32::INFINITY, closed: [false, false] };
+
+    /// Parses a condition on x, such as `x < 1` or `0 <= x <= 2*pi`, where the bounds are constant.
+    pub fn parse(condition: &str) -> Result<Self, String> {
+        let tokens = expr_text::tokens(condition);
+        let mut parts = Vec::new();
+        let mut comparisons = Vec::new();
+        let mut start = 0;
+        let mut k = 0;
+
+        while k < tokens.len() {
+            let (range, token) = (tokens[k].0.clone(), tokens[k].1);
+            let less = match token {
+                Token::Symbol('<') => true,
+                Token::Symbol('>') => false,
+                _ => {
+                    k += 1;
+                    continue;
+                }
+            };
+            let or_equal = matches!(tokens.get(k + 1), Some((_, Token::Symbol('='))));
+
+            parts.push(condition[start..range.start].trim());
+            comparisons.push((less, or_equal));
+            start = if or_equal { tokens[k + 1].0.end } else { range.end };
+            k += if or_equal { 2 } else { 1 };
+        }
+        parts.push(condition[start..].trim());
+
+        if comparisons.is_empty() {
+            return Err(format!("Expected a condition on x, such as x < 1, in '{}'", condition));
+        }
+
+        let mut domain = Domain::ALL;
+        for (i, &(less, or_equal)) in comparisons.iter().enumerate() {
+            // x < b bounds the end, and b < x the start
+            let (bound, is_end) = match (parts[i], parts[i + 1]) {
+                (left, right) if left == VARIABLE => (right, less),
+                (left, right) if right == VARIABLE => (left, !less),
+                _ => return Err(format!("Expected a comparison with x in '{}'", condition)),
+            };
+
+            let value = constant(bound)?;
+            domain = domain.intersect(if is_end {
+                Domain { end: value, closed: [false, or_equal], ..Domain::ALL }
+            } else {
+                Domain { start: value, closed: [or_equal, false], ..Domain::ALL }
+            });
+        }
+
+        Ok(domain)
+    }
+
+    pub fn contains(&self, x: f32) -> bool {
+        (x > self.start || (self.closed[0] && x == self.start))
+            && (x < self.end || (self.closed[1] && x == self.end))
+    }
+
+    /// The values in both domains.
+    pub fn intersect(self, other: Domain) -> Domain {
+        let start = if other.start > self.start || (other.start == self.start && !other.closed[0]) { other } else { self };
+        let end = if other.end < self.end || (other.end == self.end && !other.closed[1]) { other } else { self };
+
+        Domain {
+            start: start.start,
+            end: end.end,
+            closed: [start.closed[0], end.closed[1]],
+        }
+    }
+}
+
+/// A bound of a condition, which can't use x.
+fn constant(text: &str) -> Result<f32, String> {
+    let uses_variable = expr_text::tokens(text).iter()
+        .any(|(_, token)| matches!(token, Token::Identifier(name, _) if *name == VARIABLE));
+    if uses_variable || text.is_empty() {
+        return Err(format!("Expected a constant bound, found '{}'", text));
+    }
+
+    match eval_expression(&Expression::parse(text)?, &[0.0])[0] {
+        value if value.is_nan() => Err(format!("The bound '{}' is undefined", text)),
+        value => Ok(value),
+    }
+}
+
+/// Splits an expression into pieces, each applying over a domain, from a list of
+/// `{condition: expression, ...}` or a restriction `expression {condition}`.
+/// A piece of a list without a condition applies wherever the pieces before it don't.
+pub fn split_pieces(text: &str) -> Result<Vec<(Domain, String)>, String> {
+    let text = text.trim();
+    let tokens = expr_text::tokens(text);
+
+    let close = match tokens.last() {
+        Some((close, Token::Symbol('}'))) => close.clone(),
+        _ => return Ok(vec![(Domain::ALL, text.to_string())]),
+    };
+    let open = opening_brace(&tokens)
+        .ok_or_else(|| format!("Unmatched '}}' in '{}'", text))?;
+    let inner = &text[tokens[open].0.end..close.start];
+    let before = text[..tokens[open].0.start].trim();
+
+    if !before.is_empty() {
+        let domain = Domain::parse(inner)?;
+        return Ok(split_pieces(before)?.into_iter()
+            .map(|(piece, expression)| (piece.intersect(domain), expression))
+            .collect());
+    }
+
+    let mut pieces = Vec::new();
+    for piece in split_top_level(inner, ',') {
+        let (domain, expression) = match split_top_level(piece, ':')[..] {
+            [expression] => (Domain::ALL, expression),
+            [condition, expression] => (Domain::parse(condition)?, expression),
+            _ => return Err(format!("Expected condition: expression, found '{}'", piece.trim())),
+        };
+        if expression.trim().is_empty() {
+            return Err(format!("Missing expression in '{}'", text));
+        }
+
+        // pieces may themselves be piecewise
+        for (inner_domain, expression) in split_pieces(expression)? {
+            pieces.push((inner_domain.intersect(domain), expression));
+        }
+    }
+
+    Ok(pieces)
+}
+
+/// Index of the brace opening the one closing `tokens`.
+fn opening_brace(tokens: &[(Range<usize>, Token)]) -> Option<usize> {
+    let mut depth = 0;
+    for (k, (_, token)) in tokens.iter().enumerate().rev() {
+        match token {
+            Token::Symbol('}') => depth += 1,
+            Token::Symbol('{') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(k);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Splits `text` at each `separator` outside brackets and braces.
+fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (range, token) in expr_text::tokens(text) {
+        match token {
+            Token::Symbol('(') | Token::Symbol('{') => depth += 1,
+            Token::Symbol(')') | Token::Symbol('}') => depth -= 1,
+            Token::Symbol(c) if c == separator && depth == 0 => {
+                parts.push(&text[start..range.start]);
+                start = range.end;
+            }
+            _ => (),
+        }
+    }
+
+    parts.push(&text[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{sample_expression, AxisScale, Bounds};
+
+    #[test]
+    fn conditions_bound_either_end() {
+        let domain = Domain::parse("0 <= x < 2").unwrap();
+        assert_eq!(domain, Domain { start: 0.0, end: 2.0, closed: [true, false] });
+        assert!(domain.contains(0.0) && !domain.contains(2.0));
+
+        assert_eq!(Domain::parse("1 > x").unwrap(), Domain { end: 1.0, ..Domain::ALL });
+        assert!(Domain::parse("x < x").is_err());
+        assert!(Domain::parse("x").is_err());
+    }
+
+    #[test]
+    fn lists_and_restrictions_split_into_pieces() {
+        let pieces = split_pieces("{x < 0: -x, x >= 0: sqrt(x)}").unwrap();
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[1].1, "sqrt(x)");
+        assert!(pieces[1].0.contains(0.0) && !pieces[0].0.contains(0.0));
+
+        let pieces = split_pieces("{x < 0: 0, 1} {x < 5}").unwrap();
+        assert_eq!(pieces[1], (Domain { end: 5.0, ..Domain::ALL }, "1".to_string()));
+    }
+
+    #[test]
+    fn pieces_are_sampled_at_their_ends_and_broken_between() {
+        let expression = Expression::parse("{x < 0: 0, x >= 0: 1}").unwrap();
+        let bounds = Bounds { start: -1.0, end: 1.0 };
+        let points = sample_expression(&expression, bounds, (AxisScale::Linear, AxisScale::Linear));
+
+        let gap = points.iter().position(|[x, y]| x.is_nan() || y.is_nan()).unwrap();
+        assert_eq!(points[gap - 1], [0.0, 0.0]);
+        assert_eq!(points[gap + 1], [0.0, 1.0]);
+        assert_eq!(points.iter().filter(|[x, _]| x.is_nan()).count(), 1);
+    }
+
+    #[test]
+    fn restrictions_leave_the_curve_undefined_outside() {
+        let expression = Expression::parse("sin(x) {0 <= x <= 2π}").unwrap();
+        let values = eval_expression(&expression, &[-1.0, 0.0, 7.0]);
+        assert!(values[0].is_nan() && values[2].is_nan());
+        assert_eq!(values[1], 0.0);
+    }
+}
@@ -0,0 +1,213 @@
+use super::piecewise::{split_pieces, Domain};
+use super::{AxisScale, Bounds};
+use crate::expr_text;
+
+const RESOLUTION: usize = 256;
+
+/// A function of x made of pieces that each apply over a domain, as in
+/// `{x < 0: -x, x >= 0: sqrt(x)}` or `sin(x) {0 <= x <= 2π}`. Where domains overlap
+/// the first piece applies, and outside them all the function is undefined.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    pieces: Vec<(Domain, mathjit::expr_parse::Expression)>,
+}
+
+impl Expression {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let pieces = split_pieces(&expr_text::expand_symbols(expr))?.into_iter()
+            .map(|(domain, piece)| mathjit::expr_parse::parse_expression(&piece)
+                .map(|parsed| (domain, parsed))
+                .map_err(|_| format!("Error in expression '{}'", expr)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Expression { pieces })
+    }
+
+    /// Ends of the pieces' domains in increasing order, where the function may jump.
+    pub fn breakpoints(&self) -> Vec<f32> {
+        let mut breakpoints = self.pieces.iter()
+            .flat_map(|(domain, _)| [domain.start, domain.end])
+            .filter(|x| x.is_finite())
+            .collect::<Vec<f32>>();
+        breakpoints.sort_by(f32::total_cmp);
+        breakpoints.dedup();
+        breakpoints
+    }
+
+    /// The piece applying at `x`.
+    fn piece_at(&self, x: f32) -> Option<&mathjit::expr_parse::Expression> {
+        self.pieces.iter()
+            .find(|(domain, _)| domain.contains(x))
+            .map(|(_, piece)| piece)
+    }
+}
+
+/// Evaluates the expression at evenly spaced points in graph space.
+/// Returns the points in graph space, including any where the curve is undefined.
+///
+/// Between pieces, the points run to the exact ends of each piece's domain and are
+/// separated by an undefined point, so jumps aren't bridged.
+pub fn sample_expression(
+    expression: &Expression,
+    bounds: Bounds,
+    (xscale, yscale): (AxisScale, AxisScale),
+) -> Vec<[f32; 2]> {
+    let xs = sample_xs(bounds, xscale, RESOLUTION);
+    let (first, last) = (xscale.inverse(bounds.start), xscale.inverse(bounds.end));
+    let breakpoints = expression.breakpoints().into_iter()
+        .filter(|&b| first < b && b < last)
+        .collect::<Vec<f32>>();
+
+    let mut points = Vec::with_capacity(xs.len() + 3 * breakpoints.len());
+    let mut remaining = &xs[..];
+    for k in 0..=breakpoints.len() {
+        let start = if k > 0 { Some(breakpoints[k - 1]) } else { None };
+        let end = breakpoints.get(k).copied();
+
+        let inside = remaining.iter().take_while(|&&x| end.map_or(true, |end| x < end)).count();
+        let mut region = remaining[..inside].iter()
+            .copied()
+            .filter(|&x| start.map_or(true, |start| x > start))
+            .collect::<Vec<f32>>();
+        remaining = &remaining[inside..];
+
+        // the piece between two breakpoints is the same throughout
+        let middle = match (start, end) {
+            (Some(start), Some(end)) => (start + end) / 2.0,
+            _ => match region.first() {
+                Some(&x) => x,
+                None => continue,
+            },
+        };
+        let piece = match expression.piece_at(middle) {
+            Some(piece) => piece,
+            None => continue,
+        };
+
+        if let Some(start) = start {
+            region.insert(0, start);
+        }
+        region.extend(end);
+        let values = eval_piece(piece, &region);
+
+        if !points.is_empty() {
+            points.push([f32::NAN, f32::NAN]);
+        }
+        points.extend(region.iter().zip(values.iter())
+            .map(|(&x, &y)| [xscale.forward(x), yscale.forward(y)]));
+    }
+
+    points
+}
+
+/// The pieces of the curve within `bounds` where it is defined, in graph space.
+pub fn sample_polylines(
+    expression: &Expression,
+    bounds: Bounds,
+    scales: (AxisScale, AxisScale),
+) -> Vec<Vec<[f32; 2]>> {
+    let points = sample_expression(expression, bounds, scales);
+    curve_pieces(&points).map(<[[f32; 2]]>::to_vec).collect()
+}
+
+/// `count` x values, evenly spaced in graph space from the start of `bounds`.
+/// The end of `bounds` is not included.
+pub fn sample_xs(bounds: Bounds, xscale: AxisScale, count: usize) -> Vec<f32> {
+    let dx = (bounds.end - bounds.start) / count as f32;
+    (0..count)
+        .map(|n| xscale.inverse(bounds.start + n as f32 * dx))
+        .collect()
+}
+
+/// Values of the expression at each x, undefined outside the domains of its pieces.
+pub fn eval_expression(expression: &Expression, xs: &[f32]) -> Vec<f32> {
+    CompiledExpression::new(expression).eval(xs)
+}
+
+/// `start` followed by `iterations` applications of the expression to it, stopping early
+/// at an undefined value.
+pub fn iterate_expression(expression: &Expression, start: f32, iterations: usize) -> Vec<f32> {
+    // compiled once, rather than for each value
+    let compiled = CompiledExpression::new(expression);
+
+    let mut orbit = vec![start];
+    let mut x = start;
+    for _ in 0..iterations {
+        x = compiled.eval_at(x);
+        if !x.is_finite() {
+            break;
+        }
+        orbit.push(x);
+    }
+
+    orbit
+}
+
+/// An expression with each piece compiled, to be evaluated many times.
+pub struct CompiledExpression {
+    pieces: Vec<(Domain, mathjit::CompiledExpression)>,
+}
+
+impl CompiledExpression {
+    pub fn new(expression: &Expression) -> Self {
+        let pieces = expression.pieces.iter()
+            .map(|(domain, piece)| (*domain, compile(piece)))
+            .collect();
+        CompiledExpression { pieces }
+    }
+
+    /// Values at each x, undefined outside the domains of the pieces.
+    pub fn eval(&self, xs: &[f32]) -> Vec<f32> {
+        let mut values = vec![f32::NAN; xs.len()];
+        let mut evaluated = vec![false; xs.len()];
+
+        for (domain, piece) in self.pieces.iter() {
+            let indices = (0..xs.len())
+                .filter(|&i| !evaluated[i] && domain.contains(xs[i]))
+                .collect::<Vec<usize>>();
+            if indices.is_empty() {
+                continue;
+            }
+
+            let inside = indices.iter().map(|&i| xs[i]).collect::<Vec<f32>>();
+            for (&i, &value) in indices.iter().zip(piece.eval(&inside).iter()) {
+                values[i] = value;
+                evaluated[i] = true;
+            }
+        }
+
+        values
+    }
+
+    /// The value at a single x.
+    pub fn eval_at(&self, x: f32) -> f32 {
+        match self.pieces.iter().find(|(domain, _)| domain.contains(x)) {
+            Some((_, piece)) => piece.eval(&[x]).iter().copied().next().unwrap_or(f32::NAN),
+            None => f32::NAN,
+        }
+    }
+}
+
+/// Values of one piece at each x, compiled once for them all.
+fn eval_piece(piece: &mathjit::expr_parse::Expression, xs: &[f32]) -> Vec<f32> {
+    compile(piece).eval(xs).iter().copied().collect()
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Pieces compiled by this thread, so tests can check expressions aren't compiled
+    /// more often than they need to be. Tests each run on their own thread.
+    pub static COMPILES: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
+
+fn compile(piece: &mathjit::expr_parse::Expression) -> mathjit::CompiledExpression {
+    #[cfg(test)]
+    COMPILES.with(|compiles| compiles.set(compiles.get() + 1));
+    mathjit::CompiledExpression::new(piece).expect("error compiling equation.")
+}
+
+/// Splits `points` into runs of finite points, dropping runs too short to draw.
+pub fn curve_pieces(points: &[[f32; 2]]) -> impl Iterator<Item = &[[f32; 2]]> {
+    points.split(|[x, y]| !(x.is_finite() && y.is_finite()))
+        .filter(|piece| piece.len() > 1)
+}
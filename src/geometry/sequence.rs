@@ -0,0 +1,155 @@
+use super::{eval_expression, AxisScale, Bounds, Expression};
+use crate::expr_text::{self, Token, VARIABLE};
+
+/// The index of sequences, `a(n)`.
+pub const SEQUENCE_VARIABLE: &str = "n";
+
+/// Most terms of a sequence drawn at once.
+pub const MAX_SEQUENCE_POINTS: usize = 4096;
+
+/// The previous term in a recurrence, `x_{n+1} = f(x_n)`.
+pub const PREVIOUS_TERM: &str = "x_n";
+
+/// Parses `a(n) = f(n)`, `a_n = f(n)` or `f(n)` as an expression of x, which mathjit compiles.
+/// `expand` is applied to `f(n)`, so definitions are expanded without touching the name.
+pub fn parse_sequence(text: &str, expand: impl Fn(&str) -> Result<String, String>) -> Result<String, String> {
+    let text = text.trim();
+    let body = expand(match text.split_once('=') {
+        Some((lhs, rhs)) if is_sequence_name(lhs) => rhs.trim(),
+        _ => text,
+    })?;
+    let body = body.as_str();
+
+    if uses(body, VARIABLE) {
+        return Err(format!("Sequences are functions of n, found x in '{}'", text));
+    }
+
+    let expression = expr_text::replace(body, &[(SEQUENCE_VARIABLE, VARIABLE.to_string())]);
+    Expression::parse(&expression)?;
+    Ok(expression)
+}
+
+/// Parses `x_{n+1} = f(x_n)` or `f(x)` as an expression of x, with `expand` applied to `f(x_n)`.
+pub fn parse_recurrence(text: &str, expand: impl Fn(&str) -> Result<String, String>) -> Result<String, String> {
+    let text = text.trim();
+    let body = expand(match text.split_once('=') {
+        Some((lhs, rhs)) if matches!(&lhs.split_whitespace().collect::<String>()[..], "x_{n+1}" | "x_(n+1)") => rhs.trim(),
+        _ => text,
+    })?;
+    let body = body.as_str();
+
+    if uses(body, SEQUENCE_VARIABLE) {
+        return Err(format!("Recurrences can only use the previous term x_n, found n in '{}'", text));
+    }
+
+    let expression = expr_text::replace(body, &[(PREVIOUS_TERM, VARIABLE.to_string())]);
+    Expression::parse(&expression)?;
+    Ok(expression)
+}
+
+/// Whether `lhs` names a sequence, as `a(n)` or `a_n`.
+fn is_sequence_name(lhs: &str) -> bool {
+    match &expr_text::tokens(lhs)[..] {
+        [(_, Token::Identifier(_, true)), (_, Token::Symbol('(')), (_, Token::Identifier(n, false)), (_, Token::Symbol(')'))] => {
+            *n == SEQUENCE_VARIABLE
+        }
+        [(_, Token::Identifier(name, false))] => name.ends_with("_n"),
+        _ => false,
+    }
+}
+
+fn uses(text: &str, name: &str) -> bool {
+    expr_text::tokens(text).iter()
+        .any(|(_, token)| matches!(token, Token::Identifier(n, false) if *n == name))
+}
+
+/// The terms of a sequence for each integer n within `xbounds`, in graph space.
+pub fn sequence_points(
+    expression: &Expression,
+    xbounds: Bounds,
+    (xscale, yscale): (AxisScale, AxisScale),
+) -> Vec<[f32; 2]> {
+    let first = xscale.inverse(xbounds.start).ceil();
+    let last = xscale.inverse(xbounds.end).floor();
+    if !(first <= last) {
+        return Vec::new();
+    }
+
+    let count = (last - first) as usize + 1;
+    // zoomed far out, evenly spaced terms are kept
+    let step = if count > MAX_SEQUENCE_POINTS { count / MAX_SEQUENCE_POINTS + 1 } else { 1 };
+    let ns = (0..count)
+        .step_by(step)
+        .map(|k| first + k as f32)
+        .collect::<Vec<f32>>();
+
+    ns.iter().zip(eval_expression(expression, &ns))
+        .map(|(&n, a)| [xscale.forward(n), yscale.forward(a)])
+        .collect()
+}
+
+/// The path of a cobweb diagram of `orbit`, in data space. It starts on y = x, and
+/// for each iteration goes to the curve and back across to y = x.
+pub fn cobweb_points(orbit: &[f32]) -> Vec<[f32; 2]> {
+    let mut points = Vec::with_capacity(2 * orbit.len());
+    if let Some(&start) = orbit.first() {
+        points.push([start, start]);
+    }
+    for pair in orbit.windows(2) {
+        let [a, b] = [pair[0], pair[1]];
+        points.extend([[a, b], [b, b]]);
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{iterate_expression, Definitions};
+
+    fn unchanged(text: &str) -> Result<String, String> {
+        Ok(text.to_string())
+    }
+
+    #[test]
+    fn sequences_parse_with_or_without_lhs() {
+        assert_eq!(parse_sequence("a(n) = 1/n", unchanged), Ok("1/x".to_string()));
+        assert_eq!(parse_sequence("a_n = n^2", unchanged), Ok("x^2".to_string()));
+        assert_eq!(parse_sequence("(-1)^n", unchanged), Ok("(-1)^x".to_string()));
+        assert!(parse_sequence("x + n", unchanged).is_err());
+    }
+
+    #[test]
+    fn only_the_body_is_expanded() {
+        let definitions = Definitions::resolve(["a = 3"]).unwrap();
+        assert_eq!(parse_sequence("a(n) = a*n", |body| definitions.expand(body)), Ok("(3)*x".to_string()));
+    }
+
+    #[test]
+    fn recurrences_use_the_previous_term() {
+        assert_eq!(parse_recurrence("x_{n+1} = 3*x_n*(1 - x_n)", unchanged), Ok("3*x*(1 - x)".to_string()));
+        assert_eq!(parse_recurrence("cos(x)", unchanged), Ok("cos(x)".to_string()));
+        assert!(parse_recurrence("x_{n+1} = x_n + n", unchanged).is_err());
+    }
+
+    #[test]
+    fn sequences_have_a_term_for_each_integer() {
+        let expression = Expression::parse("1/x").unwrap();
+        let bounds = Bounds { start: 0.5, end: 3.5 };
+        let points = sequence_points(&expression, bounds, (AxisScale::Linear, AxisScale::Linear));
+        assert_eq!(points, vec![[1.0, 1.0], [2.0, 0.5], [3.0, 1.0 / 3.0]]);
+    }
+
+    #[test]
+    fn cobwebs_step_between_the_curve_and_the_diagonal() {
+        let orbit = iterate_expression(&Expression::parse("x/2").unwrap(), 1.0, 2);
+        assert_eq!(orbit, vec![1.0, 0.5, 0.25]);
+        assert_eq!(
+            cobweb_points(&orbit),
+            vec![[1.0, 1.0], [1.0, 0.5], [0.5, 0.5], [0.5, 0.25], [0.25, 0.25]],
+        );
+
+        // stops where the function is undefined
+        assert_eq!(iterate_expression(&Expression::parse("sqrt(x)").unwrap(), -1.0, 5), vec![-1.0]);
+    }
+}
@@ -0,0 +1,300 @@
+use serde::{Deserialize, Serialize};
+
+use super::curve_pieces;
+
+/// Number of strip segments used to round off half a turn, for round joins and caps.
+const ROUND_SEGMENTS: usize = 8;
+
+/// How consecutive segments of a curve are joined.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum JoinStyle {
+    /// Mitered joins. Joins whose miter would exceed `limit` times the
+    /// curve width fall back to `fallback`.
+    Miter { limit: f32, fallback: FallbackJoin },
+    Bevel,
+    Round,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FallbackJoin {
+    Bevel,
+    Round,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CapStyle {
+    Butt,
+    Round,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StrokeStyle {
+    pub join: JoinStyle,
+    pub cap: CapStyle,
+}
+
+/// A vertex of a stroked triangle strip.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StrokeVertex {
+    /// Point on the curve, in graph space.
+    pub position: [f32; 2],
+    /// Offset from `position` in units of the curve width.
+    pub offset: [f32; 2],
+    /// The offset along and across the curve, unit length on the edge of the stroke.
+    /// Unlike `offset`, this isn't lengthened at miters, so it gives the distance from
+    /// the centreline wherever it is interpolated.
+    pub distance: [f32; 2],
+    /// Cumulative distance along the curve to `position`.
+    pub arc_length: f32,
+}
+
+impl Default for StrokeStyle {
+    fn default() -> Self {
+        Self {
+            join: JoinStyle::Miter { limit: 4.0, fallback: FallbackJoin::Round },
+            cap: CapStyle::Round,
+        }
+    }
+}
+
+/// Strokes a polyline as a single triangle strip.
+/// The line is broken at non-finite points, with the pieces joined by degenerate triangles.
+pub fn stroke_polyline(points: &[[f32; 2]], stroke: &StrokeStyle) -> Vec<StrokeVertex> {
+    let mut vertices: Vec<StrokeVertex> = Vec::new();
+
+    // Break the curve where it is undefined, e.g. the log of a negative value.
+    for piece in curve_pieces(points) {
+        let strip = stroke_strip(piece, stroke);
+
+        // Join strips with degenerate triangles.
+        // Pieces have an even number of vertices, so the winding is kept.
+        if let Some(&prev) = vertices.last() {
+            vertices.extend_from_slice(&[prev, strip[0]]);
+        }
+
+        vertices.extend(strip);
+    }
+
+    vertices
+}
+
+/// Builds a triangle strip along `points`.
+/// Every vertex lies on the curve, and is offset in units of the curve width.
+/// The offsets are not normalized - mitered joins are longer than the curve width.
+pub fn stroke_strip(points: &[[f32; 2]], stroke: &StrokeStyle) -> Vec<StrokeVertex> {
+    assert!(points.len() > 1);
+    let segment_normals = segment_normals(points);
+    let vertex_normals = normals(points);
+    let arc_lengths = arc_lengths(points);
+
+    let mut vertices = Vec::with_capacity(points.len() * 2);
+
+    // `distance` is that of the left vertex, the right mirrors it across the curve
+    let mut push_pair = |i: usize, a: [f32; 2], b: [f32; 2], [along, across]: [f32; 2]| {
+        for (offset, distance) in [(a, [along, across]), (b, [along, -across])] {
+            vertices.push(StrokeVertex { position: points[i], offset, distance, arc_length: arc_lengths[i] });
+        }
+    };
+    let edge = [0.0, 1.0];
+
+    let first = 0;
+    let last = points.len() - 1;
+
+    if stroke.cap == CapStyle::Round {
+        let [nx, ny] = segment_normals[0];
+        let [tx, ty] = [ny, -nx];
+        // sweep from the back of the curve around to the first normal
+        for k in 0..ROUND_SEGMENTS {
+            let (s, c) = (std::f32::consts::FRAC_PI_2 * k as f32 / ROUND_SEGMENTS as f32).sin_cos();
+            push_pair(first, [-tx * c + nx * s, -ty * c + ny * s], [-tx * c - nx * s, -ty * c - ny * s], [-c, s]);
+        }
+    }
+
+    let n = vertex_normals[0];
+    push_pair(first, n, neg(n), edge);
+
+    for i in 1..(points.len() - 1) {
+        let n1 = segment_normals[i - 1];
+        let n2 = segment_normals[i];
+        let n = vertex_normals[i];
+        let cos_half = dot(n, n1);
+
+        let miter_limit = match stroke.join {
+            JoinStyle::Miter { limit, .. } => limit,
+            JoinStyle::Bevel | JoinStyle::Round => 1.0,
+        };
+
+        // Nearly straight joins are always mitered, otherwise each bevel or round join
+        // would add vertices for no visual difference.
+        if cos_half > 0.999 || (cos_half > 0.0 && cos_half.recip() <= miter_limit) {
+            let m = [n[0] / cos_half, n[1] / cos_half];
+            // the miter is lengthened, but lies on the edge of both segments
+            push_pair(i, m, neg(m), edge);
+            continue;
+        }
+
+        let fallback = match stroke.join {
+            JoinStyle::Miter { fallback, .. } => fallback,
+            JoinStyle::Bevel => FallbackJoin::Bevel,
+            JoinStyle::Round => FallbackJoin::Round,
+        };
+
+        match fallback {
+            FallbackJoin::Bevel => {
+                push_pair(i, n1, neg(n1), edge);
+                push_pair(i, n2, neg(n2), edge);
+            }
+            FallbackJoin::Round => {
+                let angle = dot(n1, n2).clamp(-1.0, 1.0).acos();
+                let turn = if cross(n1, n2) < 0.0 { -angle } else { angle };
+                let steps = ((angle / std::f32::consts::PI * ROUND_SEGMENTS as f32).ceil() as usize).max(1);
+                for k in 0..=steps {
+                    let r = rotate(n1, turn * k as f32 / steps as f32);
+                    push_pair(i, r, neg(r), edge);
+                }
+            }
+        }
+    }
+
+    let n = vertex_normals[vertex_normals.len() - 1];
+    push_pair(last, n, neg(n), edge);
+
+    if stroke.cap == CapStyle::Round {
+        let [nx, ny] = segment_normals[segment_normals.len() - 1];
+        let [tx, ty] = [ny, -nx];
+        // sweep from the last normal around to the front of the curve
+        for k in (0..ROUND_SEGMENTS).rev() {
+            let (s, c) = (std::f32::consts::FRAC_PI_2 * k as f32 / ROUND_SEGMENTS as f32).sin_cos();
+            push_pair(last, [tx * c + nx * s, ty * c + ny * s], [tx * c - nx * s, ty * c - ny * s], [c, s]);
+        }
+    }
+
+    vertices
+}
+
+/// Cumulative distance along the curve to each point.
+pub fn arc_lengths(points: &[[f32; 2]]) -> Vec<f32> {
+    let mut total = 0.0;
+    let mut lengths = Vec::with_capacity(points.len());
+    lengths.push(0.0);
+
+    for w in points.windows(2) {
+        let d = [w[1][0] - w[0][0], w[1][1] - w[0][1]];
+        total += (d[0] * d[0] + d[1] * d[1]).sqrt();
+        lengths.push(total);
+    }
+
+    lengths
+}
+
+/// Normalized normal for each point, averaged from the segments on either side.
+/// The first and last points take the normal of their only segment.
+pub fn normals(points: &[[f32; 2]]) -> Box<[[f32; 2]]> {
+    assert!(points.len() > 1);
+    let segment_normals = segment_normals(points);
+
+    let mut normals = Vec::with_capacity(points.len());
+    normals.push(segment_normals[0]);
+
+    for w in segment_normals.windows(2) {
+        let sum = [w[0][0] + w[1][0], w[0][1] + w[1][1]];
+        let len = (sum[0] * sum[0] + sum[1] * sum[1]).sqrt();
+
+        // Segments pointing in opposite directions (a cusp) have no average.
+        if len > 1e-4 {
+            normals.push([sum[0] / len, sum[1] / len]);
+        } else {
+            normals.push(w[1]);
+        }
+    }
+
+    normals.push(segment_normals[segment_normals.len() - 1]);
+    normals.into_boxed_slice()
+}
+
+/// Normalized normal of each segment between consecutive points.
+pub fn segment_normals(points: &[[f32; 2]]) -> Vec<[f32; 2]> {
+    points.windows(2).map(|w| {
+        let d = [w[1][0] - w[0][0], w[1][1] - w[0][1]];
+        let len = (d[0] * d[0] + d[1] * d[1]).sqrt();
+        if len > 0.0 {
+            [-d[1] / len, d[0] / len]
+        } else {
+            [0.0, 1.0]
+        }
+    }).collect()
+}
+
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn cross(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn neg([x, y]: [f32; 2]) -> [f32; 2] {
+    [-x, -y]
+}
+
+fn rotate([x, y]: [f32; 2], angle: f32) -> [f32; 2] {
+    let (s, c) = angle.sin_cos();
+    [x * c - y * s, x * s + y * c]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn length([x, y]: [f32; 2]) -> f32 {
+        (x * x + y * y).sqrt()
+    }
+
+    #[test]
+    fn straight_line_normals_point_left() {
+        let points = [[0.0, 0.0], [1.0, 0.0], [2.0, 0.0]];
+        assert!(normals(&points).iter().all(|&n| n == [0.0, 1.0]));
+    }
+
+    #[test]
+    fn cusp_takes_following_normal() {
+        let points = [[0.0, 0.0], [1.0, 0.0], [0.0, 0.0]];
+        assert_eq!(normals(&points)[1], [0.0, -1.0]);
+    }
+
+    #[test]
+    fn miters_are_on_the_edge() {
+        // a right angle, mitered to sqrt(2) times the width
+        let points = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]];
+        let stroke = StrokeStyle { cap: CapStyle::Butt, ..Default::default() };
+        let strip = stroke_strip(&points, &stroke);
+
+        let miter = strip[2];
+        assert!((length(miter.offset) - 2.0f32.sqrt()).abs() < 1e-4);
+        assert_eq!(miter.distance, [0.0, 1.0]);
+        assert_eq!(strip[3].distance, [0.0, -1.0]);
+    }
+
+    proptest! {
+        #[test]
+        fn normals_are_unit(points in prop::collection::vec(prop::array::uniform2(-1e3f32..1e3), 2..64)) {
+            let normals = normals(&points);
+            prop_assert_eq!(normals.len(), points.len());
+            for n in normals.iter() {
+                prop_assert!((length(*n) - 1.0).abs() < 1e-4, "{:?}", n);
+            }
+        }
+
+        #[test]
+        fn repeated_points_have_unit_normals(
+            points in prop::collection::vec(prop::array::uniform2(-2i32..2), 2..64),
+        ) {
+            // small integer coordinates often repeat, giving zero length segments
+            let points = points.iter().map(|&[x, y]| [x as f32, y as f32]).collect::<Vec<_>>();
+            for n in normals(&points).iter() {
+                prop_assert!((length(*n) - 1.0).abs() < 1e-4, "{:?}", n);
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use super::{Bounds, Grid};
+
+/// Half the height of the box surfaces are scaled into, which is 2 wide along
+/// the longer of x and y.
+pub const SURFACE_HEIGHT: f32 = 0.75;
+
+/// A surface z = f(x, y) scaled into a box centred on the origin, with z upwards.
+#[derive(Clone, Debug, Default)]
+pub struct SurfaceGeometry {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// Triangles, skipping any with an undefined corner.
+    pub indices: Vec<u32>,
+    /// Pairs of endpoints along every `spacing`th row and column of the grid.
+    pub wireframe: Vec<[f32; 3]>,
+    /// Endpoints of the x, y and z axes through the origin, or the nearest edges of the box.
+    pub axes: [[[f32; 3]; 2]; 3],
+}
+
+/// Meshes the values of `grid`, with normals from finite differences.
+/// Every `spacing`th row and column is traced by the wireframe.
+pub fn surface_geometry(grid: &Grid, spacing: usize) -> SurfaceGeometry {
+    let (width, height) = (grid.width, grid.height);
+    let (xbounds, ybounds) = (grid.bounds.xbounds, grid.bounds.ybounds);
+    let scale = 2.0 / (xbounds.end - xbounds.start).max(ybounds.end - ybounds.start);
+    let (low, high) = grid.range().map_or((0.0, 1.0), |[low, high]| (low, high));
+    let zscale = if high > low { 2.0 * SURFACE_HEIGHT / (high - low) } else { 1.0 };
+    let zmid = (low + high) / 2.0;
+
+    let to_box = |[x, y]: [f32; 2], z: f32| [
+        (x - xbounds.centre()) * scale,
+        (y - ybounds.centre()) * scale,
+        (z - zmid) * zscale,
+    ];
+
+    let value = |i: usize, j: usize| grid.values[j * width + i];
+    let defined = |i: usize, j: usize| value(i, j).is_finite();
+
+    let mut geometry = SurfaceGeometry::default();
+    for j in 0..height {
+        for i in 0..width {
+            let z = if defined(i, j) { value(i, j) } else { zmid };
+            geometry.positions.push(to_box(grid.point(i as f32, j as f32), z));
+        }
+    }
+
+    // central differences, or one sided at the edges and next to undefined values
+    let slope = |p: usize, q: usize| {
+        let [a, b] = [geometry.positions[p], geometry.positions[q]];
+        (b[2] - a[2]) / (b[0] - a[0] + b[1] - a[1])
+    };
+    for j in 0..height {
+        for i in 0..width {
+            let k = j * width + i;
+            let neighbour = |i: Option<usize>, j: Option<usize>| match (i, j) {
+                (Some(i), Some(j)) if i < width && j < height && defined(i, j) => Some(j * width + i),
+                _ => None,
+            };
+
+            let along = |before: Option<usize>, after: Option<usize>| match (before, after) {
+                (Some(p), Some(q)) => slope(p, q),
+                (Some(p), None) => slope(p, k),
+                (None, Some(q)) => slope(k, q),
+                (None, None) => 0.0,
+            };
+            let dx = along(neighbour(i.checked_sub(1), Some(j)), neighbour(Some(i + 1), Some(j)));
+            let dy = along(neighbour(Some(i), j.checked_sub(1)), neighbour(Some(i), Some(j + 1)));
+
+            let length = (dx * dx + dy * dy + 1.0).sqrt();
+            let normal = if length.is_finite() { [-dx / length, -dy / length, 1.0 / length] } else { [0.0, 0.0, 1.0] };
+            geometry.normals.push(normal);
+        }
+    }
+
+    for j in 0..height.saturating_sub(1) {
+        for i in 0..width.saturating_sub(1) {
+            let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+            if corners.iter().any(|&(i, j)| !defined(i, j)) {
+                continue;
+            }
+
+            let [a, b, c, d] = corners.map(|(i, j)| (j * width + i) as u32);
+            // rows run downwards in y, so these wind anticlockwise seen from above
+            geometry.indices.extend([a, c, b, a, d, c]);
+        }
+    }
+
+    // lifted off the surface slightly so they aren't hidden by it
+    let lifted = |k: usize| {
+        let [p, n] = [geometry.positions[k], geometry.normals[k]];
+        [p[0] + n[0] * 1e-3, p[1] + n[1] * 1e-3, p[2] + n[2] * 1e-3]
+    };
+    let spacing = spacing.max(1);
+    let mut wireframe = Vec::new();
+    for j in 0..height {
+        for i in 0..width {
+            let k = j * width + i;
+            if !defined(i, j) {
+                continue;
+            }
+            if j % spacing == 0 && i + 1 < width && defined(i + 1, j) {
+                wireframe.extend([lifted(k), lifted(k + 1)]);
+            }
+            if i % spacing == 0 && j + 1 < height && defined(i, j + 1) {
+                wireframe.extend([lifted(k), lifted(k + width)]);
+            }
+        }
+    }
+    geometry.wireframe = wireframe;
+
+    let clamp = |v: f32, b: Bounds| v.clamp(b.start, b.end);
+    let [x0, y0, z0] = to_box([clamp(0.0, xbounds), clamp(0.0, ybounds)], 0.0);
+    let z0 = z0.clamp(-SURFACE_HEIGHT, SURFACE_HEIGHT);
+    let [xstart, ystart, _] = to_box([xbounds.start, ybounds.start], zmid);
+    let [xend, yend, _] = to_box([xbounds.end, ybounds.end], zmid);
+    geometry.axes = [
+        [[xstart, y0, z0], [xend, y0, z0]],
+        [[x0, ystart, z0], [x0, yend, z0]],
+        [[x0, y0, -SURFACE_HEIGHT], [x0, y0, SURFACE_HEIGHT]],
+    ];
+
+    geometry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::GraphingBounds;
+
+    fn grid(values: Vec<f32>, width: usize) -> Grid {
+        let height = values.len() / width;
+        Grid {
+            values,
+            width,
+            height,
+            bounds: GraphingBounds {
+                xbounds: Bounds { start: -1.0, end: 1.0 },
+                ybounds: Bounds { start: -1.0, end: 1.0 },
+            },
+        }
+    }
+
+    #[test]
+    fn planes_have_one_normal() {
+        // z = x, which is the column
+        let geometry = surface_geometry(&grid((0..16).map(|k| (k % 4) as f32).collect(), 4), 1);
+
+        let first = geometry.normals[0];
+        assert!(first[0] < 0.0 && first[2] > 0.0, "{:?}", first);
+        for normal in geometry.normals.iter() {
+            for (a, b) in normal.iter().zip(first) {
+                assert!((a - b).abs() < 1e-5, "{:?} {:?}", normal, first);
+            }
+        }
+        assert_eq!(geometry.indices.len(), 3 * 3 * 6);
+    }
+
+    #[test]
+    fn undefined_values_leave_holes() {
+        let mut values = vec![1.0; 9];
+        values[4] = f32::NAN;
+        let geometry = surface_geometry(&grid(values, 3), 1);
+
+        // every cell touches the centre
+        assert!(geometry.indices.is_empty());
+        assert!(geometry.positions.iter().flatten().all(|v| v.is_finite()));
+    }
+}
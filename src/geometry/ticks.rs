@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use super::axes::{finer_axis_diff, mid_axis_diff};
+use super::AxisScale;
+
+/// Spacing of the mid axis lines and formatting of their labels.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TickMode {
+    /// 1, 2 or 5 times a power of ten.
+    Decimal,
+    /// Multiples or fractions of π, labelled like `π/2` or `3π`.
+    Pi,
+    /// Values are in radians, spaced and labelled at angles like 15°, 45° or 90°.
+    Degrees,
+    /// Multiples of a fixed step.
+    Step(f32),
+}
+
+const DEGREE_STEPS: [f32; 10] = [1.0, 2.0, 5.0, 10.0, 15.0, 30.0, 45.0, 90.0, 180.0, 360.0];
+
+impl TickMode {
+    /// Separation of mid axis lines at the view scale.
+    pub fn axis_diff(self, scale: f32) -> f32 {
+        match self {
+            TickMode::Decimal => mid_axis_diff(scale),
+            TickMode::Pi => {
+                let multiple = mid_axis_diff(scale / PI);
+                if multiple >= 1.0 {
+                    PI * multiple
+                } else {
+                    // halve π rather than using decimal fractions of it
+                    PI / 2.0f32.powf(multiple.recip().log2().ceil())
+                }
+            }
+            TickMode::Degrees => {
+                let scale = scale.to_degrees();
+                let decimal = mid_axis_diff(scale);
+                let degrees = if decimal > 360.0 {
+                    360.0 * mid_axis_diff(scale / 360.0)
+                } else if decimal < 1.0 {
+                    decimal
+                } else {
+                    DEGREE_STEPS.into_iter()
+                        .find(|&step| step >= decimal * 0.75)
+                        .unwrap_or(360.0)
+                };
+                degrees.to_radians()
+            }
+            TickMode::Step(step) => step * mid_axis_diff(scale / step).max(1.0),
+        }
+    }
+
+    /// Separation of minor axis lines, given the mid axis separation.
+    pub fn finer_diff(self, separation: f32) -> f32 {
+        match self {
+            TickMode::Decimal => finer_axis_diff(separation),
+            TickMode::Pi if separation > PI * 1.5 => PI * finer_axis_diff(separation / PI),
+            TickMode::Pi => separation / 2.0,
+            TickMode::Degrees => {
+                let separation = to_degrees(separation);
+                let degrees = if separation > 360.0 || separation <= 1.0 {
+                    finer_axis_diff(separation)
+                } else {
+                    DEGREE_STEPS.into_iter()
+                        .rev()
+                        .find(|&step| step < separation && (separation / step).fract() == 0.0)
+                        .unwrap_or(separation / 2.0)
+                };
+                degrees.to_radians()
+            }
+            TickMode::Step(step) if separation > step * 1.5 => step * finer_axis_diff(separation / step),
+            TickMode::Step(_) => separation / 2.0,
+        }
+    }
+
+    /// Label for an axis line at `value`, where lines are `separation` apart in graph space.
+    pub fn format(self, value: f32, separation: f32, scale: AxisScale) -> String {
+        // lines of other scales are evenly spaced in graph space rather than in value
+        let decimal = |value: f32, separation: f32| match scale {
+            AxisScale::Linear => format_decimal(value, separation),
+            AxisScale::Log10 | AxisScale::Ln | AxisScale::Symlog { .. } => format_significant(value),
+        };
+
+        match self {
+            TickMode::Pi => format_pi_fraction(value, separation),
+            TickMode::Degrees => format!("{}°", decimal(value.to_degrees(), to_degrees(separation))),
+            TickMode::Decimal | TickMode::Step(_) => decimal(value, separation),
+        }
+    }
+}
+
+impl std::str::FromStr for TickMode {
+    type Err = String;
+
+    /// Parses `decimal`, `pi`, `degrees` or a step size.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decimal" => Ok(TickMode::Decimal),
+            "pi" | "π" => Ok(TickMode::Pi),
+            "degrees" | "deg" => Ok(TickMode::Degrees),
+            _ => match s.parse::<f32>() {
+                Ok(step) if step > 0.0 && step.is_finite() => Ok(TickMode::Step(step)),
+                _ => Err(format!("Unknown tick mode '{}'", s)),
+            }
+        }
+    }
+}
+
+/// Shows enough decimal places to tell apart values `separation` apart.
+fn format_decimal(value: f32, separation: f32) -> String {
+    let places = (-separation.log10().floor()).max(0.0) as usize;
+    let s = format!("{:.*}", places, value);
+
+    // avoid "-0"
+    if s.trim_start_matches('-').trim_matches(|c| c == '0' || c == '.').is_empty() {
+        "0".to_string()
+    } else {
+        s
+    }
+}
+
+/// Shows enough decimal places for the first significant digit of `value`.
+fn format_significant(value: f32) -> String {
+    if value == 0.0 || !value.is_finite() {
+        return "0".to_string();
+    }
+
+    // slightly under, so powers of ten aren't given an extra place by rounding error
+    let places = (-value.abs().log10() - 1e-4).ceil().max(0.0) as usize;
+    format!("{:.*}", places, value)
+}
+
+/// Degrees of an angle in radians, rounded to 5 significant figures so steps
+/// converted to radians and back are whole again.
+fn to_degrees(radians: f32) -> f32 {
+    let degrees = radians.to_degrees();
+    if degrees == 0.0 || !degrees.is_finite() {
+        return degrees;
+    }
+
+    let precision = 10.0f32.powf(4.0 - degrees.abs().log10().floor());
+    (degrees * precision).round() / precision
+}
+
+fn format_pi_fraction(value: f32, separation: f32) -> String {
+    let denominator = (PI / separation).round().max(1.0) as i64;
+    let numerator = (value / PI * denominator as f32).round() as i64;
+    if numerator == 0 {
+        return "0".to_string();
+    }
+
+    let gcd = gcd(numerator.abs(), denominator);
+    let (numerator, denominator) = (numerator / gcd, denominator / gcd);
+
+    let sign = if numerator < 0 { "-" } else { "" };
+    let coefficient = match numerator.abs() {
+        1 => String::new(),
+        n => n.to_string(),
+    };
+
+    match denominator {
+        1 => format!("{}{}π", sign, coefficient),
+        d => format!("{}{}π/{}", sign, coefficient, d),
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degree_lines_are_at_angles_in_radians() {
+        let separation = TickMode::Degrees.axis_diff(5.0);
+        assert_eq!(to_degrees(separation), 45.0);
+        assert_eq!(TickMode::Degrees.format(PI / 4.0, separation, AxisScale::Linear), "45°");
+        assert_eq!(to_degrees(TickMode::Degrees.finer_diff(separation)), 15.0);
+    }
+
+    #[test]
+    fn log_labels_keep_their_first_digit() {
+        // a decade apart in graph space
+        assert_eq!(TickMode::Decimal.format(0.01, 1.0, AxisScale::Log10), "0.01");
+        assert_eq!(TickMode::Decimal.format(0.2, 1.0, AxisScale::Log10), "0.2");
+        assert_eq!(TickMode::Decimal.format(100.0, 1.0, AxisScale::Log10), "100");
+    }
+}
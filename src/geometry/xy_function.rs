@@ -0,0 +1,229 @@
+use super::complex::describe;
+use super::piecewise::{split_pieces, Domain};
+use super::FIELD_VARIABLE;
+use crate::expr_text::{self, Token, VARIABLE};
+
+/// A function of x and y, parsed once and evaluated with both as inputs.
+///
+/// mathjit compiles functions of x alone, so compiling a function of x and y means
+/// substituting y and compiling again for each y. Fields are evaluated at a new y for
+/// every glyph row and every stage of a solution curve, so they are interpreted instead.
+///
+/// Supports `+ - * / ^`, `pi`, `e`, and `sin`, `cos`, `tan`, `asin`, `acos`, `atan`,
+/// `sinh`, `cosh`, `tanh`, `exp`, `ln` or `log`, `sqrt`, `abs`, `floor` and `ceil`.
+/// Pieces may be restricted to domains of x, as curves are.
+#[derive(Clone, Debug)]
+pub struct XyFunction {
+    pieces: Vec<(Domain, Node)>,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Number(f32),
+    X,
+    Y,
+    Neg(Box<Node>),
+    Binary(char, Box<Node>, Box<Node>),
+    Call(fn(f32) -> f32, Box<Node>),
+}
+
+impl XyFunction {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let pieces = split_pieces(&expr_text::expand_symbols(text))?.into_iter()
+            .map(|(domain, piece)| {
+                let tokens = expr_text::tokens(&piece).into_iter().map(|(_, t)| t).collect::<Vec<_>>();
+                let mut parser = Parser { tokens: &tokens, next: 0 };
+                let node = parser.expression()?;
+                match parser.peek() {
+                    None => Ok((domain, node)),
+                    Some(token) => Err(format!("unexpected {}", describe(token))),
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|e| format!("Error in expression '{}': {}", text, e))?;
+
+        Ok(XyFunction { pieces })
+    }
+
+    /// The value at `(x, y)`, undefined outside the domains of the pieces.
+    pub fn eval(&self, x: f32, y: f32) -> f32 {
+        match self.pieces.iter().find(|(domain, _)| domain.contains(x)) {
+            Some((_, node)) => node.eval(x, y),
+            None => f32::NAN,
+        }
+    }
+}
+
+impl Node {
+    fn eval(&self, x: f32, y: f32) -> f32 {
+        match self {
+            Node::Number(value) => *value,
+            Node::X => x,
+            Node::Y => y,
+            Node::Neg(a) => -a.eval(x, y),
+            Node::Binary(op, a, b) => {
+                let (a, b) = (a.eval(x, y), b.eval(x, y));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _ => a.powf(b),
+                }
+            }
+            Node::Call(f, a) => f(a.eval(x, y)),
+        }
+    }
+}
+
+fn function(name: &str) -> Option<fn(f32) -> f32> {
+    Some(match name {
+        "sin" => f32::sin,
+        "cos" => f32::cos,
+        "tan" => f32::tan,
+        "asin" => f32::asin,
+        "acos" => f32::acos,
+        "atan" => f32::atan,
+        "sinh" => f32::sinh,
+        "cosh" => f32::cosh,
+        "tanh" => f32::tanh,
+        "exp" => f32::exp,
+        "ln" | "log" => f32::ln,
+        "sqrt" => f32::sqrt,
+        "abs" => f32::abs,
+        "floor" => f32::floor,
+        "ceil" => f32::ceil,
+        _ => return None,
+    })
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token<'a>],
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.next).copied()
+    }
+
+    fn take(&mut self) -> Option<Token<'a>> {
+        let token = self.peek();
+        self.next += 1;
+        token
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        match self.take() {
+            Some(Token::Symbol(c)) if c == symbol => Ok(()),
+            Some(token) => Err(format!("expected '{}', found {}", symbol, describe(token))),
+            None => Err(format!("expected '{}'", symbol)),
+        }
+    }
+
+    /// Sums and differences of terms.
+    fn expression(&mut self) -> Result<Node, String> {
+        let mut node = self.term()?;
+        while let Some(Token::Symbol(op @ ('+' | '-'))) = self.peek() {
+            self.take();
+            node = Node::Binary(op, Box::new(node), Box::new(self.term()?));
+        }
+        Ok(node)
+    }
+
+    /// Products and quotients.
+    fn term(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        while let Some(Token::Symbol(op @ ('*' | '/'))) = self.peek() {
+            self.take();
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            Some(Token::Symbol('-')) => {
+                self.take();
+                Ok(Node::Neg(Box::new(self.unary()?)))
+            }
+            Some(Token::Symbol('+')) => {
+                self.take();
+                self.unary()
+            }
+            _ => self.power(),
+        }
+    }
+
+    /// Powers, which bind tighter than negation on their left and group to the right.
+    fn power(&mut self) -> Result<Node, String> {
+        let base = self.atom()?;
+        if self.peek() != Some(Token::Symbol('^')) {
+            return Ok(base);
+        }
+        self.take();
+        Ok(Node::Binary('^', Box::new(base), Box::new(self.unary()?)))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.take() {
+            Some(Token::Number(n)) => n.parse::<f32>()
+                .map(Node::Number)
+                .map_err(|_| format!("'{}' is not a number", n)),
+            Some(Token::Identifier(name, false)) => match name {
+                VARIABLE => Ok(Node::X),
+                FIELD_VARIABLE => Ok(Node::Y),
+                "pi" => Ok(Node::Number(std::f32::consts::PI)),
+                "e" => Ok(Node::Number(std::f32::consts::E)),
+                _ => Err(format!("unknown name '{}'", name)),
+            },
+            Some(Token::Identifier(name, true)) => {
+                let f = function(name).ok_or_else(|| format!("unknown function '{}'", name))?;
+                self.expect('(')?;
+                let argument = self.expression()?;
+                self.expect(')')?;
+                Ok(Node::Call(f, Box::new(argument)))
+            }
+            Some(Token::Symbol('(')) => {
+                let node = self.expression()?;
+                self.expect(')')?;
+                Ok(node)
+            }
+            Some(token) => Err(format!("unexpected {}", describe(token))),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_follow_the_usual_precedence() {
+        let eval = |text: &str| XyFunction::parse(text).unwrap().eval(2.0, 3.0);
+        assert_eq!(eval("x*y + 1"), 7.0);
+        assert_eq!(eval("x - y - 1"), -2.0);
+        assert_eq!(eval("-x^2"), -4.0);
+        assert_eq!(eval("2^3^2"), 512.0);
+        assert_eq!(eval("x^-1"), 0.5);
+        assert_eq!(eval("(x + y) / 5"), 1.0);
+        assert_eq!(eval("abs(x - y) * sqrt(4)"), 2.0);
+    }
+
+    #[test]
+    fn pieces_apply_over_their_domains() {
+        let f = XyFunction::parse("{x < 0: y, x >= 0: x}").unwrap();
+        assert_eq!(f.eval(-1.0, 5.0), 5.0);
+        assert_eq!(f.eval(1.0, 5.0), 1.0);
+        assert!(XyFunction::parse("y {x > 0}").unwrap().eval(-1.0, 5.0).is_nan());
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        assert!(XyFunction::parse("a*x").is_err());
+        assert!(XyFunction::parse("foo(x)").is_err());
+        assert!(XyFunction::parse("x +").is_err());
+        assert!(XyFunction::parse("(x").is_err());
+    }
+}
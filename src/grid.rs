@@ -0,0 +1,134 @@
+use bevy::prelude::*;
+use crate::axis_text::MidAxisInfo;
+use crate::curve_material::CurveMaterial;
+use crate::geometry::{mid_axis_diff, DEFAULT_SCALE, MIN_MID_LINES};
+use crate::scaling::GraphingView;
+use serde::{Deserialize, Serialize};
+
+pub use crate::geometry::TickMode;
+
+/// Colours and widths of the grid lines.
+/// Widths are in logical pixels.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GridSettings {
+    pub main_color: Color,
+    pub main_width: f32,
+    pub mid_color: Color,
+    pub mid_width: f32,
+    pub min_color: Color,
+    pub min_width: f32,
+    /// Lines closer together than this many logical pixels are hidden.
+    pub fade_start: f32,
+    /// Lines further apart than this many logical pixels are fully opaque.
+    /// Lines between `fade_start` and `fade_end` apart are faded in.
+    pub fade_end: f32,
+    pub xticks: TickMode,
+    pub yticks: TickMode,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            main_color: Color::WHITE,
+            main_width: 1.5,
+            mid_color: Color::rgb_u8(100, 100, 100),
+            mid_width: 1.0,
+            min_color: Color::rgb_u8(50, 50, 50),
+            min_width: 1.0,
+            fade_start: 6.0,
+            fade_end: 30.0,
+            xticks: TickMode::Decimal,
+            yticks: TickMode::Decimal,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GridMaterials {
+    pub main_axis: Handle<CurveMaterial>,
+    pub mid_axis: Handle<CurveMaterial>,
+    pub min_axis: Handle<CurveMaterial>,
+}
+
+/// Fade grid lines in and out depending on how closely they are spaced on screen.
+pub fn grid_fade_system(
+    view: Res<GraphingView>,
+    window_descriptor: Res<WindowDescriptor>,
+    mid_axis_info: Res<MidAxisInfo>,
+    settings: Res<GridSettings>,
+    grid_materials: Res<GridMaterials>,
+    mut materials: ResMut<Assets<CurveMaterial>>,
+) {
+    if !(view.is_changed() || window_descriptor.is_changed()
+        || mid_axis_info.is_changed() || settings.is_changed()) {
+        return
+    }
+
+    let pixels_per_unit = window_descriptor.width / (2.0 * view.scale);
+    let (mid_color, min_color) = settings.faded_colors(&mid_axis_info, view.scale, pixels_per_unit);
+
+    if settings.is_changed() {
+        if let Some(main) = materials.get_mut(&grid_materials.main_axis) {
+            main.color = settings.main_color;
+            main.width = settings.main_width;
+        }
+    }
+
+    if let Some(material) = materials.get_mut(&grid_materials.mid_axis) {
+        material.color = mid_color;
+        material.width = settings.mid_width;
+    }
+
+    if let Some(material) = materials.get_mut(&grid_materials.min_axis) {
+        material.color = min_color;
+        material.width = settings.min_width;
+    }
+}
+
+impl GridSettings {
+    /// Opacity of lines `spacing` logical pixels apart.
+    pub fn fade(&self, spacing: f32) -> f32 {
+        let t = (spacing - self.fade_start) / (self.fade_end - self.fade_start);
+        let t = t.clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// Colours of the mid and minor lines, faded by how closely they are spaced.
+    /// Minor lines are also faded in by how far the view at `scale` is zoomed in
+    /// towards them becoming mid lines.
+    pub fn faded_colors(&self, mid_axis_info: &MidAxisInfo, scale: f32, pixels_per_unit: f32) -> (Color, Color) {
+        // Both axes share materials, so fade by the more closely spaced axis.
+        let min_info = mid_axis_info.calculate_min_axis_info();
+        let min_separation = min_info.xseparation.min(min_info.yseparation);
+        let mid_separation = mid_axis_info.xseparation.min(mid_axis_info.yseparation);
+        let mid_fade = self.fade(mid_separation * pixels_per_unit);
+        let min_fade = self.fade(min_separation * pixels_per_unit) * zoom_fade(scale / mid_separation);
+
+        let mut mid_color = self.mid_color;
+        mid_color.set_a(self.mid_color.a() * mid_fade);
+
+        // Fully faded in minor lines are drawn in the mid line colour, so there is no
+        // jump in colour when zooming in turns them into mid lines.
+        let mut min_color = lerp_color(self.min_color, self.mid_color, min_fade);
+        min_color.set_a(min_color.a() * min_fade);
+
+        (mid_color, min_color)
+    }
+}
+
+/// How far minor lines are faded in with `lines` mid lines either side of the centre.
+/// They are hidden at the default scale and fully in once zooming in would turn them
+/// into mid lines, so nothing jumps when the separation changes either way.
+fn zoom_fade(lines: f32) -> f32 {
+    let hidden = DEFAULT_SCALE / mid_axis_diff(DEFAULT_SCALE);
+    let t = ((hidden - lines) / (hidden - MIN_MID_LINES)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp_color(a: Color, b: Color, t: f32) -> Color {
+    let a = a.as_rgba_f32();
+    let b = b.as_rgba_f32();
+    let l = |i: usize| a[i] + (b[i] - a[i]) * t;
+    Color::rgba(l(0), l(1), l(2), l(3))
+}
@@ -0,0 +1,287 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, FilterMode, TextureDimension, TextureFormat};
+
+use crate::axis_text::AxisTextInfo;
+use crate::curve_material::CurveMaterial;
+use crate::domain_coloring::PIXELS_PER_TEXEL;
+use crate::gen_mesh::line_quads;
+use crate::geometry::{contour_label_position, format_level, Definitions, Grid};
+use crate::grid::GridSettings;
+use crate::project::{HeatmapEntry, Project};
+use crate::scaling::{GraphingBounds, GraphingView};
+
+/// Logical pixels
+pub const CONTOUR_WIDTH: f32 = 1.5;
+pub const COLOR_BAR_WIDTH: f32 = 16.0;
+pub const COLOR_BAR_HEIGHT: f32 = 200.0;
+/// Distance of the colour bar from the top right corner of the window.
+pub const COLOR_BAR_MARGIN: f32 = 16.0;
+pub const LEGEND_FONT_SIZE: f32 = 14.0;
+
+/// A function of x and y coloured over the graphing bounds, so panning
+/// within them keeps the view filled.
+#[derive(Component, Clone, Debug)]
+pub struct Heatmap {
+    pub entry: HeatmapEntry,
+    /// The expression with the definitions expanded and the parameters substituted.
+    pub expression: String,
+}
+
+/// The contour lines of the heatmap entity, in one mesh.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct ContourMesh(pub Entity);
+
+/// The colour bar and the contour labels of the heatmap entity, which are respawned with it.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct HeatmapLegend(pub Entity);
+
+/// A contour label, kept over its point in graph space as the view moves.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct ContourLabel(pub [f32; 2]);
+
+pub fn spawn_heatmap(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    curve_materials: &mut Assets<CurveMaterial>,
+    entry: &HeatmapEntry,
+    expression: String,
+) {
+    // the texture and contours are drawn by `regenerate_heatmap_system`
+    let heatmap = commands.spawn_bundle(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::ONE))),
+        material: materials.add(StandardMaterial {
+            unlit: true,
+            alpha_mode: AlphaMode::Blend,
+            ..Default::default()
+        }),
+        transform: Transform::from_xyz(0.0, 0.0, -0.04),
+        ..Default::default()
+    })
+        .insert(Heatmap {
+            entry: entry.clone(),
+            expression,
+        })
+        .id();
+
+    commands.spawn_bundle(MaterialMeshBundle {
+        mesh: meshes.add(line_quads(&[])),
+        material: curve_materials.add(CurveMaterial {
+            color: entry.contour_color,
+            width: CONTOUR_WIDTH,
+            ..Default::default()
+        }),
+        transform: Transform::from_xyz(0.0, 0.0, 0.03),
+        visibility: Visibility { is_visible: true },
+        ..Default::default()
+    })
+        .insert(ContourMesh(heatmap));
+}
+
+/// Samples the heatmap again when the bounds or the heatmap change,
+/// redrawing its texture, contours and legend.
+/// Contours and legends of heatmaps that no longer exist are despawned.
+#[allow(clippy::too_many_arguments)]
+pub fn regenerate_heatmap_system(
+    mut commands: Commands,
+    graphing_bounds: Res<GraphingBounds>,
+    view: Res<GraphingView>,
+    window: Res<WindowDescriptor>,
+    grid_settings: Res<GridSettings>,
+    text_info: Res<AxisTextInfo>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut heatmaps: Query<(Entity, &Heatmap, ChangeTrackers<Heatmap>, &Handle<StandardMaterial>, &mut Transform)>,
+    mut contours: Query<(Entity, &ContourMesh, &mut Handle<Mesh>)>,
+    legends: Query<(Entity, &HeatmapLegend)>,
+) {
+    let orphans = contours.iter()
+        .map(|(entity, contour, _)| (entity, contour.0))
+        .chain(legends.iter().map(|(entity, legend)| (entity, legend.0)))
+        .filter(|(_, heatmap)| !heatmaps.contains(*heatmap))
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+    for entity in orphans {
+        commands.entity(entity).despawn();
+    }
+
+    let pixels_per_unit = window.width / (2.0 * view.scale);
+    let (xbounds, ybounds) = (graphing_bounds.xbounds, graphing_bounds.ybounds);
+    let width = ((xbounds.end - xbounds.start) * pixels_per_unit / PIXELS_PER_TEXEL).ceil().max(2.0) as usize;
+    let height = ((ybounds.end - ybounds.start) * pixels_per_unit / PIXELS_PER_TEXEL).ceil().max(2.0) as usize;
+
+    for (heatmap_entity, heatmap, tracker, material, mut transform) in heatmaps.iter_mut() {
+        if !(graphing_bounds.is_changed() || tracker.is_changed()) {
+            continue;
+        }
+
+        let grid = match Grid::sample(&heatmap.expression, &graphing_bounds, (view.xscale, view.yscale), width, height) {
+            Ok(grid) => grid,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        let colormap = heatmap.entry.colormap;
+        let range = heatmap.entry.range(&grid);
+
+        let mut image = Image::new(
+            Extent3d { width: width as u32, height: height as u32, depth_or_array_layers: 1 },
+            TextureDimension::D2,
+            grid.colors(colormap, range),
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        image.sampler_descriptor.mag_filter = FilterMode::Linear;
+        image.sampler_descriptor.min_filter = FilterMode::Linear;
+
+        // as for domain colouring, a new image so the material is prepared again
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color_texture = Some(images.add(image));
+        }
+
+        transform.translation.x = xbounds.centre();
+        transform.translation.y = ybounds.centre();
+        transform.scale = Vec3::new(xbounds.end - xbounds.start, ybounds.end - ybounds.start, 1.0);
+
+        let levels = heatmap.entry.levels.iter()
+            .map(|&level| (level, grid.contour(level)))
+            .collect::<Vec<_>>();
+
+        let endpoints = levels.iter()
+            .flat_map(|(_, endpoints)| endpoints.iter().copied())
+            .collect::<Vec<_>>();
+        for (_, contour, mut mesh_handle) in contours.iter_mut() {
+            if contour.0 == heatmap_entity {
+                *mesh_handle = meshes.set(mesh_handle.clone(), line_quads(&endpoints));
+            }
+        }
+
+        for (entity, legend) in legends.iter() {
+            if legend.0 == heatmap_entity {
+                commands.entity(entity).despawn();
+            }
+        }
+
+        let style = |color: Color| TextStyle {
+            font: text_info.text_style.font.clone(),
+            font_size: LEGEND_FONT_SIZE,
+            color,
+        };
+
+        // labelled where each contour is nearest the centre of the view
+        for (level, endpoints) in levels.iter() {
+            if let Some(point) = contour_label_position(endpoints, view.centre) {
+                commands.spawn_bundle(TextBundle {
+                    style: label_style(point, &view, &window),
+                    text: Text::with_section(format_level(*level), style(heatmap.entry.contour_color), Default::default()),
+                    ..Default::default()
+                })
+                    .insert(ContourLabel(point))
+                    .insert(HeatmapLegend(heatmap_entity));
+            }
+        }
+
+        spawn_color_bar(&mut commands, &mut images, heatmap_entity, heatmap, range, style(grid_settings.main_color));
+    }
+}
+
+/// The colour bar in the top right corner, labelled with the ends of the range and the contour levels.
+fn spawn_color_bar(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    heatmap_entity: Entity,
+    heatmap: &Heatmap,
+    [low, high]: [f32; 2],
+    text_style: TextStyle,
+) {
+    let bar = Image::new(
+        Extent3d { width: 1, height: COLOR_BAR_HEIGHT as u32, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        heatmap.entry.colormap.strip(COLOR_BAR_HEIGHT as usize),
+        TextureFormat::Rgba8UnormSrgb,
+    );
+
+    commands.spawn_bundle(ImageBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(COLOR_BAR_MARGIN),
+                right: Val::Px(COLOR_BAR_MARGIN),
+                ..Default::default()
+            },
+            size: Size::new(Val::Px(COLOR_BAR_WIDTH), Val::Px(COLOR_BAR_HEIGHT)),
+            ..Default::default()
+        },
+        image: UiImage(images.add(bar)),
+        ..Default::default()
+    })
+        .insert(HeatmapLegend(heatmap_entity));
+
+    for (value, offset) in heatmap.entry.color_bar_ticks([low, high]) {
+        commands.spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(COLOR_BAR_MARGIN + offset * COLOR_BAR_HEIGHT - LEGEND_FONT_SIZE / 2.0),
+                    right: Val::Px(COLOR_BAR_MARGIN + COLOR_BAR_WIDTH + 4.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(format_level(value), text_style.clone(), Default::default()),
+            ..Default::default()
+        })
+            .insert(HeatmapLegend(heatmap_entity));
+    }
+}
+
+/// Places a label just above and right of a point in graph space.
+fn label_style([x, y]: [f32; 2], view: &GraphingView, window: &WindowDescriptor) -> Style {
+    let visible = view.visible_bounds(window.width, window.height);
+    let pixels_per_unit = window.width / (2.0 * view.scale);
+
+    Style {
+        position_type: PositionType::Absolute,
+        position: Rect {
+            left: Val::Px((x - visible.xbounds.start) * pixels_per_unit + 2.0),
+            bottom: Val::Px((y - visible.ybounds.start) * pixels_per_unit + 2.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Keeps contour labels over their contours as the view is panned and zoomed.
+pub fn position_contour_labels_system(
+    view: Res<GraphingView>,
+    window: Res<WindowDescriptor>,
+    mut labels: Query<(&ContourLabel, &mut Style)>,
+) {
+    if !(view.is_changed() || window.is_changed()) {
+        return
+    }
+
+    for (label, mut style) in labels.iter_mut() {
+        *style = label_style(label.0, &view, &window);
+    }
+}
+
+/// Substitutes the project's parameters into the heatmap again when they change.
+pub fn update_heatmap_parameters_system(
+    project: Res<Project>,
+    definitions: Res<Definitions>,
+    mut heatmaps: Query<&mut Heatmap>,
+) {
+    if !project.is_changed() {
+        return
+    }
+
+    for mut heatmap in heatmaps.iter_mut() {
+        match project.parse_heatmap(&heatmap.entry, &definitions) {
+            Ok(expression) if expression != heatmap.expression => heatmap.expression = expression,
+            Ok(_) => (),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
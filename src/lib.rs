@@ -0,0 +1,322 @@
+use bevy::prelude::*;
+pub use mathjit::expr_parse;
+
+pub mod geometry;
+pub mod gen_mesh;
+pub mod scaling;
+pub mod curve_material;
+pub mod grid;
+pub mod cli;
+pub mod export;
+pub mod data_series;
+pub mod expr_text;
+pub mod fit;
+pub mod project;
+pub mod watch;
+pub mod curves;
+pub mod field;
+pub mod trajectory;
+pub mod domain_coloring;
+pub mod heatmap;
+pub mod surface;
+pub mod sequence;
+pub mod axis_text;
+
+use curve_material::CurveMaterial;
+
+#[derive(Component, Copy, Clone)]
+pub struct MainCamera {}
+#[derive(Component, Copy, Clone)]
+pub struct UICamera {}
+
+/// Graphs the curves, data and fields of the `Project` resource, with panning, zooming and
+/// hot reloading of a `watch::WatchedFile`. Curves can be changed with `curves::Curves`.
+///
+/// Add after `DefaultPlugins`, with a `WindowDescriptor` of the window's size and
+/// optionally `FgeSettings`. The plugin spawns its own cameras.
+pub struct FgePlugin;
+
+/// Read when `FgePlugin` is added.
+#[derive(Clone, Debug)]
+pub struct FgeSettings {
+    /// Whether ctrl+E exports an svg, ctrl+S saves the project to its `project::ProjectFile`,
+    /// and ctrl+O or dropping a project file on the window opens one.
+    /// Applications that manage their own files can turn these off.
+    pub file_keybindings: bool,
+}
+
+impl Default for FgeSettings {
+    fn default() -> Self {
+        Self {
+            file_keybindings: true,
+        }
+    }
+}
+
+impl Plugin for FgePlugin {
+    fn build(&self, app: &mut App) {
+        let settings = app.world.get_resource::<FgeSettings>().cloned().unwrap_or_default();
+        if settings.file_keybindings {
+            app.add_system(export::export_keybind_system)
+                .add_system(project::save_project_system)
+                .add_system(project::open_project_system);
+        }
+
+        app.init_resource::<project::Project>()
+            .init_resource::<project::ProjectFile>()
+            .init_resource::<data_series::LoadedSeries>()
+            .init_resource::<geometry::Definitions>()
+            .init_resource::<surface::ViewMode>()
+            .init_resource::<surface::OrbitCamera>()
+            // before other startup systems, so they can use `curves::Curves`
+            .add_startup_system_to_stage(StartupStage::PreStartup, setup)
+            .add_plugin(MaterialPlugin::<CurveMaterial>::default())
+            .add_plugin(field::FieldPlugin)
+            .add_system_set(SystemSet::new()
+                        .label("input")
+                        .with_system(scaling::zoom_system)
+                        .with_system(scaling::pan_system)
+                        .with_system(scaling::window_resize))
+            .add_system(scaling::update_projection_system.after("input"))
+            .add_system(curve_material::update_curve_viewport_system)
+            .add_system(scaling::recalculate_graphing_bounds_system
+                        .label("calc bounds").after("input"))
+            .add_system(gen_mesh::regenerate_meshes_system.after("calc bounds"))
+            .add_system(data_series::regenerate_data_series_system.after("calc bounds"))
+            .add_system(curves::resolve_definitions_system)
+            .add_system(field::update_field_parameters_system.label("field parameters"))
+            .add_system(field::regenerate_fields_system.after("calc bounds").after("field parameters"))
+            .add_system(trajectory::place_trajectory_system.label("place trajectories"))
+            .add_system(trajectory::regenerate_trajectories_system
+                        .after("calc bounds").after("field parameters").after("place trajectories"))
+            .add_system(domain_coloring::update_domain_coloring_parameters_system.label("complex parameters"))
+            .add_system(domain_coloring::regenerate_domain_coloring_system
+                        .after("calc bounds").after("complex parameters"))
+            .add_system(heatmap::update_heatmap_parameters_system.label("heatmap parameters"))
+            .add_system(heatmap::regenerate_heatmap_system
+                        .after("calc bounds").after("heatmap parameters"))
+            .add_system(heatmap::position_contour_labels_system.after("input"))
+            .add_system(surface::surface_keys_system.label("view mode"))
+            .add_system(surface::apply_view_mode_system.after("view mode"))
+            .add_system(surface::orbit_camera_system.after("view mode"))
+            .add_system(surface::update_surface_parameters_system.label("surface parameters"))
+            .add_system(surface::regenerate_surface_system
+                        .after("view mode").after("surface parameters"))
+            .add_system(sequence::update_sequence_parameters_system.label("sequence parameters"))
+            .add_system(sequence::cobweb_input_system.label("cobweb input"))
+            .add_system(sequence::regenerate_sequences_system
+                        .after("calc bounds").after("sequence parameters"))
+            .add_system(sequence::regenerate_cobwebs_system
+                        .after("calc bounds").after("sequence parameters").after("cobweb input"))
+            .add_system(grid::grid_fade_system.after("calc bounds"))
+            .add_system(axis_text::regenerate_axis_text_system
+                        .label("gen axis text").after("calc bounds"))
+            .add_system(axis_text::position_axis_text_system
+                        .after("gen axis text").after("view mode"))
+            .add_system(watch::watch_file_system);
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut curve_materials: ResMut<Assets<CurveMaterial>>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+    project: Res<project::Project>,
+    series: Res<data_series::LoadedSeries>,
+) {
+    // resolve definitions and parse expressions, skipping any with errors
+    let definitions = project.definitions().unwrap_or_else(|e| {
+        println!("{}", e);
+        geometry::Definitions::default()
+    });
+    let exprs = project.curves.iter()
+        .filter_map(|curve| match project.parse_curve(curve, &definitions) {
+            Ok(expr) => Some((expr, curve)),
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // setup bounds
+    let (graphing_bounds, view) = {
+        use scaling::*;
+        let mut camera = OrthographicCameraBundle::new_3d();
+        let mut ui_camera = UiCameraBundle::default();
+
+        // no need to initialize projection - will be done in scaling::update_projection_system
+        camera.orthographic_projection.scaling_mode = bevy::render::camera::ScalingMode::None;
+        camera.transform = Transform::from_xyz(0.0, 0.0, 999.0).looking_at(Vec3::ZERO, Vec3::Y);
+        ui_camera.orthographic_projection.scaling_mode = bevy::render::camera::ScalingMode::None;
+        ui_camera.transform = Transform::from_xyz(0.0, 0.0, 999.0).looking_at(Vec3::ZERO, Vec3::Y);
+
+        commands.spawn_bundle(camera)
+            .insert(MainCamera {});
+        commands.spawn_bundle(ui_camera)
+            .insert(UICamera {});
+
+        // temporarily remake window descriptor to calc regen bounds.
+        // we cannot insert it here and must in main for some reason.
+        let window_descriptor = project.window_descriptor();
+        let view = project.graphing_view();
+        let graphing_bounds = recalculate_graphing_bounds(&view, &window_descriptor);
+        
+        (graphing_bounds, view)
+    };
+
+    // setup materials
+    let grid_settings = project.grid.clone();
+    let grid_materials = {
+        // mid and min axis colours are set by grid::grid_fade_system
+        grid::GridMaterials {
+            main_axis: curve_materials.add(CurveMaterial {
+                color: grid_settings.main_color,
+                width: grid_settings.main_width,
+                ..Default::default()
+            }),
+            mid_axis: curve_materials.add(CurveMaterial {
+                color: grid_settings.mid_color,
+                width: grid_settings.mid_width,
+                ..Default::default()
+            }),
+            min_axis: curve_materials.add(CurveMaterial {
+                color: grid_settings.min_color,
+                width: grid_settings.min_width,
+                ..Default::default()
+            }),
+        }
+    };
+
+    let mid_axis_info = { // Text info
+        axis_text::recalculate_mid_axis_info(&graphing_bounds, &view, &grid_settings)
+    };
+
+    // spawn meshes
+    {
+        for (expr, curve) in exprs {
+            curves::spawn_curve(&mut commands, &mut meshes, &mut curve_materials, expr, curve, &graphing_bounds, &view);
+        }
+
+        for (data, entry) in series.0.iter().cloned().zip(&project.data) {
+            curves::spawn_data_series(&mut commands, &mut meshes, &mut curve_materials, data, entry, &graphing_bounds, &view);
+        }
+
+        for entry in &project.fields {
+            match project.parse_field(entry, &definitions) {
+                Ok(kind) => {
+                    field::spawn_field(&mut commands, &mut meshes, &mut curve_materials, entry, kind, &graphing_bounds);
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        if let Some(entry) = &project.complex {
+            match project.parse_complex(entry, &definitions) {
+                Ok(expression) => {
+                    domain_coloring::spawn_domain_coloring(&mut commands, &mut meshes, &mut standard_materials, entry, expression);
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        if let Some(entry) = &project.heatmap {
+            match project.parse_heatmap(entry, &definitions) {
+                Ok(expression) => {
+                    heatmap::spawn_heatmap(&mut commands, &mut meshes, &mut standard_materials, &mut curve_materials, entry, expression);
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        for entry in &project.sequences {
+            match project.parse_sequence(entry, &definitions) {
+                Ok(expression) => {
+                    sequence::spawn_sequence(&mut commands, &mut meshes, entry, expression);
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        for entry in &project.cobwebs {
+            match project.parse_cobweb(entry, &definitions) {
+                Ok(expression) => {
+                    sequence::spawn_cobweb(&mut commands, &mut meshes, &mut curve_materials, entry, expression);
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        // opened in surface mode, since a surface is the only thing there is to see in 3D
+        if let Some(entry) = &project.surface {
+            match project.parse_surface(entry, &definitions) {
+                Ok(expression) => {
+                    surface::spawn_surface(&mut commands, &mut meshes, &mut standard_materials, entry, expression);
+                    commands.insert_resource(surface::ViewMode::Surface);
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        let main_axis = meshes.add(gen_mesh::gen_main_axis(graphing_bounds.xbounds, graphing_bounds.ybounds, (view.xscale, view.yscale)));
+        let mid_axis = meshes.add(gen_mesh::gen_mid_axis(mid_axis_info, &graphing_bounds));
+        let min_axis = meshes.add(gen_mesh::gen_min_axis(mid_axis_info.calculate_min_axis_info(), &graphing_bounds));
+
+        let grid_mesh_handles = gen_mesh::GridMeshHandles {
+            main_axis,
+            mid_axis,
+            min_axis,
+        };
+
+        commands.spawn_bundle(MaterialMeshBundle {
+            mesh: grid_mesh_handles.main_axis.clone(),
+            material: grid_materials.main_axis.clone(),
+            transform: Transform::from_xyz(0.0, 0.0, 0.02),
+            visibility: Visibility { is_visible: true },
+            ..Default::default()
+        });
+
+        commands.spawn_bundle(MaterialMeshBundle {
+            mesh: grid_mesh_handles.mid_axis.clone(),
+            material: grid_materials.mid_axis.clone(),
+            transform: Transform::from_xyz(0.0, 0.0, 0.01),
+            visibility: Visibility { is_visible: true },
+            ..Default::default()
+        });
+
+        commands.spawn_bundle(MaterialMeshBundle {
+            mesh: grid_mesh_handles.min_axis.clone(),
+            material: grid_materials.min_axis.clone(),
+            visibility: Visibility { is_visible: true },
+            ..Default::default()
+        });
+
+        commands.insert_resource(grid_mesh_handles);
+    };
+
+    { // axis info
+        let font = asset_server.load("fonts/Lato-Light.ttf");
+        let text_style = TextStyle {
+            font,
+            font_size: axis_text::AXIS_FONT_SIZE,
+            color: Color::WHITE,
+        };
+
+        let axis_text_info = axis_text::AxisTextInfo {
+            text_style,
+        };
+
+        commands.insert_resource(axis_text_info);
+
+    }
+
+    commands.insert_resource(definitions);
+    commands.insert_resource(graphing_bounds);
+    commands.insert_resource(view);
+    commands.insert_resource(mid_axis_info);
+    commands.insert_resource(grid_settings);
+    commands.insert_resource(grid_materials);
+}
+
@@ -0,0 +1,816 @@
+use bevy::prelude::*;
+use bevy::window::FileDragAndDrop;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::cli::Args;
+use crate::curve_material::{curve_color, CurveMaterial, LineStyle};
+use crate::curves::{spawn_curve, spawn_data_series};
+use crate::data_series::{Column, DataSeries, DataSource, MarkerShape};
+use crate::domain_coloring::{spawn_domain_coloring, DomainColoring};
+use crate::expr_text;
+use crate::field::{spawn_field, Field};
+use crate::heatmap::{spawn_heatmap, ContourMesh, Heatmap, HeatmapLegend};
+use crate::sequence::{spawn_cobweb, spawn_sequence, Cobweb, CobwebMesh, Sequence};
+use crate::surface::{spawn_surface, Surface};
+use crate::trajectory::{Trajectories, TrajectoryMesh};
+use crate::gen_mesh::{Expression, ExpressionText, StrokeStyle};
+use crate::geometry::{
+    self, Colormap, ComplexExpression, Definitions, FieldKind, Grid, MagnitudeStyle, COMPLEX_VARIABLE, PREVIOUS_TERM,
+    SEQUENCE_VARIABLE,
+};
+use crate::grid::GridSettings;
+use crate::scaling::{self, recalculate_graphing_bounds, AxisScale, GraphingView};
+
+/// Version of the project file format written by this build, raised whenever the
+/// format gains something an older build would silently drop:
+///
+/// 1. curves, data and parameters
+/// 2. fields
+/// 3. initial conditions of the solution curves of fields
+/// 4. domain colouring of a complex function
+/// 5. a heatmap
+/// 6. a surface
+/// 7. sequences and cobwebs
+///
+/// Older files are read with defaults for anything they are missing, and newer files are refused.
+pub const PROJECT_VERSION: u32 = 7;
+
+pub const PROJECT_EXTENSION: &str = ".fge";
+
+/// File saved to by ctrl+S when no project was opened.
+pub const DEFAULT_PROJECT_PATH: &str = "project.fge";
+
+/// Everything needed to reopen a graph, saved as RON.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Project {
+    pub version: u32,
+    pub window: WindowSize,
+    pub view: ViewState,
+    pub grid: GridSettings,
+    pub parameters: Vec<Parameter>,
+    pub curves: Vec<CurveEntry>,
+    pub data: Vec<DataEntry>,
+    pub fields: Vec<FieldEntry>,
+    pub complex: Option<ComplexEntry>,
+    pub heatmap: Option<HeatmapEntry>,
+    pub surface: Option<SurfaceEntry>,
+    pub sequences: Vec<SequenceEntry>,
+    pub cobwebs: Vec<CobwebEntry>,
+}
+
+/// Logical pixels
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct WindowSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+/// The saved `GraphingView`.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewState {
+    pub centre: [f32; 2],
+    pub scale: f32,
+    pub xscale: AxisScale,
+    pub yscale: AxisScale,
+}
+
+/// A named value substituted into every expression that uses it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Parameter {
+    pub name: String,
+    pub value: f64,
+    /// Range a slider for the parameter should cover.
+    /// Kept for editors of the project, fge itself has no sliders.
+    #[serde(default)]
+    pub range: Option<[f64; 2]>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CurveEntry {
+    /// The expression as written, which may use parameters.
+    pub expression: String,
+    pub color: Color,
+    /// Logical pixels
+    pub width: f32,
+    pub glow: f32,
+    pub line_style: LineStyle,
+    pub stroke: StrokeStyle,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DataEntry {
+    /// Relative paths are relative to the project file.
+    pub source: DataSource,
+    pub color: Color,
+    /// No markers are drawn if this is `None`.
+    pub marker: Option<MarkerShape>,
+    /// Logical pixels
+    pub marker_size: f32,
+    pub connect: bool,
+}
+
+/// A slope or vector field of functions of x and y.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FieldEntry {
+    /// The expressions as written, which may use parameters and definitions.
+    pub kind: FieldKind,
+    pub color: Color,
+    /// Logical pixels
+    pub width: f32,
+    /// Ignored for slope fields.
+    pub magnitude: MagnitudeStyle,
+    /// Initial conditions of solution curves drawn on the field, on the axes
+    /// before they are transformed by their `AxisScale`.
+    pub trajectories: Vec<[f32; 2]>,
+    pub trajectory_color: Color,
+}
+
+/// A function of a complex variable z, drawn by domain colouring.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ComplexEntry {
+    /// The expression as written, which may use parameters and definitions.
+    pub expression: String,
+    pub contours: bool,
+}
+
+/// A function of x and y drawn as a colour-mapped heatmap, with labelled contours.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeatmapEntry {
+    /// The expression as written, which may use parameters and definitions.
+    pub expression: String,
+    pub colormap: Colormap,
+    /// Values at the ends of the colormap, fitted to the values sampled if `None`.
+    pub range: Option<[f32; 2]>,
+    /// Values to draw contours at.
+    pub levels: Vec<f32>,
+    pub contour_color: Color,
+}
+
+/// A function of x and y drawn as a 3D surface, shown by pressing Tab.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SurfaceEntry {
+    /// The expression as written, which may use parameters and definitions.
+    pub expression: String,
+    pub color: Color,
+    pub wireframe: bool,
+    pub wireframe_color: Color,
+}
+
+/// An explicit sequence `a(n)`, drawn as a marker for each integer n.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SequenceEntry {
+    /// The expression as written, which may use parameters and definitions.
+    pub expression: String,
+    pub color: Color,
+    pub marker: MarkerShape,
+    /// Logical pixels
+    pub marker_size: f32,
+}
+
+/// A recurrence `x_{n+1} = f(x_n)`, drawn as a cobweb diagram against y = x.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CobwebEntry {
+    /// The expression as written, which may use parameters and definitions.
+    pub expression: String,
+    /// The first term, x_0.
+    pub start: f32,
+    pub iterations: usize,
+    pub color: Color,
+    pub path_color: Color,
+}
+
+/// Where the open project was loaded from, and is saved to.
+#[derive(Clone, Debug, Default)]
+pub struct ProjectFile(pub Option<PathBuf>);
+
+impl Default for Project {
+    fn default() -> Self {
+        Self {
+            version: PROJECT_VERSION,
+            window: WindowSize {
+                width: scaling::DEFAULT_WINDOW_WIDTH,
+                height: scaling::DEFAULT_WINDOW_HEIGHT,
+            },
+            view: ViewState::default(),
+            grid: GridSettings::default(),
+            parameters: Vec::new(),
+            curves: Vec::new(),
+            data: Vec::new(),
+            fields: Vec::new(),
+            complex: None,
+            heatmap: None,
+            surface: None,
+            sequences: Vec::new(),
+            cobwebs: Vec::new(),
+        }
+    }
+}
+
+impl Default for ViewState {
+    fn default() -> Self {
+        Self {
+            centre: [0.0, 0.0],
+            scale: scaling::DEFAULT_SCALE,
+            xscale: AxisScale::Linear,
+            yscale: AxisScale::Linear,
+        }
+    }
+}
+
+impl Default for CurveEntry {
+    fn default() -> Self {
+        let material = CurveMaterial::default();
+        Self {
+            expression: String::new(),
+            color: material.color,
+            width: material.width,
+            glow: material.glow,
+            line_style: material.line_style,
+            stroke: StrokeStyle::default(),
+        }
+    }
+}
+
+impl Default for DataEntry {
+    fn default() -> Self {
+        Self {
+            source: DataSource {
+                path: PathBuf::new(),
+                xcol: Column::Index(0),
+                ycol: Column::Index(1),
+            },
+            color: Color::WHITE,
+            marker: Some(MarkerShape::Circle),
+            marker_size: 6.0,
+            connect: false,
+        }
+    }
+}
+
+impl Default for FieldEntry {
+    fn default() -> Self {
+        Self {
+            kind: FieldKind::Slope(String::new()),
+            color: Color::rgb(0.6, 0.6, 0.6),
+            width: 1.5,
+            magnitude: MagnitudeStyle::Length,
+            trajectories: Vec::new(),
+            trajectory_color: curve_color(1),
+        }
+    }
+}
+
+impl Default for HeatmapEntry {
+    fn default() -> Self {
+        Self {
+            expression: String::new(),
+            colormap: Colormap::Viridis,
+            range: None,
+            levels: Vec::new(),
+            contour_color: Color::WHITE,
+        }
+    }
+}
+
+impl Default for SurfaceEntry {
+    fn default() -> Self {
+        Self {
+            expression: String::new(),
+            color: Color::rgb(0.3, 0.6, 0.9),
+            wireframe: false,
+            wireframe_color: Color::rgb(0.1, 0.1, 0.1),
+        }
+    }
+}
+
+impl Default for SequenceEntry {
+    fn default() -> Self {
+        Self {
+            expression: String::new(),
+            color: curve_color(2),
+            marker: MarkerShape::Circle,
+            marker_size: 6.0,
+        }
+    }
+}
+
+impl Default for CobwebEntry {
+    fn default() -> Self {
+        Self {
+            expression: String::new(),
+            start: 0.5,
+            iterations: 20,
+            color: CurveMaterial::default().color,
+            path_color: curve_color(1),
+        }
+    }
+}
+
+impl Project {
+    /// The project given on the command line, or an empty one, with the
+    /// other arguments applied.
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let mut project = match &args.project {
+            Some(path) => Project::load(path)?,
+            None => Project::default(),
+        };
+
+        if let Some(xscale) = args.xscale {
+            project.view.xscale = xscale;
+        }
+        if let Some(yscale) = args.yscale {
+            project.view.yscale = yscale;
+        }
+        if let Some(xticks) = args.xticks {
+            project.grid.xticks = xticks;
+        }
+        if let Some(yticks) = args.yticks {
+            project.grid.yticks = yticks;
+        }
+
+        for expression in &args.expressions {
+            let color = project.next_color();
+            project.curves.push(CurveEntry {
+                expression: expression.clone(),
+                color,
+                ..Default::default()
+            });
+        }
+
+        for source in &args.data {
+            let color = project.next_color();
+            project.data.push(DataEntry {
+                source: source.clone(),
+                color,
+                marker: args.marker,
+                marker_size: args.marker_size,
+                connect: args.connect,
+            });
+        }
+
+        for kind in &args.fields {
+            project.fields.push(FieldEntry {
+                kind: kind.clone(),
+                magnitude: args.magnitude,
+                trajectories: args.trajectories.clone(),
+                ..Default::default()
+            });
+        }
+
+        if let Some(expression) = &args.complex {
+            project.complex = Some(ComplexEntry {
+                expression: expression.clone(),
+                contours: args.contours,
+            });
+        }
+
+        if let Some(expression) = &args.heatmap {
+            project.heatmap = Some(HeatmapEntry {
+                expression: expression.clone(),
+                colormap: args.colormap,
+                levels: args.levels.clone(),
+                ..Default::default()
+            });
+        }
+
+        if let Some(expression) = &args.surface {
+            project.surface = Some(SurfaceEntry {
+                expression: expression.clone(),
+                wireframe: args.wireframe,
+                ..Default::default()
+            });
+        }
+
+        for expression in &args.sequences {
+            project.sequences.push(SequenceEntry {
+                expression: expression.clone(),
+                ..Default::default()
+            });
+        }
+
+        for expression in &args.cobwebs {
+            let defaults = CobwebEntry::default();
+            project.cobwebs.push(CobwebEntry {
+                expression: expression.clone(),
+                start: args.start.unwrap_or(defaults.start),
+                iterations: args.iterations.unwrap_or(defaults.iterations),
+                ..defaults
+            });
+        }
+
+        Ok(project)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+        let mut project = Self::from_ron(&text)
+            .map_err(|e| format!("Error in {}: {}", path.display(), e))?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for entry in &mut project.data {
+            if entry.source.path.is_relative() {
+                entry.source.path = dir.join(&entry.source.path);
+            }
+        }
+
+        Ok(project)
+    }
+
+    /// Reads a project, refusing any written by a newer build that may have
+    /// things this build would drop.
+    pub fn from_ron(text: &str) -> Result<Self, String> {
+        let mut project = ron::from_str::<Project>(text).map_err(|e| e.to_string())?;
+
+        if project.version > PROJECT_VERSION {
+            return Err(format!(
+                "the project is from a newer version of fge (format {}, this build reads up to {})",
+                project.version, PROJECT_VERSION,
+            ));
+        }
+        project.version = PROJECT_VERSION;
+
+        Ok(project)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        // keep data paths relative, so the project can be moved with its data
+        let mut project = self.clone();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for entry in &mut project.data {
+            if let Ok(relative) = entry.source.path.strip_prefix(dir) {
+                entry.source.path = relative.to_path_buf();
+            }
+        }
+
+        let text = ron::ser::to_string_pretty(&project, ron::ser::PrettyConfig::new())
+            .map_err(|e| e.to_string())?;
+        std::fs::write(path, text)
+            .map_err(|e| format!("Error writing {}: {}", path.display(), e))
+    }
+
+    /// Colour for the next curve or data series added.
+    pub fn next_color(&self) -> Color {
+        curve_color(self.curves.len() + self.data.len())
+    }
+
+    /// Parses everything graphed, so mistakes are found before a window is opened.
+    pub fn check(&self) -> Result<(), String> {
+        let definitions = self.definitions()?;
+        for curve in &self.curves {
+            self.parse_curve(curve, &definitions)?;
+        }
+        for field in &self.fields {
+            self.parse_field(field, &definitions)?;
+        }
+        if let Some(complex) = &self.complex {
+            self.parse_complex(complex, &definitions)?;
+        }
+        if let Some(heatmap) = &self.heatmap {
+            self.parse_heatmap(heatmap, &definitions)?;
+        }
+        if let Some(surface) = &self.surface {
+            self.parse_surface(surface, &definitions)?;
+        }
+        for sequence in &self.sequences {
+            self.parse_sequence(sequence, &definitions)?;
+        }
+        for cobweb in &self.cobwebs {
+            self.parse_cobweb(cobweb, &definitions)?;
+        }
+        Ok(())
+    }
+
+    /// The variables and functions defined among the project's curves.
+    pub fn definitions(&self) -> Result<Definitions, String> {
+        Definitions::resolve(self.curves.iter().map(|curve| curve.expression.as_str()))
+    }
+
+    /// Parses a curve's expression with the definitions expanded and the parameters substituted.
+    /// Definitions may use parameters.
+    pub fn parse_curve(&self, curve: &CurveEntry, definitions: &Definitions) -> Result<Expression, String> {
+        let expression = definitions.curve_expression(&curve.expression)?;
+        Expression::parse(&self.substitute_parameters(&expression))
+    }
+
+    /// A field's expressions with the definitions expanded and the parameters substituted,
+    /// checked to parse.
+    pub fn parse_field(&self, field: &FieldEntry, definitions: &Definitions) -> Result<FieldKind, String> {
+        let kind = field.kind.try_map(|text| self.expand(text, definitions, None))?;
+        kind.check()?;
+        Ok(kind)
+    }
+
+    /// Lowers the complex function with the definitions expanded and the parameters substituted.
+    pub fn parse_complex(&self, complex: &ComplexEntry, definitions: &Definitions) -> Result<ComplexExpression, String> {
+        ComplexExpression::parse(&self.expand(&complex.expression, definitions, Some(COMPLEX_VARIABLE))?)
+    }
+
+    /// A heatmap's expression with the definitions expanded and the parameters substituted,
+    /// checked to parse.
+    pub fn parse_heatmap(&self, heatmap: &HeatmapEntry, definitions: &Definitions) -> Result<String, String> {
+        geometry::parse_xy_function(&heatmap.expression, |text| self.expand(text, definitions, None))
+    }
+
+    /// A surface's expression with the definitions expanded and the parameters substituted,
+    /// checked to parse.
+    pub fn parse_surface(&self, surface: &SurfaceEntry, definitions: &Definitions) -> Result<String, String> {
+        geometry::parse_xy_function(&surface.expression, |text| self.expand(text, definitions, None))
+    }
+
+    /// A sequence's expression of n, written in x, with the definitions expanded and the
+    /// parameters substituted.
+    pub fn parse_sequence(&self, sequence: &SequenceEntry, definitions: &Definitions) -> Result<String, String> {
+        geometry::parse_sequence(&sequence.expression, |text| self.expand(text, definitions, Some(SEQUENCE_VARIABLE)))
+    }
+
+    /// A recurrence's expression of the previous term, written in x, with the definitions
+    /// expanded and the parameters substituted.
+    pub fn parse_cobweb(&self, cobweb: &CobwebEntry, definitions: &Definitions) -> Result<String, String> {
+        geometry::parse_recurrence(&cobweb.expression, |text| self.expand(text, definitions, Some(PREVIOUS_TERM)))
+    }
+
+    /// Expands the definitions used by `text`, other than its own `variable`, then
+    /// substitutes the parameters, which definitions may use.
+    fn expand(&self, text: &str, definitions: &Definitions, variable: Option<&str>) -> Result<String, String> {
+        Ok(self.substitute_parameters(&definitions.expand_bound(text, variable)?))
+    }
+
+    fn substitute_parameters(&self, text: &str) -> String {
+        let values = self.parameters.iter()
+            .map(|p| (p.name.as_str(), p.value))
+            .collect::<Vec<_>>();
+        expr_text::substitute(text, &values)
+    }
+
+    pub fn graphing_view(&self) -> GraphingView {
+        GraphingView {
+            centre: self.view.centre,
+            scale: self.view.scale,
+            xscale: self.view.xscale,
+            yscale: self.view.yscale,
+        }
+    }
+
+    pub fn window_descriptor(&self) -> WindowDescriptor {
+        WindowDescriptor {
+            title: "Grapher".to_string(),
+            width: self.window.width,
+            height: self.window.height,
+            vsync: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&GraphingView> for ViewState {
+    fn from(view: &GraphingView) -> Self {
+        Self {
+            centre: view.centre,
+            scale: view.scale,
+            xscale: view.xscale,
+            yscale: view.yscale,
+        }
+    }
+}
+
+impl HeatmapEntry {
+    /// Values coloured by the ends of the colormap for a sampled grid.
+    pub fn range(&self, grid: &Grid) -> [f32; 2] {
+        self.range
+            .or_else(|| grid.range().map(|range| self.colormap.fit_range(range)))
+            .unwrap_or([0.0, 1.0])
+    }
+
+    /// Values labelled on the colour bar, the ends of `range` and the contour levels
+    /// within it, with their distance down the bar from 0 to 1.
+    pub fn color_bar_ticks(&self, [low, high]: [f32; 2]) -> Vec<(f32, f32)> {
+        let span = if high > low { high - low } else { 1.0 };
+        let mut ticks = vec![(high, 0.0), (low, 1.0)];
+        ticks.extend(self.levels.iter()
+            .filter(|&&level| low < level && level < high)
+            .map(|&level| (level, (high - level) / span)));
+        ticks
+    }
+}
+
+impl CurveEntry {
+    pub fn from_curve(expression: &str, stroke: StrokeStyle, material: &CurveMaterial) -> Self {
+        Self {
+            expression: expression.to_string(),
+            color: material.color,
+            width: material.width,
+            glow: material.glow,
+            line_style: material.line_style,
+            stroke,
+        }
+    }
+
+    pub fn material(&self) -> CurveMaterial {
+        CurveMaterial {
+            color: self.color,
+            width: self.width,
+            glow: self.glow,
+            line_style: self.line_style,
+            ..Default::default()
+        }
+    }
+}
+
+fn ctrl_pressed(keys: &Input<KeyCode>, key: KeyCode) -> bool {
+    let ctrl = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl);
+    ctrl && keys.just_pressed(key)
+}
+
+/// Saves the graph on ctrl+S, to the opened project or `DEFAULT_PROJECT_PATH`.
+#[allow(clippy::too_many_arguments)]
+pub fn save_project_system(
+    keys: Res<Input<KeyCode>>,
+    file: Res<ProjectFile>,
+    window_descriptor: Res<WindowDescriptor>,
+    view: Res<GraphingView>,
+    grid: Res<GridSettings>,
+    materials: Res<Assets<CurveMaterial>>,
+    curves: Query<(&ExpressionText, &StrokeStyle, &Handle<CurveMaterial>)>,
+    fields: Query<(&Field, &Trajectories)>,
+    complex: Query<&DomainColoring>,
+    heatmaps: Query<&Heatmap>,
+    surfaces: Query<&Surface>,
+    sequences: Query<&Sequence>,
+    cobwebs: Query<(&Cobweb, &CobwebMesh)>,
+    mut project: ResMut<Project>,
+) {
+    if !ctrl_pressed(&keys, KeyCode::S) {
+        return
+    }
+
+    project.window = WindowSize {
+        width: window_descriptor.width,
+        height: window_descriptor.height,
+    };
+    project.view = ViewState::from(&*view);
+    project.grid = grid.clone();
+    project.curves = curves.iter()
+        .map(|(text, stroke, material)| {
+            let material = materials.get(material).copied().unwrap_or_default();
+            CurveEntry::from_curve(&text.0, *stroke, &material)
+        })
+        .collect();
+    project.fields = fields.iter()
+        .map(|(field, trajectories)| field.to_entry(trajectories))
+        .collect();
+    project.complex = complex.iter().next().map(|plot| plot.entry.clone());
+    project.heatmap = heatmaps.iter().next().map(|heatmap| heatmap.entry.clone());
+    project.surface = surfaces.iter().next().map(|surface| surface.entry.clone());
+    project.sequences = sequences.iter().map(|sequence| sequence.entry.clone()).collect();
+    // one entry for the three parts of each cobweb
+    project.cobwebs = cobwebs.iter()
+        .filter(|(_, part)| **part == CobwebMesh::Path)
+        .map(|(cobweb, _)| cobweb.entry.clone())
+        .collect();
+
+    let path = file.0.clone().unwrap_or_else(|| DEFAULT_PROJECT_PATH.into());
+    match project.save(&path) {
+        Ok(()) => println!("Saved project to {}", path.display()),
+        Err(e) => println!("Error saving project: {}", e),
+    }
+}
+
+/// Opens a project file dropped on the window, or reverts to the saved project file
+/// on ctrl+O, replacing everything graphed. A dropped project is saved to from then on.
+/// Nothing is changed if the project cannot be loaded.
+#[allow(clippy::too_many_arguments)]
+pub fn open_project_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut dropped: EventReader<FileDragAndDrop>,
+    mut file: ResMut<ProjectFile>,
+    mut project: ResMut<Project>,
+    mut view: ResMut<GraphingView>,
+    mut grid: ResMut<GridSettings>,
+    mut windows: ResMut<Windows>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<CurveMaterial>>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
+    graphed: Query<Entity, Or<(With<Expression>, With<DataSeries>, With<Field>, With<TrajectoryMesh>, With<DomainColoring>, With<Sequence>, With<Cobweb>)>>,
+    heatmaps: Query<Entity, Or<(With<Heatmap>, With<ContourMesh>, With<HeatmapLegend>)>>,
+    surfaces: Query<Entity, With<Surface>>,
+) {
+    let dropped = dropped.iter()
+        .filter_map(|event| match event {
+            FileDragAndDrop::DroppedFile { path_buf, .. } => Some(path_buf.clone()),
+            _ => None,
+        })
+        .filter(|path| path.to_string_lossy().ends_with(PROJECT_EXTENSION))
+        .last();
+
+    let path = match dropped {
+        Some(path) => path,
+        None if ctrl_pressed(&keys, KeyCode::O) => file.0.clone().unwrap_or_else(|| DEFAULT_PROJECT_PATH.into()),
+        None => return,
+    };
+
+    let loaded = Project::load(&path).and_then(|loaded| {
+        let definitions = loaded.definitions()?;
+        let expressions = loaded.curves.iter()
+            .map(|curve| loaded.parse_curve(curve, &definitions))
+            .collect::<Result<Vec<_>, String>>()?;
+        let series = loaded.data.iter()
+            .map(|entry| DataSeries::load(&entry.source))
+            .collect::<Result<Vec<_>, String>>()?;
+        let fields = loaded.fields.iter()
+            .map(|field| loaded.parse_field(field, &definitions))
+            .collect::<Result<Vec<_>, String>>()?;
+        let complex = loaded.complex.as_ref()
+            .map(|complex| loaded.parse_complex(complex, &definitions))
+            .transpose()?;
+        let heatmap = loaded.heatmap.as_ref()
+            .map(|heatmap| loaded.parse_heatmap(heatmap, &definitions))
+            .transpose()?;
+        let surface = loaded.surface.as_ref()
+            .map(|surface| loaded.parse_surface(surface, &definitions))
+            .transpose()?;
+        let sequences = loaded.sequences.iter()
+            .map(|sequence| loaded.parse_sequence(sequence, &definitions))
+            .collect::<Result<Vec<_>, String>>()?;
+        let cobwebs = loaded.cobwebs.iter()
+            .map(|cobweb| loaded.parse_cobweb(cobweb, &definitions))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok((loaded, expressions, series, fields, complex, heatmap, surface, sequences, cobwebs))
+    });
+
+    let (loaded, expressions, series, fields, complex, heatmap, surface, sequences, cobwebs) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("Error opening project: {}", e);
+            return
+        }
+    };
+
+    for entity in graphed.iter().chain(heatmaps.iter()) {
+        commands.entity(entity).despawn();
+    }
+    // with its wireframe, axes and light
+    for entity in surfaces.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    *view = loaded.graphing_view();
+    *grid = loaded.grid.clone();
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_resolution(loaded.window.width, loaded.window.height);
+    }
+
+    // meshes are regenerated once the bounds are recalculated for the new view
+    let bounds = recalculate_graphing_bounds(&view, &loaded.window_descriptor());
+    for (expression, curve) in expressions.into_iter().zip(&loaded.curves) {
+        spawn_curve(&mut commands, &mut meshes, &mut materials, expression, curve, &bounds, &view);
+    }
+    for (data, entry) in series.into_iter().zip(&loaded.data) {
+        spawn_data_series(&mut commands, &mut meshes, &mut materials, data, entry, &bounds, &view);
+    }
+    for (kind, entry) in fields.into_iter().zip(&loaded.fields) {
+        spawn_field(&mut commands, &mut meshes, &mut materials, entry, kind, &bounds);
+    }
+    if let (Some(expression), Some(entry)) = (complex, &loaded.complex) {
+        spawn_domain_coloring(&mut commands, &mut meshes, &mut standard_materials, entry, expression);
+    }
+    if let (Some(expression), Some(entry)) = (heatmap, &loaded.heatmap) {
+        spawn_heatmap(&mut commands, &mut meshes, &mut standard_materials, &mut materials, entry, expression);
+    }
+    if let (Some(expression), Some(entry)) = (surface, &loaded.surface) {
+        spawn_surface(&mut commands, &mut meshes, &mut standard_materials, entry, expression);
+    }
+    for (expression, entry) in sequences.into_iter().zip(&loaded.sequences) {
+        spawn_sequence(&mut commands, &mut meshes, entry, expression);
+    }
+    for (expression, entry) in cobwebs.into_iter().zip(&loaded.cobwebs) {
+        spawn_cobweb(&mut commands, &mut meshes, &mut materials, entry, expression);
+    }
+
+    println!("Opened project {}", path.display());
+    *project = loaded;
+    file.0 = Some(path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newer_projects_are_refused() {
+        assert!(Project::from_ron(&format!("(version: {})", PROJECT_VERSION)).is_ok());
+        assert!(Project::from_ron(&format!("(version: {})", PROJECT_VERSION + 1)).is_err());
+    }
+}
@@ -4,62 +4,35 @@ use bevy::render::camera::{Camera, OrthographicProjection};
 use bevy::window::WindowResized;
 
 use crate::axis_text::{recalculate_mid_axis_info, MidAxisInfo};
+use crate::grid::GridSettings;
+use crate::surface::ViewMode;
 
-/// Only renders functions within these xbounds.
-/// May have y bounds in the future.
-#[derive(Clone, Debug)]
-pub struct GraphingBounds {
-    pub xbounds: Bounds,
-    pub ybounds: Bounds,
-}
-
-#[derive(Clone, Debug)]
-pub struct GraphingView {
-    pub centre: Vec2,
-    pub scale: f32,
-}
-
-#[derive(Copy, Clone, Debug)]
-pub struct Bounds {
-    pub start: f32,
-    pub end: f32,
-}
+pub use crate::geometry::{AxisScale, Bounds, GraphingBounds, GraphingView, DEFAULT_SCALE, PREGENERATE_DISTANCE_FACTOR};
 
 pub const DEFAULT_WINDOW_WIDTH: f32 = 640.0;
 pub const DEFAULT_WINDOW_HEIGHT: f32 = 640.0;
 
-/// The scale is half the magnitude of the total x range visible.
-/// E.g x is between -5 and 5 when starting if the default scale is 5
-pub const DEFAULT_SCALE: f32 = 5.0;
-
-// Pregenerate meshes this factor outside the current window view.
-pub const PREGENERATE_DISTANCE_FACTOR: f32 = 2.0;
-
 pub const ZOOM_FACTOR: f32 = 1.1;
 
 pub fn recalculate_graphing_bounds_system(
     view: Res<GraphingView>,
     window_descriptor: Res<WindowDescriptor>,
+    grid_settings: Res<GridSettings>,
     mut graphing_bounds: ResMut<GraphingBounds>,
     mut mid_axis_info: ResMut<MidAxisInfo>,
 ) {
+    if grid_settings.is_changed() {
+        // tick modes may have changed, so regenerate the grid
+        *mid_axis_info = recalculate_mid_axis_info(&graphing_bounds, &view, &grid_settings);
+        graphing_bounds.set_changed();
+    }
+
     if view.is_changed() || window_descriptor.is_changed() {
-        let visible_xbounds = view.visible_xbounds(&window_descriptor);
-        let visible_ybounds = view.visible_ybounds(&window_descriptor);
-
-        let should_recalculate = 
-            visible_xbounds.start < graphing_bounds.xbounds.start
-            || visible_xbounds.end > graphing_bounds.xbounds.end
-            || (visible_xbounds.end - visible_xbounds.start) * PREGENERATE_DISTANCE_FACTOR 
-                < graphing_bounds.xbounds.end - graphing_bounds.xbounds.start
-            || visible_ybounds.start < graphing_bounds.ybounds.start
-            || visible_ybounds.end > graphing_bounds.ybounds.end
-            || (visible_ybounds.end - visible_ybounds.start) * PREGENERATE_DISTANCE_FACTOR 
-                < graphing_bounds.ybounds.end - graphing_bounds.ybounds.start;
-
-        if should_recalculate {
+        let visible = view.visible_bounds(window_descriptor.width, window_descriptor.height);
+
+        if graphing_bounds.need_regenerating(&visible) {
             *graphing_bounds = recalculate_graphing_bounds(&view, &window_descriptor);
-            *mid_axis_info = recalculate_mid_axis_info(&graphing_bounds, &view);
+            *mid_axis_info = recalculate_mid_axis_info(&graphing_bounds, &view, &grid_settings);
         }
     }
 }
@@ -67,8 +40,12 @@ pub fn recalculate_graphing_bounds_system(
 pub fn zoom_system(
     mut scroll: EventReader<MouseWheel>,
     mut view: ResMut<GraphingView>,
+    mode: Res<ViewMode>,
 ) {
     let total_y_scroll = scroll.iter().map(|s| s.y).sum::<f32>();
+    if *mode != ViewMode::Graph {
+        return
+    }
 
     match total_y_scroll {
         n if n > 0.0 => {
@@ -86,15 +63,20 @@ pub fn pan_system(
     mut mouse_motion: EventReader<MouseMotion>,
     mut view: ResMut<GraphingView>,
     window_descriptor: Res<WindowDescriptor>,
+    mode: Res<ViewMode>,
 ) {
     let mut delta = mouse_motion.iter().map(|motion| &motion.delta).sum::<Vec2>();
+    if *mode != ViewMode::Graph {
+        return
+    }
 
     // Mouse y coordinate is positive downwards - opposite of world space.
     delta.y = -delta.y;
 
     if mouse_click.pressed(MouseButton::Left) && delta.length_squared() > 0.01 {
-        let scale = view.scale;
-        view.centre -= delta * 2.0 * scale / window_descriptor.width;
+        let delta = delta * 2.0 * view.scale / window_descriptor.width;
+        view.centre[0] -= delta.x;
+        view.centre[1] -= delta.y;
     }
 }
 
@@ -113,7 +95,13 @@ pub fn update_projection_system(
     mut projection: Query<(&mut Transform, &mut Camera, &mut OrthographicProjection)>,
     view: Res<GraphingView>,
     window_descriptor: Res<WindowDescriptor>,
+    mode: Res<ViewMode>,
 ) {
+    // the camera orbits the surface in surface mode
+    if *mode != ViewMode::Graph {
+        return
+    }
+
     if view.is_changed() || window_descriptor.is_changed() {
         let proj_x = view.scale;
         let proj_y = view.scale * window_descriptor.height / window_descriptor.width;
@@ -128,8 +116,8 @@ pub fn update_projection_system(
             camera.projection_matrix = proj.get_projection_matrix();
             camera.depth_calculation = proj.depth_calculation();
             transform.translation = Vec3::new(
-                view.centre.x,
-                view.centre.y, 
+                view.centre[0],
+                view.centre[1],
                 1.0
             );
         }
@@ -138,59 +126,15 @@ pub fn update_projection_system(
 }
 
 pub fn recalculate_graphing_bounds(view: &GraphingView, window: &WindowDescriptor) -> GraphingBounds {
-    let vis_x = view.visible_xbounds(window);
-    let centre_x = vis_x.centre();
-    let dx = (vis_x.end - vis_x.start) * PREGENERATE_DISTANCE_FACTOR / 2.0;
-
-    let vis_y = view.visible_ybounds(window);
-    let centre_y = vis_y.centre();
-    let dy = (vis_y.end - vis_y.start) * PREGENERATE_DISTANCE_FACTOR / 2.0;
-    GraphingBounds {
-        xbounds: Bounds {
-            start: centre_x - dx,
-            end: centre_x + dx,
-        },
-        ybounds: Bounds {
-            start: centre_y - dy,
-            end: centre_y + dy,
-        }
-    }
-}
-
-impl Bounds {
-    pub fn centre(&self) -> f32 {
-        (self.start + self.end) / 2.0
-    }
-}
-
-impl From<std::ops::Range<f32>> for Bounds {
-    fn from(range: std::ops::Range<f32>) -> Self {
-        Self {
-            start: range.start,
-            end: range.end,
-        }
-    }
-}
-
-impl Into<std::ops::Range<f32>> for Bounds {
-    fn into(self) -> std::ops::Range<f32> {
-        self.start..self.end
-    }
+    view.pregenerated_bounds(window.width, window.height)
 }
 
 impl GraphingView {
-    pub fn visible_xbounds(&self, _window: &WindowDescriptor) -> Bounds {
-        Bounds {
-            start: self.centre.x - self.scale,
-            end: self.centre.x + self.scale,
-        }
+    pub fn visible_xbounds(&self, window: &WindowDescriptor) -> Bounds {
+        self.visible_bounds(window.width, window.height).xbounds
     }
 
     pub fn visible_ybounds(&self, window: &WindowDescriptor) -> Bounds {
-        let dy = self.scale * window.height / window.width;
-        Bounds {
-            start: self.centre.y - dy,
-            end: self.centre.y + dy,
-        }
+        self.visible_bounds(window.width, window.height).ybounds
     }
 }
@@ -0,0 +1,276 @@
+use bevy::prelude::*;
+use bevy::render::primitives::Aabb;
+use bevy::utils::HashMap;
+
+use crate::curve_material::CurveMaterial;
+use crate::data_series::{spawn_markers, Markers};
+use crate::field::{bounds_aabb, ndc_half_width, resize_glyphs, Glyphs};
+use crate::gen_mesh::{gen_expr_mesh, gen_polyline_mesh, sample_xs, Expression, StrokeStyle};
+use crate::geometry::{cobweb_points, iterate_expression, sequence_points, Definitions};
+use crate::project::{CobwebEntry, Project, SequenceEntry};
+use crate::scaling::{GraphingBounds, GraphingView};
+use crate::surface::ViewMode;
+use crate::trajectory::clicked_cursor;
+
+/// Logical pixels
+pub const COBWEB_WIDTH: f32 = 1.5;
+
+/// Most iterations drawn by a cobweb, set with the up and down arrow keys.
+pub const MAX_ITERATIONS: usize = 1000;
+
+const DIAGONAL_COLOR: Color = Color::rgb(0.5, 0.5, 0.5);
+
+/// Samples of y = x, which is only straight when both axes are scaled alike.
+const DIAGONAL_SAMPLES: usize = 256;
+
+/// An explicit sequence, drawn as a marker for each integer n within the bounds.
+#[derive(Component, Clone, Debug)]
+pub struct Sequence {
+    pub entry: SequenceEntry,
+    /// The expression of n, written in x, with the definitions expanded and the
+    /// parameters substituted.
+    pub expression: String,
+}
+
+/// A recurrence drawn as a cobweb diagram. Each of its parts is a separate entity
+/// with a copy of the component.
+#[derive(Component, Clone, Debug)]
+pub struct Cobweb {
+    pub entry: CobwebEntry,
+    /// The expression of the previous term, written in x, with the definitions
+    /// expanded and the parameters substituted.
+    pub expression: String,
+}
+
+/// Which part of a cobweb diagram an entity draws.
+#[derive(Component, Copy, Clone, Debug, PartialEq)]
+pub enum CobwebMesh {
+    Function,
+    Diagonal,
+    Path,
+}
+
+pub fn spawn_sequence(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    entry: &SequenceEntry,
+    expression: String,
+) {
+    // terms are evaluated by `regenerate_sequences_system` once the bounds are known
+    let markers = Markers { color: entry.color, size: entry.marker_size };
+    spawn_markers(commands, meshes, entry.marker, markers, 0.12)
+        .insert(Sequence {
+            entry: entry.clone(),
+            expression,
+        });
+}
+
+pub fn spawn_cobweb(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<CurveMaterial>,
+    entry: &CobwebEntry,
+    expression: String,
+) {
+    let parts = [
+        (CobwebMesh::Diagonal, DIAGONAL_COLOR, 1.0, 0.09),
+        (CobwebMesh::Function, entry.color, CurveMaterial::default().width, 0.1),
+        (CobwebMesh::Path, entry.path_color, COBWEB_WIDTH, 0.11),
+    ];
+
+    // each part is drawn by `regenerate_cobwebs_system`
+    for (part, color, width, z) in parts {
+        commands.spawn_bundle(MaterialMeshBundle {
+            mesh: meshes.add(gen_polyline_mesh(&[], &StrokeStyle::default())),
+            material: materials.add(CurveMaterial {
+                color,
+                width,
+                ..Default::default()
+            }),
+            transform: Transform::from_xyz(0.0, 0.0, z),
+            visibility: Visibility { is_visible: true },
+            ..Default::default()
+        })
+            .insert(Cobweb {
+                entry: entry.clone(),
+                expression: expression.clone(),
+            })
+            .insert(part);
+    }
+}
+
+/// The error last reported for each entity, so an error is reported once when the
+/// expression changes rather than each time it is parsed.
+#[derive(Default)]
+pub struct ReportedErrors(HashMap<Entity, String>);
+
+impl ReportedErrors {
+    /// The value of `result`, warning of its error unless it was the last reported for `entity`.
+    fn check<T>(&mut self, entity: Entity, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.0.remove(&entity);
+                Some(value)
+            }
+            Err(e) => {
+                if self.0.get(&entity) != Some(&e) {
+                    warn!("{}", e);
+                    self.0.insert(entity, e);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Evaluates the terms of sequences again when the bounds or the sequence change,
+/// and keeps their markers the same size in pixels when the window is resized.
+pub fn regenerate_sequences_system(
+    graphing_bounds: Res<GraphingBounds>,
+    view: Res<GraphingView>,
+    windows: Res<Windows>,
+    mut reported: Local<ReportedErrors>,
+    mut sequences: Query<(Entity, &Sequence, ChangeTrackers<Sequence>, &Markers, &mut Glyphs, &mut Aabb)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    for (entity, sequence, tracker, markers, mut glyphs, mut aabb) in sequences.iter_mut() {
+        if !(graphing_bounds.is_changed() || tracker.is_changed()) {
+            resize_glyphs(&mut glyphs, ndc_half_width(markers.size, window));
+            continue;
+        }
+
+        let expression = match reported.check(entity, Expression::parse(&sequence.expression)) {
+            Some(expression) => expression,
+            None => continue,
+        };
+
+        let points = sequence_points(&expression, graphing_bounds.xbounds, (view.xscale, view.yscale));
+        glyphs.0 = markers.instances(&points, window);
+        *aabb = bounds_aabb(&graphing_bounds);
+    }
+}
+
+/// Draws the parts of cobweb diagrams again when the bounds or the cobweb change.
+pub fn regenerate_cobwebs_system(
+    graphing_bounds: Res<GraphingBounds>,
+    view: Res<GraphingView>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut reported: Local<ReportedErrors>,
+    mut cobwebs: Query<(Entity, &Cobweb, ChangeTrackers<Cobweb>, &CobwebMesh, &mut Handle<Mesh>)>,
+) {
+    let (xscale, yscale) = (view.xscale, view.yscale);
+    let stroke = StrokeStyle::default();
+
+    for (entity, cobweb, tracker, part, mut mesh_handle) in cobwebs.iter_mut() {
+        if !(graphing_bounds.is_changed() || tracker.is_changed()) {
+            continue;
+        }
+
+        let mesh = match part {
+            CobwebMesh::Diagonal => {
+                let points = sample_xs(graphing_bounds.xbounds, xscale, DIAGONAL_SAMPLES).into_iter()
+                    .map(|x| [xscale.forward(x), yscale.forward(x)])
+                    .collect::<Vec<_>>();
+                gen_polyline_mesh(&points, &stroke)
+            }
+            CobwebMesh::Function | CobwebMesh::Path => {
+                // the function and the path share the expression, so only the function reports its errors
+                let parsed = Expression::parse(&cobweb.expression);
+                let expression = match *part {
+                    CobwebMesh::Function => reported.check(entity, parsed),
+                    _ => parsed.ok(),
+                };
+                let expression = match expression {
+                    Some(expression) => expression,
+                    None => continue,
+                };
+
+                if *part == CobwebMesh::Function {
+                    gen_expr_mesh(&expression, graphing_bounds.xbounds, (xscale, yscale), &stroke)
+                } else {
+                    let orbit = iterate_expression(&expression, cobweb.entry.start, cobweb.entry.iterations);
+                    let points = cobweb_points(&orbit).into_iter()
+                        .map(|[x, y]| [xscale.forward(x), yscale.forward(y)])
+                        .collect::<Vec<_>>();
+                    gen_polyline_mesh(&points, &stroke)
+                }
+            }
+        };
+
+        *mesh_handle = meshes.set(mesh_handle.clone(), mesh);
+    }
+}
+
+/// Clicking without dragging starts every cobweb at the clicked x, and the up and
+/// down arrow keys change how many iterations they draw.
+pub fn cobweb_input_system(
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    view: Res<GraphingView>,
+    mode: Res<ViewMode>,
+    mut pressed_at: Local<Option<Vec2>>,
+    mut cobwebs: Query<&mut Cobweb>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) if *mode == ViewMode::Graph => window,
+        _ => return,
+    };
+
+    let start = clicked_cursor(&mouse, window, &mut pressed_at).map(|cursor| {
+        let [s, _] = view.graph_point(cursor.into(), window.width(), window.height());
+        view.xscale.inverse(s)
+    });
+    let step = match (keys.just_pressed(KeyCode::Up), keys.just_pressed(KeyCode::Down)) {
+        (true, false) => Some(true),
+        (false, true) => Some(false),
+        _ => None,
+    };
+
+    // only touched on input, so cobwebs aren't redrawn every frame
+    if start.is_none() && step.is_none() {
+        return
+    }
+
+    for mut cobweb in cobwebs.iter_mut() {
+        if let Some(start) = start {
+            cobweb.entry.start = start;
+        }
+        cobweb.entry.iterations = match step {
+            Some(true) => (cobweb.entry.iterations + 1).min(MAX_ITERATIONS),
+            Some(false) => cobweb.entry.iterations.saturating_sub(1).max(1),
+            None => cobweb.entry.iterations,
+        };
+    }
+}
+
+/// Substitutes the parameters into sequences and cobwebs again when the project changes.
+pub fn update_sequence_parameters_system(
+    project: Res<Project>,
+    definitions: Res<Definitions>,
+    mut reported: Local<ReportedErrors>,
+    mut sequences: Query<(Entity, &mut Sequence)>,
+    mut cobwebs: Query<(Entity, &mut Cobweb)>,
+) {
+    if !project.is_changed() {
+        return
+    }
+
+    for (entity, mut sequence) in sequences.iter_mut() {
+        match reported.check(entity, project.parse_sequence(&sequence.entry, &definitions)) {
+            Some(expression) if expression != sequence.expression => sequence.expression = expression,
+            _ => (),
+        }
+    }
+
+    for (entity, mut cobweb) in cobwebs.iter_mut() {
+        match reported.check(entity, project.parse_cobweb(&cobweb.entry, &definitions)) {
+            Some(expression) if expression != cobweb.expression => cobweb.expression = expression,
+            _ => (),
+        }
+    }
+}
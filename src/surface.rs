@@ -0,0 +1,348 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::input::mouse::{MouseMotion, MouseWheel};
+use bevy::prelude::*;
+use bevy::render::camera::{Camera, CameraProjection, OrthographicProjection};
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::view::RenderLayers;
+
+use crate::geometry::{surface_geometry, Definitions, Grid, SurfaceGeometry};
+use crate::project::{Project, SurfaceEntry};
+use crate::scaling::{GraphingView, ZOOM_FACTOR};
+use crate::MainCamera;
+
+/// Render layer of the surface, its wireframe and axes, which the main camera
+/// only sees in surface mode.
+pub const SURFACE_LAYER: u8 = 1;
+
+/// Grid points along the longer side of the surface.
+pub const SURFACE_RESOLUTION: usize = 64;
+
+/// Grid cells between lines of the wireframe.
+pub const WIREFRAME_SPACING: usize = 4;
+
+/// Radians turned per logical pixel dragged.
+pub const ORBIT_SPEED: f32 = 0.01;
+
+/// Colours of the x, y and z axes.
+pub const AXIS_COLORS: [Color; 3] = [
+    Color::rgb(0.9, 0.3, 0.3),
+    Color::rgb(0.3, 0.8, 0.3),
+    Color::rgb(0.3, 0.5, 1.0),
+];
+
+/// Distance from the camera to its target. Surfaces are scaled into a box about
+/// 2 wide, so this keeps them between the near and far planes.
+const CAMERA_DISTANCE: f32 = 10.0;
+
+/// Whether the main camera shows the 2D graph or the surface.
+/// The `GraphingView` is left as it was while the surface is shown.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ViewMode {
+    #[default]
+    Graph,
+    Surface,
+}
+
+/// The orthographic camera of surface mode, looking at `target` from `yaw`
+/// around and `pitch` above the xy plane, with z upwards.
+#[derive(Copy, Clone, Debug)]
+pub struct OrbitCamera {
+    pub target: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Half the width of the view.
+    pub scale: f32,
+}
+
+impl Default for OrbitCamera {
+    fn default() -> Self {
+        Self {
+            target: Vec3::ZERO,
+            yaw: -1.0,
+            pitch: 0.5,
+            scale: 1.6,
+        }
+    }
+}
+
+impl OrbitCamera {
+    /// Unit vector from the target to the camera.
+    fn direction(&self) -> Vec3 {
+        Vec3::new(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        )
+    }
+}
+
+/// A surface z = f(x, y) over the visible bounds of the graph when surface mode is entered.
+/// Its wireframe, axes and light are its children.
+#[derive(Component, Clone, Debug)]
+pub struct Surface {
+    pub entry: SurfaceEntry,
+    /// The expression with the definitions expanded and the parameters substituted.
+    pub expression: String,
+}
+
+#[derive(Component, Copy, Clone, Debug)]
+pub struct SurfaceWireframe;
+
+/// The x, y or z axis, 0 to 2.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct SurfaceAxis(pub usize);
+
+pub fn spawn_surface(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    entry: &SurfaceEntry,
+    expression: String,
+) {
+    let layer = RenderLayers::layer(SURFACE_LAYER);
+    let mut line_material = |color: Color| materials.add(StandardMaterial {
+        base_color: color,
+        unlit: true,
+        ..Default::default()
+    });
+    let wireframe_material = line_material(entry.wireframe_color);
+    let axis_materials = AXIS_COLORS.map(line_material);
+
+    // meshes are generated by `regenerate_surface_system` in surface mode
+    commands.spawn_bundle(PbrBundle {
+        mesh: meshes.add(surface_mesh(&SurfaceGeometry::default())),
+        material: materials.add(StandardMaterial {
+            base_color: entry.color,
+            perceptual_roughness: 0.8,
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+        .insert(layer)
+        .insert(Surface {
+            entry: entry.clone(),
+            expression,
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(PbrBundle {
+                mesh: meshes.add(line_mesh(&[])),
+                material: wireframe_material,
+                ..Default::default()
+            })
+                .insert(layer)
+                .insert(SurfaceWireframe);
+
+            for (i, material) in axis_materials.into_iter().enumerate() {
+                parent.spawn_bundle(PbrBundle {
+                    mesh: meshes.add(line_mesh(&[])),
+                    material,
+                    ..Default::default()
+                })
+                    .insert(layer)
+                    .insert(SurfaceAxis(i));
+            }
+
+            parent.spawn_bundle(DirectionalLightBundle {
+                transform: Transform::from_xyz(1.0, -0.5, 2.0).looking_at(Vec3::ZERO, Vec3::Z),
+                ..Default::default()
+            });
+        });
+}
+
+/// Triangles of the surface, drawn from both sides as the pipeline culls back faces.
+fn surface_mesh(geometry: &SurfaceGeometry) -> Mesh {
+    let count = geometry.positions.len();
+    let mut positions = geometry.positions.clone();
+    positions.extend_from_within(..);
+    let mut normals = geometry.normals.clone();
+    normals.extend(geometry.normals.iter().map(|n| n.map(|c| -c)));
+
+    let mut indices = geometry.indices.clone();
+    indices.extend(geometry.indices.chunks_exact(3).flat_map(|t| [t[0], t[2], t[1]].map(|k| k + count as u32)));
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0f32; 2]; 2 * count]);
+    mesh
+}
+
+/// Lines between pairs of endpoints.
+fn line_mesh(endpoints: &[[f32; 3]]) -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, endpoints.to_vec());
+    // unused by unlit materials, but needed by the pipeline
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0f32, 0.0, 1.0]; endpoints.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0f32; 2]; endpoints.len()]);
+    mesh
+}
+
+/// Meshes the surface over the visible bounds on entering surface mode, and
+/// again whenever it changes while shown.
+pub fn regenerate_surface_system(
+    mode: Res<ViewMode>,
+    view: Res<GraphingView>,
+    window: Res<WindowDescriptor>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    surfaces: Query<(&Surface, ChangeTrackers<Surface>, &Handle<Mesh>)>,
+    mut wireframes: Query<(&Handle<Mesh>, &mut Visibility), With<SurfaceWireframe>>,
+    axes: Query<(&SurfaceAxis, &Handle<Mesh>)>,
+) {
+    if *mode != ViewMode::Surface {
+        return
+    }
+
+    for (surface, tracker, mesh) in surfaces.iter() {
+        if !(mode.is_changed() || tracker.is_changed()) {
+            continue;
+        }
+
+        let visible = view.visible_bounds(window.width, window.height);
+        let aspect = window.height / window.width;
+        let (width, height) = if aspect <= 1.0 {
+            (SURFACE_RESOLUTION, ((SURFACE_RESOLUTION as f32 * aspect).round() as usize).max(2))
+        } else {
+            (((SURFACE_RESOLUTION as f32 / aspect).round() as usize).max(2), SURFACE_RESOLUTION)
+        };
+
+        let grid = match Grid::sample(&surface.expression, &visible, (view.xscale, view.yscale), width, height) {
+            Ok(grid) => grid,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        let geometry = surface_geometry(&grid, WIREFRAME_SPACING);
+
+        meshes.set_untracked(mesh.clone(), surface_mesh(&geometry));
+        for (mesh, mut visibility) in wireframes.iter_mut() {
+            meshes.set_untracked(mesh.clone(), line_mesh(&geometry.wireframe));
+            visibility.is_visible = surface.entry.wireframe;
+        }
+        for (axis, mesh) in axes.iter() {
+            meshes.set_untracked(mesh.clone(), line_mesh(&geometry.axes[axis.0]));
+        }
+    }
+}
+
+/// Tab switches between the graph and the surface, and W toggles the wireframe.
+pub fn surface_keys_system(
+    keys: Res<Input<KeyCode>>,
+    mut mode: ResMut<ViewMode>,
+    mut surfaces: Query<&mut Surface>,
+) {
+    if keys.just_pressed(KeyCode::Tab) && surfaces.iter().next().is_some() {
+        *mode = match *mode {
+            ViewMode::Graph => ViewMode::Surface,
+            ViewMode::Surface => ViewMode::Graph,
+        };
+    }
+
+    if keys.just_pressed(KeyCode::W) && *mode == ViewMode::Surface {
+        for mut surface in surfaces.iter_mut() {
+            surface.entry.wireframe = !surface.entry.wireframe;
+        }
+    }
+}
+
+/// Points the main camera at the layer of the current mode.
+/// Returning to the graph puts the camera back as the `GraphingView` has it.
+pub fn apply_view_mode_system(
+    mut commands: Commands,
+    mode: Res<ViewMode>,
+    mut view: ResMut<GraphingView>,
+    mut orbit: ResMut<OrbitCamera>,
+    mut cameras: Query<(Entity, &mut Transform), With<MainCamera>>,
+) {
+    if !mode.is_changed() {
+        return
+    }
+
+    for (entity, mut transform) in cameras.iter_mut() {
+        match *mode {
+            ViewMode::Surface => {
+                commands.entity(entity).insert(RenderLayers::layer(SURFACE_LAYER));
+                orbit.set_changed();
+            }
+            ViewMode::Graph => {
+                commands.entity(entity).insert(RenderLayers::layer(0));
+                transform.rotation = Quat::IDENTITY;
+                // only marked, so the projection is updated without moving the view
+                view.set_changed();
+            }
+        }
+    }
+}
+
+/// In surface mode, dragging with the left button orbits the camera, dragging with
+/// the right button pans it and scrolling zooms.
+pub fn orbit_camera_system(
+    mode: Res<ViewMode>,
+    mouse: Res<Input<MouseButton>>,
+    mut motion: EventReader<MouseMotion>,
+    mut scroll: EventReader<MouseWheel>,
+    window: Res<WindowDescriptor>,
+    mut orbit: ResMut<OrbitCamera>,
+    mut cameras: Query<(&mut Transform, &mut Camera, &mut OrthographicProjection), With<MainCamera>>,
+) {
+    let delta = motion.iter().map(|motion| motion.delta).sum::<Vec2>();
+    let scrolled = scroll.iter().map(|s| s.y).sum::<f32>();
+
+    if *mode != ViewMode::Surface {
+        return
+    }
+
+    if delta.length_squared() > 0.01 {
+        if mouse.pressed(MouseButton::Left) {
+            orbit.yaw -= delta.x * ORBIT_SPEED;
+            orbit.pitch = (orbit.pitch + delta.y * ORBIT_SPEED).clamp(-FRAC_PI_2 + 0.01, FRAC_PI_2 - 0.01);
+        } else if mouse.pressed(MouseButton::Right) {
+            let forward = -orbit.direction();
+            let right = forward.cross(Vec3::Z).normalize();
+            let up = right.cross(forward);
+            let units_per_pixel = 2.0 * orbit.scale / window.width;
+            // mouse y is positive downwards
+            orbit.target -= (right * delta.x - up * delta.y) * units_per_pixel;
+        }
+    }
+
+    if scrolled != 0.0 {
+        orbit.scale *= ZOOM_FACTOR.powf(-scrolled);
+    }
+
+    if !(orbit.is_changed() || window.is_changed()) {
+        return
+    }
+
+    for (mut transform, mut camera, mut projection) in cameras.iter_mut() {
+        *transform = Transform::from_translation(orbit.target + orbit.direction() * CAMERA_DISTANCE)
+            .looking_at(orbit.target, Vec3::Z);
+
+        projection.left = -orbit.scale;
+        projection.right = orbit.scale;
+        projection.top = orbit.scale * window.height / window.width;
+        projection.bottom = -projection.top;
+        camera.projection_matrix = projection.get_projection_matrix();
+    }
+}
+
+/// Substitutes the project's parameters into the surface again when they change.
+pub fn update_surface_parameters_system(
+    project: Res<Project>,
+    definitions: Res<Definitions>,
+    mut surfaces: Query<&mut Surface>,
+) {
+    if !project.is_changed() {
+        return
+    }
+
+    for mut surface in surfaces.iter_mut() {
+        match project.parse_surface(&surface.entry, &definitions) {
+            Ok(expression) if expression != surface.expression => surface.expression = expression,
+            Ok(_) => (),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use bevy::prelude::*;
+
+use crate::curve_material::CurveMaterial;
+use crate::field::Field;
+use crate::gen_mesh::{gen_polyline_mesh, StrokeStyle};
+use crate::geometry::{self, AxisScale, FieldKind};
+use crate::scaling::{GraphingBounds, GraphingView};
+use crate::surface::ViewMode;
+
+/// Logical pixels
+pub const TRAJECTORY_WIDTH: f32 = 2.0;
+
+/// Distance in logical pixels the cursor may move between pressing and releasing
+/// the mouse for it to count as a click rather than a pan.
+const CLICK_DISTANCE: f32 = 4.0;
+
+/// Initial conditions of the solution curves drawn on a field, on the axes
+/// before they are transformed by their `AxisScale`.
+#[derive(Component, Clone, Debug, Default)]
+pub struct Trajectories(pub Vec<[f32; 2]>);
+
+/// The solution curves of the field entity, in one mesh.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct TrajectoryMesh(pub Entity);
+
+pub fn spawn_trajectory_mesh(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<CurveMaterial>,
+    field: Entity,
+    color: Color,
+) -> Entity {
+    // curves are integrated by `regenerate_trajectories_system` once the field is added
+    commands.spawn_bundle(MaterialMeshBundle {
+        mesh: meshes.add(gen_polyline_mesh(&[], &StrokeStyle::default())),
+        material: materials.add(CurveMaterial {
+            color,
+            width: TRAJECTORY_WIDTH,
+            ..Default::default()
+        }),
+        transform: Transform::from_xyz(0.0, 0.0, 0.08),
+        visibility: Visibility { is_visible: true },
+        ..Default::default()
+    })
+        .insert(TrajectoryMesh(field))
+        .id()
+}
+
+/// Solution curves through each initial condition, in graph space, separated by NaN.
+/// Curves that cannot be integrated are skipped.
+pub fn trajectory_points(
+    kind: &FieldKind,
+    starts: &[[f32; 2]],
+    bounds: &GraphingBounds,
+    scales: (AxisScale, AxisScale),
+) -> Vec<[f32; 2]> {
+    let mut points = Vec::new();
+
+    for &start in starts {
+        match geometry::trajectory(kind, start, bounds, scales) {
+            Ok(curve) => {
+                points.extend(curve);
+                points.push([f32::NAN; 2]);
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    points
+}
+
+/// Integrates the solution curves of a field again when the bounds, the field
+/// or its initial conditions change.
+/// Meshes of fields that no longer exist are despawned.
+pub fn regenerate_trajectories_system(
+    mut commands: Commands,
+    graphing_bounds: Res<GraphingBounds>,
+    view: Res<GraphingView>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    fields: Query<(&Field, ChangeTrackers<Field>, &Trajectories, ChangeTrackers<Trajectories>)>,
+    mut trajectory_meshes: Query<(Entity, &TrajectoryMesh, &mut Handle<Mesh>)>,
+) {
+    for (entity, trajectory_mesh, mut mesh_handle) in trajectory_meshes.iter_mut() {
+        let (field, field_tracker, trajectories, trajectories_tracker) = match fields.get(trajectory_mesh.0) {
+            Ok(field) => field,
+            Err(_) => {
+                commands.entity(entity).despawn();
+                continue;
+            }
+        };
+
+        if graphing_bounds.is_changed() || field_tracker.is_changed() || trajectories_tracker.is_changed() {
+            let points = trajectory_points(&field.kind, &trajectories.0, &graphing_bounds, (view.xscale, view.yscale));
+            *mesh_handle = meshes.set(mesh_handle.clone(), gen_polyline_mesh(&points, &StrokeStyle::default()));
+        }
+    }
+}
+
+/// Where the left button was released, if it didn't move far enough since being pressed to
+/// be a pan. `pressed_at` is kept between frames by the caller.
+pub fn clicked_cursor(mouse: &Input<MouseButton>, window: &Window, pressed_at: &mut Option<Vec2>) -> Option<Vec2> {
+    if mouse.just_pressed(MouseButton::Left) {
+        *pressed_at = window.cursor_position();
+    }
+
+    if !mouse.just_released(MouseButton::Left) {
+        return None;
+    }
+
+    match (pressed_at.take(), window.cursor_position()) {
+        (Some(pressed), Some(released)) if pressed.distance(released) <= CLICK_DISTANCE => Some(released),
+        _ => None,
+    }
+}
+
+/// Clicking without dragging places an initial condition on every field.
+pub fn place_trajectory_system(
+    mouse: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    view: Res<GraphingView>,
+    mode: Res<ViewMode>,
+    mut pressed_at: Local<Option<Vec2>>,
+    mut fields: Query<&mut Trajectories, With<Field>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) if *mode == ViewMode::Graph => window,
+        _ => return,
+    };
+
+    let cursor = match clicked_cursor(&mouse, window, &mut pressed_at) {
+        Some(cursor) => cursor,
+        None => return,
+    };
+
+    let [s, t] = view.graph_point(cursor.into(), window.width(), window.height());
+    let start = [view.xscale.inverse(s), view.yscale.inverse(t)];
+
+    for mut trajectories in fields.iter_mut() {
+        trajectories.0.push(start);
+    }
+}
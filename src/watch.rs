@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::curve_material::{curve_color, CurveMaterial};
+use crate::gen_mesh::{Expression, ExpressionText};
+use crate::geometry::Definitions;
+use crate::curves::spawn_curve;
+use crate::project::{CurveEntry, Project};
+use crate::scaling::{GraphingBounds, GraphingView};
+
+/// Seconds between checks of the watched file for changes.
+const POLL_INTERVAL: f32 = 0.25;
+
+/// A file of expressions, one per line, graphed and reloaded when it changes.
+pub struct WatchedFile {
+    pub path: PathBuf,
+    /// Modification time of the file when last read.
+    modified: Option<SystemTime>,
+    timer: Timer,
+}
+
+/// A curve from a line of the watched file.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct WatchedCurve {
+    /// Index among the expressions in the file, skipping blank and comment lines.
+    pub index: usize,
+}
+
+impl WatchedFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            modified: None,
+            timer: Timer::from_seconds(POLL_INTERVAL, true),
+        }
+    }
+}
+
+/// Expressions in the file, one per line. Blank lines and lines starting with '#' are skipped.
+pub fn read_expressions(path: &Path) -> Result<Vec<String>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+
+    Ok(text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Polls the watched file and updates its curves in place when it changes.
+/// Curves whose expression no longer parses keep their last working expression.
+/// The meshes of changed curves are rebuilt by `regenerate_meshes_system`.
+#[allow(clippy::too_many_arguments)]
+pub fn watch_file_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    watched: Option<ResMut<WatchedFile>>,
+    project: Res<Project>,
+    graphing_bounds: Res<GraphingBounds>,
+    view: Res<GraphingView>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<CurveMaterial>>,
+    mut curves: Query<(Entity, &WatchedCurve, &mut Expression, &mut ExpressionText)>,
+    others: Query<&ExpressionText, Without<WatchedCurve>>,
+) {
+    let mut watched = match watched {
+        Some(watched) => watched,
+        None => return,
+    };
+
+    // checked every frame until the file is first read
+    let due = watched.timer.tick(time.delta()).just_finished();
+    if !due && watched.modified.is_some() {
+        return
+    }
+
+    let modified = match std::fs::metadata(&watched.path).and_then(|m| m.modified()) {
+        Ok(modified) => modified,
+        Err(_) => return,
+    };
+    if watched.modified == Some(modified) {
+        return
+    }
+    watched.modified = Some(modified);
+
+    let expressions = match read_expressions(&watched.path) {
+        Ok(expressions) => expressions,
+        Err(e) => {
+            println!("{}", e);
+            return
+        }
+    };
+
+    // definitions may be made in the file or by other curves
+    let lines = others.iter().map(|text| text.0.as_str()).chain(expressions.iter().map(String::as_str));
+    let definitions = match Definitions::resolve(lines) {
+        Ok(definitions) => definitions,
+        Err(e) => {
+            println!("{}", e);
+            return
+        }
+    };
+
+    let mut existing = vec![false; expressions.len()];
+
+    for (entity, curve, mut expression, mut text) in curves.iter_mut() {
+        let line = match expressions.get(curve.index) {
+            Some(line) => line,
+            None => {
+                // the line was removed
+                commands.entity(entity).despawn();
+                continue;
+            }
+        };
+        existing[curve.index] = true;
+
+        if text.0 == *line {
+            continue;
+        }
+
+        let entry = CurveEntry { expression: line.clone(), ..Default::default() };
+        match project.parse_curve(&entry, &definitions) {
+            Ok(parsed) => {
+                *expression = parsed;
+                text.0 = line.clone();
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    // watched curves are coloured after those in the project
+    let first_color = project.curves.len() + project.data.len();
+
+    for (index, line) in expressions.iter().enumerate().filter(|&(i, _)| !existing[i]) {
+        let entry = CurveEntry {
+            expression: line.clone(),
+            color: curve_color(first_color + index),
+            ..Default::default()
+        };
+
+        match project.parse_curve(&entry, &definitions) {
+            Ok(expression) => {
+                let entity = spawn_curve(
+                    &mut commands, &mut meshes, &mut materials, expression, &entry, &graphing_bounds, &view
+                );
+                commands.entity(entity).insert(WatchedCurve { index });
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+}